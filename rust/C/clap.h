@@ -15,11 +15,15 @@
 #undef EXPORT_IMPORT
 #endif
 
+#if defined(_WIN32)
 #ifndef __cplusplus
 #define EXPORT_IMPORT __declspec( dllexport )
 #else
 #define EXPORT_IMPORT __declspec( dllimport )
 #endif
+#else
+#include "export.hh"
+#endif
 
 #ifdef __cplusplus
 extern "C" {  // only need to export C interface if
@@ -0,0 +1,19 @@
+/*
+    lib/rust/C/export.hh
+    Q@khaa.pk
+ */
+
+/*
+    EXPORT_IMPORT and __stdcall for GCC and Clang on Linux and macOS, there is no import side and no __stdcall calling convention.
+    clap.h and start.hh each (re)define EXPORT_IMPORT and include this on every platform but Windows, so it has no include guard.
+ */
+
+#ifdef EXPORT_IMPORT
+#undef EXPORT_IMPORT
+#endif
+
+#define EXPORT_IMPORT __attribute__(( visibility("default") ))
+
+#ifndef __stdcall
+#define __stdcall
+#endif
@@ -45,11 +45,15 @@ typedef struct commandLines
 #undef EXPORT_IMPORT
 #endif
 
+#if defined(_WIN32)
 #ifndef __cplusplus
 #define EXPORT_IMPORT __declspec( dllimport )
 #else
 #define EXPORT_IMPORT __declspec( dllexport )
 #endif
+#else
+#include "export.hh"
+#endif
 
 #ifdef __cplusplus
 extern "C" {  // only need to export C interface if
@@ -0,0 +1,201 @@
+/*
+    lib/rust/png/src/analysis.rs
+    Q@khaa.pk
+ */
+
+use std::collections::HashSet;
+
+use crate::constants::{COLOR_TYPE_GRAYSCALE, COLOR_TYPE_TRUECOLOR, COLOR_TYPE_INDEXED, COLOR_TYPE_GRAYSCALE_ALPHA, COLOR_TYPE_TRUECOLOR_ALPHA};
+use crate::error::PngError;
+use crate::pixels::PixelBuffer;
+
+/*
+    What PixelBuffer::analyze() found, the facts a PNG optimizer decides the output format on
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub struct ColorAnalysis {
+
+    /* Red, green and blue are equal in every pixel (always true for gray images) */
+    pub all_gray: bool,
+    /* Every alpha sample is the maximum value (always true for images without alpha) */
+    pub alpha_opaque: bool,
+    /* Number of different pixels, alpha included */
+    pub distinct_colors: usize,
+    /* Smallest of 1, 2, 4, 8 and 16 bits that holds every sample exactly */
+    pub min_bit_depth: u8,
+    /* (color type, bit depth) that stores these pixels losslessly in the fewest bits */
+    pub suggested: (u8, u8),
+}
+
+/*
+    True when @value at @bit_depth bits scales to a whole number at @target bits, e.g. 0x88 at 8 bits is 0x8 at 4 bits
+ */
+fn fits_bit_depth (value: u32, bit_depth: u8, target: u8) -> bool {
+
+    (value as u64 * ((1u64 << target) - 1)).is_multiple_of((1u64 << bit_depth) - 1)
+}
+
+/*
+    Bit depth of palette indices for @colors palette entries
+ */
+fn index_bit_depth (colors: usize) -> u8 {
+
+    match colors {
+
+        0 ..= 2 => 1,
+        3 ..= 4 => 2,
+        5 ..= 16 => 4,
+        _ => 8,
+    }
+}
+
+impl PixelBuffer {
+
+    /*
+        Looks at every pixel once and reports whether the image is gray, whether its alpha is fully opaque, how many colors it has,
+        the smallest bit depth that holds its samples and the (color type, bit depth) worth storing it as.
+        Indexed buffers hold palette indices rather than colors, so they give back an error.
+     */
+    pub fn analyze (&self) -> Result<ColorAnalysis, PngError> {
+
+        if self.color_type == COLOR_TYPE_INDEXED {
+
+            return Err(PngError::UnsupportedColorType(self.color_type));
+        }
+
+        let channels = self.channels();
+        let sample_size = self.bytes_per_sample();
+        let color_channels = if self.has_alpha() { channels - 1 } else { channels };
+
+        let mut all_gray: bool = true;
+        let mut alpha_opaque: bool = true;
+        let mut colors: HashSet<u64> = HashSet::new();
+        let mut min_bit_depth: u8 = 1;
+
+        for pixel in self.data.chunks_exact(self.bytes_per_pixel()) {
+
+            let mut key: u64 = 0;
+
+            for channel in 0 .. channels {
+
+                let value = if sample_size == 2 { u16::from_be_bytes([pixel[channel * 2], pixel[channel * 2 + 1]]) as u32 } else { pixel[channel] as u32 };
+
+                while min_bit_depth < self.bit_depth && !fits_bit_depth(value, self.bit_depth, min_bit_depth) {
+
+                    min_bit_depth *= 2;
+                }
+
+                key = key << 16 | value as u64;
+            }
+
+            if color_channels == 3 {
+
+                let sample = |channel: usize| &pixel[channel * sample_size .. (channel + 1) * sample_size];
+
+                all_gray = all_gray && sample(0) == sample(1) && sample(1) == sample(2);
+            }
+
+            if self.has_alpha() {
+
+                let alpha = &pixel[(channels - 1) * sample_size ..];
+
+                alpha_opaque = alpha_opaque && alpha.iter().all(|byte| *byte == 0xFF);
+            }
+
+            colors.insert(key);
+        }
+
+        /* Alpha-free gray images down to 1 bit, gray with alpha and truecolor only come in 8 and 16 bits */
+        let suggested = if all_gray && alpha_opaque {
+
+            (COLOR_TYPE_GRAYSCALE, min_bit_depth)
+        }
+        else if colors.len() <= 256 && min_bit_depth <= 8 {
+
+            (COLOR_TYPE_INDEXED, index_bit_depth(colors.len()))
+        }
+        else if all_gray {
+
+            (COLOR_TYPE_GRAYSCALE_ALPHA, min_bit_depth.max(8))
+        }
+        else if alpha_opaque {
+
+            (COLOR_TYPE_TRUECOLOR, min_bit_depth.max(8))
+        }
+        else {
+
+            (COLOR_TYPE_TRUECOLOR_ALPHA, min_bit_depth.max(8))
+        };
+
+        Ok(ColorAnalysis { all_gray, alpha_opaque, distinct_colors: colors.len(), min_bit_depth, suggested })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn smallest_bit_depth_that_holds_every_sample () {
+
+        /* 0x88 is 0x8 at 4 bits but no whole number at 2 */
+        let gray = PixelBuffer::from_data(3, 1, COLOR_TYPE_GRAYSCALE, 8, vec![0x00, 0x88, 0xFF]).unwrap();
+
+        assert_eq!(gray.analyze().unwrap(), ColorAnalysis { all_gray: true, alpha_opaque: true, distinct_colors: 3, min_bit_depth: 4, suggested: (COLOR_TYPE_GRAYSCALE, 4) });
+
+        let black_and_white = PixelBuffer::from_data(2, 1, COLOR_TYPE_GRAYSCALE, 16, vec![0x00, 0x00, 0xFF, 0xFF]).unwrap();
+
+        assert_eq!(black_and_white.analyze().unwrap().suggested, (COLOR_TYPE_GRAYSCALE, 1));
+    }
+
+    #[test]
+    fn gray_with_alpha () {
+
+        let few = PixelBuffer::from_data(2, 1, COLOR_TYPE_GRAYSCALE_ALPHA, 8, vec![10, 255, 20, 128]).unwrap();
+        let analysis = few.analyze().unwrap();
+
+        assert!(analysis.all_gray);
+        assert!(!analysis.alpha_opaque);
+        assert_eq!((analysis.distinct_colors, analysis.min_bit_depth), (2, 8));
+        assert_eq!(analysis.suggested, (COLOR_TYPE_INDEXED, 1));
+
+        /* Too many colors for a palette */
+        let many = PixelBuffer::from_data(300, 1, COLOR_TYPE_GRAYSCALE_ALPHA, 8, (0 .. 300).flat_map(|i| [(i % 256) as u8, (128 + i / 256) as u8]).collect()).unwrap();
+
+        assert_eq!(many.analyze().unwrap().suggested, (COLOR_TYPE_GRAYSCALE_ALPHA, 8));
+
+        /* An opaque alpha channel can go */
+        let opaque = PixelBuffer::from_data(2, 1, COLOR_TYPE_GRAYSCALE_ALPHA, 8, vec![0, 255, 255, 255]).unwrap();
+
+        assert_eq!(opaque.analyze().unwrap().suggested, (COLOR_TYPE_GRAYSCALE, 1));
+    }
+
+    #[test]
+    fn more_than_256_colors () {
+
+        let rgb = PixelBuffer::from_data(300, 1, COLOR_TYPE_TRUECOLOR, 8, (0 .. 300).flat_map(|i| [(i % 256) as u8, (i / 256) as u8, 7]).collect()).unwrap();
+        let analysis = rgb.analyze().unwrap();
+
+        assert_eq!(analysis.distinct_colors, 300);
+        assert!(!analysis.all_gray);
+        assert_eq!(analysis.suggested, (COLOR_TYPE_TRUECOLOR, 8));
+
+        let rgba = PixelBuffer::from_data(300, 1, COLOR_TYPE_TRUECOLOR_ALPHA, 8, (0 .. 300).flat_map(|i| [(i % 256) as u8, (i / 256) as u8, 7, 0]).collect()).unwrap();
+
+        assert_eq!(rgba.analyze().unwrap().suggested, (COLOR_TYPE_TRUECOLOR_ALPHA, 8));
+
+        /* 256 colors still fit a palette */
+        let palette = PixelBuffer::from_data(256, 1, COLOR_TYPE_TRUECOLOR, 8, (0 .. 256).flat_map(|i| [i as u8, 0, 0]).collect()).unwrap();
+
+        assert_eq!(palette.analyze().unwrap().suggested, (COLOR_TYPE_INDEXED, 8));
+    }
+
+    #[test]
+    fn indexed_buffers_are_refused () {
+
+        let indexed = PixelBuffer::from_data(1, 1, COLOR_TYPE_INDEXED, 8, vec![0]).unwrap();
+
+        assert_eq!(indexed.analyze(), Err(PngError::UnsupportedColorType(COLOR_TYPE_INDEXED)));
+    }
+}
@@ -0,0 +1,512 @@
+/*
+    lib/rust/png/src/ancillary.rs
+    Q@khaa.pk
+ */
+
+use crate::error::PngError;
+use crate::images::{extract_block, ImageBlock};
+use crate::constants::{COLOR_TYPE_GRAYSCALE, COLOR_TYPE_TRUECOLOR, COLOR_TYPE_INDEXED};
+use crate::pixels::{is_valid_bit_depth, Pixel, PixelBuffer};
+use crate::Png;
+
+const LENGTH_OF_OFFS_DATA: usize = 9;
+const LENGTH_OF_PHYS_DATA: usize = 9;
+
+/*
+    Micrometres in an inch, pixels per metre = dots per inch * 1000000 / 25400
+ */
+const MICROMETERS_PER_INCH: u64 = 25400;
+
+/*
+    Unit of a pHYs resolution, the unit specifier byte is 0 when only the aspect ratio is known and 1 for pixels per metre
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PhysicalUnit {
+
+    Unknown,
+    Meter,
+}
+
+/*
+    Pixels per unit along x and y, as the pHYs chunk gives them
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PhysicalDimensions {
+
+    pub x: u32,
+    pub y: u32,
+    pub unit: PhysicalUnit,
+}
+
+impl PhysicalDimensions {
+
+    /*
+        Dots per inch along x and y rounded to the nearest whole number, None when the unit is not known.
+        A pHYs written by Png::set_dpi() reads back as the dpi it was given.
+     */
+    pub fn dpi (&self) -> Option<(u32, u32)> {
+
+        if self.unit != PhysicalUnit::Meter {
+
+            return None;
+        }
+
+        let dpi = |pixels_per_meter: u32| ((pixels_per_meter as u64 * MICROMETERS_PER_INCH + 500000) / 1000000) as u32;
+
+        Some((dpi(self.x), dpi(self.y)))
+    }
+}
+
+/*
+    Unit of an oFFs position, the unit specifier byte is 0 for pixels and 1 for micrometres
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OffsetUnit {
+
+    Pixel,
+    Micrometer,
+}
+
+/*
+    Layout of a stereo pair, the sTER mode byte is 0 for cross-fuse and 1 for diverging-fuse.
+    Cross-fuse puts the right eye image on the left, diverging-fuse puts the left eye image on the left.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StereoMode {
+
+    CrossFuse,
+    DivergingFuse,
+}
+
+/*
+    Position of the image on a page or larger canvas, from its top left corner, as the oFFs extension chunk gives it. Both can be negative.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ImageOffset {
+
+    pub x: i32,
+    pub y: i32,
+    pub unit: OffsetUnit,
+}
+
+impl Png {
+
+    /*
+        The oFFs chunk, None when there is none, it is not 9 bytes long or its unit specifier is not 0 or 1
+     */
+    pub fn get_offset (&self) -> Option<ImageOffset> {
+
+        let data = self.iter().find(|chunk| chunk.type_name == b"oFFs")?.data();
+
+        if data.len() != LENGTH_OF_OFFS_DATA {
+
+            return None;
+        }
+
+        let unit = match data[8] {
+
+            0 => OffsetUnit::Pixel,
+            1 => OffsetUnit::Micrometer,
+            _ => return None,
+        };
+
+        Some(ImageOffset { x: i32::from_be_bytes([data[0], data[1], data[2], data[3]]), y: i32::from_be_bytes([data[4], data[5], data[6], data[7]]), unit })
+    }
+
+    /*
+        Writes (or rewrites) the oFFs chunk, it goes ahead of the image data. Gives back its position.
+     */
+    pub fn set_offset (&mut self, x: i32, y: i32, unit: OffsetUnit) -> Result<usize, PngError> {
+
+        let mut data: Vec<u8> = Vec::with_capacity(LENGTH_OF_OFFS_DATA);
+
+        data.extend_from_slice(&x.to_be_bytes());
+        data.extend_from_slice(&y.to_be_bytes());
+        data.push(if unit == OffsetUnit::Micrometer { 1 } else { 0 });
+
+        self.upsert_chunk(b"oFFs", data)
+    }
+
+    /*
+        Layout the sTER chunk gives, None for an image that is not a stereo pair or whose sTER chunk is not a single byte of 0 or 1
+     */
+    pub fn stereo_mode (&self) -> Option<StereoMode> {
+
+        match self.iter().find(|chunk| chunk.type_name == b"sTER")?.data() {
+
+            [0] => Some(StereoMode::CrossFuse),
+            [1] => Some(StereoMode::DivergingFuse),
+            _ => None,
+        }
+    }
+
+    /*
+        Marks the image as a stereo pair by writing (or rewriting) the sTER chunk, it goes ahead of the image data. Gives back its position.
+        Nothing checks the width suits stereo_subimage_width().
+     */
+    pub fn set_stereo_mode (&mut self, mode: StereoMode) -> Result<usize, PngError> {
+
+        self.upsert_chunk(b"sTER", vec![if mode == StereoMode::DivergingFuse { 1 } else { 0 }])
+    }
+}
+
+impl Png {
+
+    /*
+        The pHYs chunk, None when there is none, it is not 9 bytes long or its unit specifier is not 0 or 1
+     */
+    pub fn physical_dimensions (&self) -> Option<PhysicalDimensions> {
+
+        let data = self.iter().find(|chunk| chunk.type_name == b"pHYs")?.data();
+
+        if data.len() != LENGTH_OF_PHYS_DATA {
+
+            return None;
+        }
+
+        let unit = match data[8] {
+
+            0 => PhysicalUnit::Unknown,
+            1 => PhysicalUnit::Meter,
+            _ => return None,
+        };
+
+        Some(PhysicalDimensions { x: u32::from_be_bytes([data[0], data[1], data[2], data[3]]), y: u32::from_be_bytes([data[4], data[5], data[6], data[7]]), unit })
+    }
+
+    /*
+        Writes (or rewrites) the pHYs chunk, it goes ahead of the image data. Gives back its position.
+        Zero pixels per unit along either axis gives InvalidResolution, the specification has no meaning for it.
+     */
+    pub fn set_physical_dimensions (&mut self, x: u32, y: u32, unit: PhysicalUnit) -> Result<usize, PngError> {
+
+        if x == 0 || y == 0 {
+
+            return Err(PngError::InvalidResolution { x, y });
+        }
+
+        let mut data: Vec<u8> = Vec::with_capacity(LENGTH_OF_PHYS_DATA);
+
+        data.extend_from_slice(&x.to_be_bytes());
+        data.extend_from_slice(&y.to_be_bytes());
+        data.push(if unit == PhysicalUnit::Meter { 1 } else { 0 });
+
+        self.upsert_chunk(b"pHYs", data)
+    }
+
+    pub fn set_pixels_per_meter (&mut self, x: u32, y: u32) -> Result<usize, PngError> {
+
+        self.set_physical_dimensions(x, y, PhysicalUnit::Meter)
+    }
+
+    /*
+        pHYs in pixels per metre for @dpi dots per inch along both axes. pHYs has no inch unit, so @dpi is converted and rounded
+        to the nearest pixel per metre, 300 dpi is stored as 11811, which PhysicalDimensions::dpi() reads back as 300.
+     */
+    pub fn set_dpi (&mut self, dpi: u32) -> Result<usize, PngError> {
+
+        let pixels_per_meter = ((dpi as u64 * 1000000 + MICROMETERS_PER_INCH / 2) / MICROMETERS_PER_INCH).min(u32::MAX as u64) as u32;
+
+        self.set_pixels_per_meter(pixels_per_meter, pixels_per_meter)
+    }
+}
+
+impl Png {
+
+    /*
+        Color the tRNS chunk of a gray (color type 0) or RGB (color type 2) image makes fully transparent, None for other images or a tRNS of the wrong length
+     */
+    pub fn transparent_color (&self) -> Option<Pixel> {
+
+        let data = self.iter().find(|chunk| chunk.type_name == b"tRNS")?.data();
+        let sample = |index: usize| u16::from_be_bytes([data[index * 2], data[index * 2 + 1]]);
+
+        match (self.color_type()?, data.len()) {
+
+            (COLOR_TYPE_GRAYSCALE, 2) => Some(Pixel::Gray(sample(0))),
+            (COLOR_TYPE_TRUECOLOR, 6) => Some(Pixel::Rgb(sample(0), sample(1), sample(2))),
+            _ => None,
+        }
+    }
+
+    /*
+        Writes (or rewrites) the 2 or 6 byte tRNS chunk making @color fully transparent, Pixel::Gray for a gray image and Pixel::Rgb for an RGB one.
+        Samples are at the bit depth of the image. Gives back the position of the chunk.
+     */
+    pub fn set_transparent_color (&mut self, color: Pixel) -> Result<usize, PngError> {
+
+        let color_type = self.color_type().ok_or(PngError::MissingChunk("IHDR"))?;
+        let bit_depth = self.bit_depth().ok_or(PngError::MissingChunk("IHDR"))?;
+
+        if color_type != COLOR_TYPE_GRAYSCALE && color_type != COLOR_TYPE_TRUECOLOR {
+
+            return Err(PngError::UnsupportedColorType(color_type));
+        }
+
+        /* A bit depth IHDR can't have would also overflow the shift below */
+        if !is_valid_bit_depth(color_type, bit_depth) {
+
+            return Err(PngError::UnsupportedBitDepth(bit_depth));
+        }
+
+        if color.color_type() != color_type {
+
+            return Err(PngError::IncompatiblePixelFormat { expected: (color_type, bit_depth), found: (color.color_type(), bit_depth) });
+        }
+
+        let max = (1u32 << bit_depth) - 1;
+        let mut data: Vec<u8> = Vec::with_capacity(6);
+
+        for sample in color.samples() {
+
+            if sample as u32 > max {
+
+                return Err(PngError::SampleOutOfRange { value: sample as u32, max });
+            }
+
+            data.extend_from_slice(&sample.to_be_bytes());
+        }
+
+        self.upsert_chunk(b"tRNS", data)
+    }
+
+    /*
+        Sets the alpha of the palette entries of an indexed image, @alpha[n] for entry n and opaque past the end of @alpha.
+        Trailing 255s are left out of tRNS, and when nothing is left the tRNS chunk is removed and None given back, otherwise its position.
+     */
+    pub fn set_palette_alpha (&mut self, alpha: &[u8]) -> Result<Option<usize>, PngError> {
+
+        let color_type = self.color_type().ok_or(PngError::MissingChunk("IHDR"))?;
+
+        if color_type != COLOR_TYPE_INDEXED {
+
+            return Err(PngError::UnsupportedColorType(color_type));
+        }
+
+        let entries = match self.iter().find(|chunk| chunk.type_name == b"PLTE") {
+
+            Some(chunk) => chunk.data().len() / 3,
+            None => return Err(PngError::MissingChunk("PLTE")),
+        };
+
+        let length = alpha.iter().rposition(|value| *value != 0xFF).map(|last| last + 1).unwrap_or(0);
+
+        if length > entries {
+
+            return Err(PngError::InvalidChunkLength { type_name: "tRNS", expected: entries, found: length });
+        }
+
+        if length == 0 {
+
+            self.remove_chunks_by_type("tRNS", false)?;
+
+            return Ok(None);
+        }
+
+        self.upsert_chunk(b"tRNS", alpha[.. length].to_vec()).map(Some)
+    }
+}
+
+/*
+    Width of each of the two sub-images of a stereo pair @width pixels wide.
+    The right sub-image starts at the first column past the left one that is a multiple of 8, so width = 2 * sub-image width + padding
+    with padding = 15 - ((width - 1) mod 16). Widths that would need 8 or more columns of padding give InvalidStereoWidth.
+ */
+pub fn stereo_subimage_width (width: u32) -> Result<u32, PngError> {
+
+    if width < 2 {
+
+        return Err(PngError::InvalidStereoWidth(width));
+    }
+
+    let padding = 15 - (width - 1) % 16;
+
+    if padding > 7 {
+
+        return Err(PngError::InvalidStereoWidth(width));
+    }
+
+    Ok((width - padding) / 2)
+}
+
+impl PixelBuffer {
+
+    /*
+        The two sub-images of a stereo pair laid out as @mode says, as (left eye, right eye), padding columns between them dropped.
+        There is no Png::split_stereo(), this crate neither inflates nor deflates image data, so split the decoded pixels of the image here.
+     */
+    pub fn split_stereo (&self, mode: StereoMode) -> Result<(PixelBuffer, PixelBuffer), PngError> {
+
+        let subimage_width = stereo_subimage_width(self.width)?;
+        let padding = self.width - 2 * subimage_width;
+        let block = ImageBlock::new(self.height as usize, subimage_width as usize);
+
+        let left = PixelBuffer::from_data(subimage_width, self.height, self.color_type, self.bit_depth, extract_block(self, &block, 0, 0)?)?;
+        let right = PixelBuffer::from_data(subimage_width, self.height, self.color_type, self.bit_depth, extract_block(self, &block, subimage_width + padding, 0)?)?;
+
+        match mode {
+
+            StereoMode::CrossFuse => Ok((right, left)),
+            StereoMode::DivergingFuse => Ok((left, right)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::constants::COLOR_TYPE_TRUECOLOR_ALPHA;
+    use crate::fixtures;
+
+    #[test]
+    fn offset_round_trips_negative_values () {
+
+        let mut png = Png::try_from(fixtures::rgb()).unwrap();
+
+        assert_eq!(png.get_offset(), None);
+
+        let index = png.set_offset(-100, i32::MIN, OffsetUnit::Micrometer).unwrap();
+
+        assert!(index < png.iter().position(|chunk| chunk.type_name == b"IDAT").unwrap());
+        assert_eq!(png.chunk_at(index).unwrap().data(), [0xFF, 0xFF, 0xFF, 0x9C, 0x80, 0x00, 0x00, 0x00, 1]);
+        assert_eq!(png.get_offset(), Some(ImageOffset { x: -100, y: i32::MIN, unit: OffsetUnit::Micrometer }));
+
+        /* Rewriting keeps a single oFFs */
+        png.set_offset(i32::MAX, -1, OffsetUnit::Pixel).unwrap();
+
+        let reread = Png::try_from(png.to_bytes()).unwrap();
+
+        assert_eq!(reread.get_offset(), Some(ImageOffset { x: i32::MAX, y: -1, unit: OffsetUnit::Pixel }));
+        assert_eq!(reread.iter().filter(|chunk| chunk.type_name == b"oFFs").count(), 1);
+
+        png.upsert_chunk(b"oFFs", vec![0, 0, 0, 1, 0, 0, 0, 1, 2]).unwrap();
+
+        assert_eq!(png.get_offset(), None);
+    }
+
+    #[test]
+    fn stereo_pair_splits_for_either_mode () {
+
+        /* 12 columns, two 4 column sub-images and 4 columns of padding so the right one starts at column 8 */
+        let left_eye: u8 = 10;
+        let right_eye: u8 = 200;
+        let row: Vec<u8> = [[left_eye; 4], [0; 4], [right_eye; 4]].concat();
+        let pair = PixelBuffer::from_data(12, 2, COLOR_TYPE_GRAYSCALE, 8, row.repeat(2)).unwrap();
+
+        assert_eq!(stereo_subimage_width(12), Ok(4));
+
+        /* Diverging-fuse has the left eye image on the left */
+        let (left, right) = pair.split_stereo(StereoMode::DivergingFuse).unwrap();
+
+        assert_eq!((left.width, left.height), (4, 2));
+        assert_eq!(left.data, [left_eye; 8]);
+        assert_eq!(right.data, [right_eye; 8]);
+
+        /* Cross-fuse has it on the right */
+        let (left, right) = pair.split_stereo(StereoMode::CrossFuse).unwrap();
+
+        assert_eq!(left.data, [right_eye; 8]);
+        assert_eq!(right.data, [left_eye; 8]);
+
+        assert_eq!(PixelBuffer::new(20, 1, COLOR_TYPE_GRAYSCALE, 8).unwrap().split_stereo(StereoMode::CrossFuse).err(), Some(PngError::InvalidStereoWidth(20)));
+
+        let mut png = Png::try_from(fixtures::rgb()).unwrap();
+
+        assert_eq!(png.stereo_mode(), None);
+
+        png.set_stereo_mode(StereoMode::DivergingFuse).unwrap();
+
+        assert_eq!(png.stereo_mode(), Some(StereoMode::DivergingFuse));
+    }
+
+    #[test]
+    fn dpi_round_trips_through_phys () {
+
+        let mut png = Png::try_from(fixtures::rgb()).unwrap();
+
+        for (dpi, pixels_per_meter) in [(72, 2835), (300, 11811), (96, 3780)] {
+
+            png.set_dpi(dpi).unwrap();
+
+            let physical = Png::try_from(png.to_bytes()).unwrap().physical_dimensions().unwrap();
+
+            assert_eq!(physical, PhysicalDimensions { x: pixels_per_meter, y: pixels_per_meter, unit: PhysicalUnit::Meter });
+            assert_eq!(physical.dpi(), Some((dpi, dpi)));
+        }
+
+        assert_eq!(png.iter().filter(|chunk| chunk.type_name == b"pHYs").count(), 1);
+
+        /* An aspect ratio alone has no dpi */
+        png.set_physical_dimensions(2, 1, PhysicalUnit::Unknown).unwrap();
+
+        assert_eq!(png.physical_dimensions().unwrap().dpi(), None);
+        assert_eq!(png.set_dpi(0), Err(PngError::InvalidResolution { x: 0, y: 0 }));
+    }
+
+    #[test]
+    fn transparent_color_checks_the_bit_depth_first () {
+
+        let mut png = Png::try_from(fixtures::rgb()).unwrap();
+
+        png.set_transparent_color(Pixel::Rgb(1, 2, 255)).unwrap();
+
+        assert_eq!(png.transparent_color(), Some(Pixel::Rgb(1, 2, 255)));
+        assert_eq!(png.set_transparent_color(Pixel::Rgb(1, 2, 256)), Err(PngError::SampleOutOfRange { value: 256, max: 255 }));
+
+        /* IHDR data patched to bit depths no shift of a u32 survives */
+        for bit_depth in [3, 32, 200] {
+
+            let ihdr = png.iter_mut().next().unwrap();
+            let mut data = ihdr.data().to_vec();
+
+            data[8] = bit_depth;
+            ihdr.set_data(data);
+
+            assert_eq!(png.set_transparent_color(Pixel::Rgb(0, 0, 0)), Err(PngError::UnsupportedBitDepth(bit_depth)));
+        }
+    }
+
+    #[test]
+    fn palette_alpha_round_trips () {
+
+        let mut png = Png::try_from(fixtures::file(&[
+
+            (b"IHDR", fixtures::ihdr(2, 2, 8, COLOR_TYPE_INDEXED)),
+            (b"PLTE", vec![255, 0, 0, 0, 255, 0, 0, 0, 255]),
+            (b"IDAT", vec![0x78, 0x01]),
+            (b"IEND", Vec::new()),
+        ])).unwrap();
+
+        /* Trailing opaque entries are left out */
+        let index = png.set_palette_alpha(&[0, 128, 255]).unwrap().unwrap();
+        let reread = Png::try_from(png.to_bytes()).unwrap();
+
+        assert_eq!(reread.chunk_at(index).unwrap().get_type_name(), "tRNS");
+        assert_eq!(reread.chunk_at(index).unwrap().data(), [0, 128]);
+        assert!(reread.validate().is_valid());
+
+        assert_eq!(png.set_palette_alpha(&[0, 0, 0, 0]), Err(PngError::InvalidChunkLength { type_name: "tRNS", expected: 3, found: 4 }));
+
+        /* All opaque removes tRNS */
+        assert_eq!(png.set_palette_alpha(&[255; 3]), Ok(None));
+        assert!(!png.iter().any(|chunk| chunk.type_name == b"tRNS"));
+    }
+
+    #[test]
+    fn indexed_copy_expands_back_to_the_original () {
+
+        let rgba = PixelBuffer::from_fn(4, 3, COLOR_TYPE_TRUECOLOR_ALPHA, 8, |x, y| Pixel::Rgba((x * 60) as u16, (y * 100) as u16, 7, if x == 0 { 0 } else { 255 })).unwrap();
+        let (indexed, palette, trns) = rgba.to_indexed().unwrap();
+
+        assert_eq!(trns, [0, 0, 0]);
+        assert_eq!(indexed.expand_palette(&palette, &trns).unwrap().data, rgba.data);
+
+        /* Without alpha the expansion is RGB */
+        let rgb = PixelBuffer::from_fn(3, 1, COLOR_TYPE_TRUECOLOR, 8, |x, _| Pixel::Rgb(x as u16, 0, 0)).unwrap();
+        let (indexed, palette, trns) = rgb.to_indexed().unwrap();
+
+        assert!(trns.is_empty());
+        assert_eq!(indexed.expand_palette(&palette, &trns).unwrap().data, rgb.data);
+    }
+}
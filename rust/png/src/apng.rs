@@ -0,0 +1,217 @@
+/*
+    lib/rust/png/src/apng.rs
+    Q@khaa.pk
+ */
+
+use crate::Png;
+
+/*
+    Data field lengths of the APNG chunks, acTL is num_frames and num_plays, fcTL starts with its sequence number and fdAT too
+ */
+const LENGTH_OF_ACTL_DATA: usize = 8;
+const LENGTH_OF_FCTL_DATA: usize = 26;
+
+impl Png {
+
+    /*
+        Data field of the acTL chunk, None when there is none, it comes after the first IDAT (where a decoder ignores it) or is not 8 bytes long
+     */
+    fn actl (&self) -> Option<&[u8]> {
+
+        let actl = self.iter().position(|chunk| chunk.type_name == b"acTL")?;
+
+        if let Some(idat) = self.iter().position(|chunk| chunk.type_name == b"IDAT") {
+
+            if idat < actl {
+
+                return None;
+            }
+        }
+
+        let data = self.chunks[actl].data();
+
+        if data.len() == LENGTH_OF_ACTL_DATA { Some(data) } else { None }
+    }
+
+    /*
+        True for an APNG, that is an image with a well formed acTL chunk ahead of its image data. Nothing is decoded.
+     */
+    pub fn is_animated (&self) -> bool {
+
+        self.actl().is_some()
+    }
+
+    /*
+        Number of frames the acTL chunk announces, None for images that are not animated.
+        The fcTL chunks actually present are not counted, checked_frame_count() does that.
+     */
+    pub fn frame_count (&self) -> Option<u32> {
+
+        self.actl().map(|data| u32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+    }
+
+    /*
+        Number of times the animation plays, 0 meaning forever. None for images that are not animated.
+     */
+    pub fn loop_count (&self) -> Option<u32> {
+
+        self.actl().map(|data| u32::from_be_bytes([data[4], data[5], data[6], data[7]]))
+    }
+
+    /*
+        True when the sequence numbers of the fcTL and fdAT chunks count up from 0 without gaps in file order, as the APNG specification requires.
+        False too when one of those chunks is too short to carry its sequence number or an fcTL chunk is not 26 bytes long.
+     */
+    pub fn is_fctl_sequence_valid (&self) -> bool {
+
+        for (expected, chunk) in (0u32 ..).zip(self.iter().filter(|chunk| chunk.type_name == b"fcTL" || chunk.type_name == b"fdAT")) {
+
+            let data = chunk.data();
+
+            if data.len() < 4 || (chunk.type_name == b"fcTL" && data.len() != LENGTH_OF_FCTL_DATA) {
+
+                return false;
+            }
+
+            if u32::from_be_bytes([data[0], data[1], data[2], data[3]]) != expected {
+
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /*
+        frame_count(), but only when it agrees with the number of fcTL chunks present and their sequence numbers are valid
+     */
+    pub fn checked_frame_count (&self) -> Option<u32> {
+
+        let frame_count = self.frame_count()?;
+        let fctl_count = self.iter().filter(|chunk| chunk.type_name == b"fcTL").count();
+
+        if fctl_count as u64 == frame_count as u64 && self.is_fctl_sequence_valid() {
+
+            Some(frame_count)
+        }
+        else {
+
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::fixtures;
+    use crate::Png;
+
+    /*
+        acTL data announcing @frames frames played @plays times
+     */
+    fn actl (frames: u32, plays: u32) -> Vec<u8> {
+
+        [frames.to_be_bytes(), plays.to_be_bytes()].concat()
+    }
+
+    /*
+        fcTL data with sequence number @sequence, the frame fields left zero
+     */
+    fn fctl (sequence: u32) -> Vec<u8> {
+
+        let mut data = vec![0; 26];
+
+        data[0 .. 4].copy_from_slice(&sequence.to_be_bytes());
+
+        data
+    }
+
+    fn animation (chunks: &[(&[u8; 4], Vec<u8>)]) -> Png {
+
+        let mut all: Vec<(&[u8; 4], Vec<u8>)> = vec![(b"IHDR", fixtures::ihdr(2, 2, 8, 2))];
+
+        all.extend(chunks.iter().cloned());
+        all.push((b"IEND", Vec::new()));
+
+        Png::try_from(fixtures::file(&all)).unwrap()
+    }
+
+    #[test]
+    fn two_frame_animation () {
+
+        let png = animation(&[
+
+            (b"acTL", actl(2, 0)),
+            (b"fcTL", fctl(0)),
+            (b"IDAT", vec![0x78, 0x01]),
+            (b"fcTL", fctl(1)),
+            (b"fdAT", [2u32.to_be_bytes().to_vec(), vec![0x78, 0x01]].concat()),
+        ]);
+
+        assert!(png.is_animated());
+        assert_eq!(png.frame_count(), Some(2));
+        assert_eq!(png.loop_count(), Some(0));
+        assert!(png.is_fctl_sequence_valid());
+        assert_eq!(png.checked_frame_count(), Some(2));
+    }
+
+    #[test]
+    fn actl_after_idat_is_ignored () {
+
+        let png = animation(&[
+
+            (b"IDAT", vec![0x78, 0x01]),
+            (b"acTL", actl(1, 3)),
+            (b"fcTL", fctl(0)),
+        ]);
+
+        assert!(!png.is_animated());
+        assert_eq!(png.frame_count(), None);
+        assert_eq!(png.loop_count(), None);
+        assert_eq!(png.checked_frame_count(), None);
+
+        assert!(!Png::try_from(fixtures::rgb()).unwrap().is_animated());
+    }
+
+    #[test]
+    fn sequence_gap_fails_the_check () {
+
+        let png = animation(&[
+
+            (b"acTL", actl(2, 1)),
+            (b"fcTL", fctl(0)),
+            (b"IDAT", vec![0x78, 0x01]),
+            (b"fcTL", fctl(2)),
+        ]);
+
+        assert_eq!(png.frame_count(), Some(2));
+        assert!(!png.is_fctl_sequence_valid());
+        assert_eq!(png.checked_frame_count(), None);
+    }
+
+    #[test]
+    fn short_fctl_fails_the_check () {
+
+        let png = animation(&[
+
+            (b"acTL", actl(1, 1)),
+            (b"fcTL", fctl(0)[.. 25].to_vec()),
+            (b"IDAT", vec![0x78, 0x01]),
+        ]);
+
+        assert!(!png.is_fctl_sequence_valid());
+        assert_eq!(png.checked_frame_count(), None);
+
+        /* An fdAT too short for its sequence number fails it the same way */
+        let png = animation(&[
+
+            (b"acTL", actl(1, 1)),
+            (b"fcTL", fctl(0)),
+            (b"IDAT", vec![0x78, 0x01]),
+            (b"fdAT", vec![0, 0, 1]),
+        ]);
+
+        assert!(!png.is_fctl_sequence_valid());
+    }
+}
@@ -0,0 +1,147 @@
+/*
+    lib/rust/png/src/base64.rs
+    Q@khaa.pk
+ */
+
+use crate::error::PngError;
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/*
+    Standard base64 (RFC 4648) with = padding
+ */
+pub fn encode (bytes: &[u8]) -> String {
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for group in bytes.chunks(3) {
+
+        let bits = (group[0] as u32) << 16 | (*group.get(1).unwrap_or(&0) as u32) << 8 | *group.get(2).unwrap_or(&0) as u32;
+
+        for index in 0 .. 4 {
+
+            if index <= group.len() {
+
+                encoded.push(BASE64_ALPHABET[(bits >> (18 - 6 * index) & 0x3F) as usize] as char);
+            }
+            else {
+
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+fn value_of (character: u8) -> Option<u32> {
+
+    BASE64_ALPHABET.iter().position(|letter| *letter == character).map(|value| value as u32)
+}
+
+/*
+    Standard base64, padded or not. Strict otherwise, no whitespace, padding only at the very end and only as much as the last group needs,
+    and unused bits of the last character have to be zero. InvalidBase64 gives the position of the first offending character.
+ */
+pub fn decode (text: &str) -> Result<Vec<u8>, PngError> {
+
+    let text = text.as_bytes();
+    let unpadded = text.iter().position(|character| *character == b'=').unwrap_or(text.len());
+    let padding = text.len() - unpadded;
+
+    if let Some(position) = text[unpadded ..].iter().position(|character| *character != b'=') {
+
+        return Err(PngError::InvalidBase64 { position: unpadded + position });
+    }
+
+    if unpadded % 4 == 1 {
+
+        return Err(PngError::TruncatedBase64);
+    }
+
+    if padding > 0 && (!text.len().is_multiple_of(4) || padding > 2) {
+
+        return Err(PngError::InvalidBase64 { position: unpadded });
+    }
+
+    let mut decoded: Vec<u8> = Vec::with_capacity(unpadded / 4 * 3 + 2);
+
+    for (group_index, group) in text[.. unpadded].chunks(4).enumerate() {
+
+        let mut bits: u32 = 0;
+
+        for (index, character) in group.iter().enumerate() {
+
+            match value_of(*character) {
+
+                Some(value) => bits |= value << (18 - 6 * index),
+                None => return Err(PngError::InvalidBase64 { position: group_index * 4 + index }),
+            }
+        }
+
+        let length = group.len() - 1;
+        let bytes = bits.to_be_bytes();
+
+        if bits & (0xFFFFFF >> (8 * length)) != 0 {
+
+            return Err(PngError::InvalidBase64 { position: group_index * 4 + length });
+        }
+
+        decoded.extend_from_slice(&bytes[1 ..= length]);
+    }
+
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{fixtures, Png};
+
+    #[test]
+    fn round_trip_of_every_length () {
+
+        let bytes: Vec<u8> = (0 ..= 255).collect();
+
+        for length in 0 .. bytes.len() {
+
+            assert_eq!(decode(&encode(&bytes[.. length])).unwrap(), &bytes[.. length]);
+        }
+
+        assert_eq!(encode(b"PNG"), "UE5H");
+        assert_eq!(encode(b"PN"), "UE4=");
+    }
+
+    #[test]
+    fn data_uri_round_trip_is_byte_identical () {
+
+        let bytes = fixtures::rgb();
+        let uri = Png::try_from(&bytes[..]).unwrap().to_data_uri();
+
+        assert!(uri.starts_with("data:image/png;base64,"), "{}", uri);
+        assert_eq!(Png::from_data_uri(&uri).unwrap().to_bytes(), bytes);
+    }
+
+    #[test]
+    fn wrong_mime_type_and_truncated_payload_are_told_apart () {
+
+        let uri = Png::try_from(fixtures::rgb()).unwrap().to_data_uri();
+        let payload = &uri["data:image/png;base64,".len() ..];
+
+        let wrong_mime = Png::from_data_uri(&format!("data:image/gif;base64,{}", payload)).unwrap_err();
+        let truncated = Png::from_data_uri(&uri[.. uri.len() - payload.len() + 4 * 5 + 1]).unwrap_err();
+
+        assert_eq!(wrong_mime, PngError::DataUriMimeType("image/gif".to_string()));
+        assert_eq!(truncated, PngError::TruncatedBase64);
+        assert_ne!(wrong_mime, truncated);
+    }
+
+    #[test]
+    fn invalid_characters_give_their_position () {
+
+        assert_eq!(decode("UE5H UE4"), Err(PngError::InvalidBase64 { position: 4 }));
+        assert_eq!(decode("UE4=UE5H"), Err(PngError::InvalidBase64 { position: 4 }));
+        assert_eq!(decode("UE5"), Err(PngError::InvalidBase64 { position: 2 }));
+    }
+}
@@ -0,0 +1,253 @@
+/*
+    lib/rust/png/src/compare.rs
+    Q@khaa.pk
+ */
+
+use crate::error::PngError;
+use crate::pixels::PixelBuffer;
+
+/*
+    Result of PixelBuffer::diff()
+ */
+#[derive(Clone)]
+pub struct DiffReport {
+
+    pub differing_pixels: usize,
+    /* Largest difference of any one sample, at the bit depth the images were compared at */
+    pub max_delta: u32,
+    /* RGBA copy of the first image with every differing pixel painted opaque red, when asked for */
+    pub visual: Option<PixelBuffer>,
+}
+
+/*
+    Both images as RGBA at the larger of their two bit depths, so images stored with different color types can be compared sample by sample
+ */
+fn reconcile (a: &PixelBuffer, b: &PixelBuffer) -> Result<(PixelBuffer, PixelBuffer), PngError> {
+
+    if a.width != b.width || a.height != b.height {
+
+        return Err(PngError::DimensionMismatch { expected: (a.width, a.height), found: (b.width, b.height) });
+    }
+
+    let bit_depth = a.bit_depth.max(b.bit_depth);
+
+    Ok((a.to_rgba()?.convert_bit_depth(bit_depth)?, b.to_rgba()?.convert_bit_depth(bit_depth)?))
+}
+
+impl PixelBuffer {
+
+    /*
+        Compares two images of the same dimensions pixel by pixel, color types are reconciled by converting both to RGBA first.
+        Set @visual to also get a picture of where they differ.
+     */
+    pub fn diff (&self, other: &PixelBuffer, visual: bool) -> Result<DiffReport, PngError> {
+
+        let (a, b) = reconcile(self, other)?;
+
+        let sample_size = a.bytes_per_sample();
+        let bytes_per_pixel = a.bytes_per_pixel();
+        let max = a.max_sample_value();
+
+        let mut report = DiffReport { differing_pixels: 0, max_delta: 0, visual: None };
+        let mut picture = if visual { Some(a.clone()) } else { None };
+
+        for pixel in 0 .. a.width as usize * a.height as usize {
+
+            let offset = pixel * bytes_per_pixel;
+            let mut differs: bool = false;
+
+            for channel in 0 .. 4 {
+
+                let delta = a.sample(offset + channel * sample_size).abs_diff(b.sample(offset + channel * sample_size));
+
+                report.max_delta = report.max_delta.max(delta);
+                differs = differs || delta != 0;
+            }
+
+            if differs {
+
+                report.differing_pixels += 1;
+
+                if let Some(picture) = picture.as_mut() {
+
+                    for (channel, value) in [max, 0, 0, max].iter().enumerate() {
+
+                        picture.set_sample(offset + channel * sample_size, *value);
+                    }
+                }
+            }
+        }
+
+        report.visual = picture;
+
+        Ok(report)
+    }
+}
+
+/*
+    Mean squared error between two images of the same dimensions, in units of the squared sample value at the larger of their bit depths.
+    Both are reconciled to RGBA as diff() does, the error is taken per channel and averaged over the channels, alpha left out when neither image has it.
+ */
+pub fn mse (a: &PixelBuffer, b: &PixelBuffer) -> Result<f64, PngError> {
+
+    let channels = if a.has_alpha() || b.has_alpha() { 4 } else { 3 };
+    let (a, b) = reconcile(a, b)?;
+
+    let sample_size = a.bytes_per_sample();
+    let bytes_per_pixel = a.bytes_per_pixel();
+    let pixels = a.width as usize * a.height as usize;
+
+    if pixels == 0 {
+
+        return Err(PngError::EmptyImage);
+    }
+
+    let mut sums: [f64; 4] = [0.0; 4];
+
+    for pixel in 0 .. pixels {
+
+        let offset = pixel * bytes_per_pixel;
+
+        for (channel, sum) in sums.iter_mut().enumerate().take(channels) {
+
+            let delta = a.sample(offset + channel * sample_size).abs_diff(b.sample(offset + channel * sample_size)) as f64;
+
+            *sum += delta * delta;
+        }
+    }
+
+    Ok(sums[.. channels].iter().map(|sum| sum / pixels as f64).sum::<f64>() / channels as f64)
+}
+
+/*
+    Peak signal to noise ratio in dB from mse(), the peak being the largest sample value at the bit depth compared at.
+    Identical images give f64::INFINITY.
+ */
+pub fn psnr (a: &PixelBuffer, b: &PixelBuffer) -> Result<f64, PngError> {
+
+    let error = mse(a, b)?;
+    let peak = ((1u32 << a.bit_depth.max(b.bit_depth)) - 1) as f64;
+
+    if error == 0.0 {
+
+        return Ok(f64::INFINITY);
+    }
+
+    Ok(10.0 * (peak * peak / error).log10())
+}
+
+/*
+    Asserts two PixelBuffers hold the same pixels (after reconciling color types), reporting how many pixels differ and by how much when they don't
+ */
+#[macro_export]
+macro_rules! assert_images_equal {
+
+    ($left:expr, $right:expr) => {
+
+        match $left.diff(&$right, false) {
+
+            Ok(report) => assert!(report.differing_pixels == 0, "images differ in {} pixels, largest sample difference {}", report.differing_pixels, report.max_delta),
+            Err(error) => panic!("images can't be compared: {:?}", error),
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::constants::{COLOR_TYPE_GRAYSCALE, COLOR_TYPE_TRUECOLOR, COLOR_TYPE_TRUECOLOR_ALPHA};
+
+    fn rgb (width: u32, height: u32, value: u8) -> PixelBuffer {
+
+        PixelBuffer::from_data(width, height, COLOR_TYPE_TRUECOLOR, 8, vec![value; width as usize * height as usize * 3]).unwrap()
+    }
+
+    #[test]
+    fn identical_images_have_infinite_psnr () {
+
+        let image = rgb(4, 4, 90);
+
+        assert_eq!(mse(&image, &image.clone()).unwrap(), 0.0);
+        assert_eq!(psnr(&image, &image.clone()).unwrap(), f64::INFINITY);
+    }
+
+    #[test]
+    fn mse_of_known_inputs () {
+
+        let a = rgb(4, 4, 100);
+        let mut b = a.clone();
+
+        b.data[5] = 110;
+
+        assert_eq!(mse(&a, &b).unwrap(), 100.0 / 16.0 / 3.0);
+        assert_eq!(mse(&a, &rgb(4, 4, 110)).unwrap(), 100.0);
+        assert!((psnr(&a, &rgb(4, 4, 110)).unwrap() - 10.0 * (255.0f64 * 255.0 / 100.0).log10()).abs() < 1e-12);
+
+        /* An opaque alpha channel on one side counts as a fourth channel that never differs */
+        let rgba = PixelBuffer::from_data(1, 1, COLOR_TYPE_TRUECOLOR_ALPHA, 8, vec![110, 110, 110, 255]).unwrap();
+
+        assert_eq!(mse(&rgb(1, 1, 100), &rgba).unwrap(), 75.0);
+    }
+
+    #[test]
+    fn mse_of_different_shapes_is_an_error () {
+
+        assert!(matches!(mse(&rgb(4, 4, 0), &rgb(4, 3, 0)), Err(PngError::DimensionMismatch { .. })));
+        assert!(psnr(&rgb(2, 2, 0), &rgb(1, 4, 0)).is_err());
+    }
+
+    #[test]
+    fn diff_counts_and_paints_differing_pixels () {
+
+        let a = rgb(3, 1, 100);
+        let mut b = a.clone();
+
+        b.data[4] = 140;
+        b.data[8] = 90;
+
+        let report = a.diff(&b, true).unwrap();
+
+        assert_eq!(report.differing_pixels, 2);
+        assert_eq!(report.max_delta, 40);
+        assert_eq!(report.visual.unwrap().data, [100, 100, 100, 255, 255, 0, 0, 255, 255, 0, 0, 255]);
+
+        assert!(a.diff(&b, false).unwrap().visual.is_none());
+        assert!(matches!(a.diff(&rgb(1, 3, 100), false), Err(PngError::DimensionMismatch { .. })));
+
+        /* A gray image and its RGB copy hold the same pixels */
+        let gray = PixelBuffer::from_data(3, 1, COLOR_TYPE_GRAYSCALE, 8, vec![100; 3]).unwrap();
+
+        let report = gray.diff(&a, false).unwrap();
+
+        assert_eq!((report.differing_pixels, report.max_delta), (0, 0));
+    }
+
+    #[test]
+    fn assert_images_equal_passes_on_the_same_pixels () {
+
+        let gray = PixelBuffer::from_data(2, 2, COLOR_TYPE_GRAYSCALE, 8, vec![7; 4]).unwrap();
+        let image = rgb(2, 2, 7);
+
+        crate::assert_images_equal!(gray, image);
+    }
+
+    #[test]
+    #[should_panic(expected = "images differ in 1 pixels, largest sample difference 3")]
+    fn assert_images_equal_panics_on_different_pixels () {
+
+        let image = rgb(2, 2, 7);
+        let mut other = image.clone();
+
+        other.data[11] = 10;
+
+        crate::assert_images_equal!(image, other);
+    }
+
+    #[test]
+    #[should_panic(expected = "images can't be compared")]
+    fn assert_images_equal_panics_on_different_dimensions () {
+
+        crate::assert_images_equal!(rgb(2, 2, 7), rgb(4, 1, 7));
+    }
+}
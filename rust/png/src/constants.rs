@@ -5,7 +5,17 @@
 #![allow(dead_code)]
 
 pub const LENGTH_OF_SIGNATURE: usize = 8;
+pub const PNG_SIGNATURE: [u8; LENGTH_OF_SIGNATURE] = [137, 80, 78, 71, 13, 10, 26, 10];
 pub const LENGTH_OF_LENGTH_FIELD: usize = 4;
 pub const LENGTH_OF_TYPE_FIELD: usize = 4;
 pub const LENGTH_OF_CRC_FIELD: usize = 4;
-pub const LENGTH_OF_THREE_FIELDS: usize = LENGTH_OF_LENGTH_FIELD + LENGTH_OF_TYPE_FIELD + LENGTH_OF_CRC_FIELD;
\ No newline at end of file
+pub const LENGTH_OF_THREE_FIELDS: usize = LENGTH_OF_LENGTH_FIELD + LENGTH_OF_TYPE_FIELD + LENGTH_OF_CRC_FIELD;
+
+pub const COLOR_TYPE_GRAYSCALE: u8 = 0;
+pub const COLOR_TYPE_TRUECOLOR: u8 = 2;
+pub const COLOR_TYPE_INDEXED: u8 = 3;
+pub const COLOR_TYPE_GRAYSCALE_ALPHA: u8 = 4;
+pub const COLOR_TYPE_TRUECOLOR_ALPHA: u8 = 6;
+
+/* (x start, y start, x step, y step) of each of the seven Adam7 passes */
+pub const ADAM7_PASSES: [(u32, u32, u32, u32); 7] = [(0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2)];
@@ -0,0 +1,55 @@
+/*
+    lib/rust/png/src/crc.rs
+    Q@khaa.pk
+ */
+
+/*
+    CRC of every byte value, polynomial 0xEDB88320 as given in the PNG specification (Annex D)
+ */
+const CRC_TABLE: [u32; 256] = make_crc_table();
+
+const fn make_crc_table () -> [u32; 256] {
+
+    let mut table = [0u32; 256];
+    let mut n: usize = 0;
+
+    while n < 256 {
+
+        let mut c = n as u32;
+        let mut k = 0;
+
+        while k < 8 {
+
+            c = if c & 1 != 0 { 0xEDB88320 ^ (c >> 1) } else { c >> 1 };
+            k = k + 1;
+        }
+
+        table[n] = c;
+        n = n + 1;
+    }
+
+    table
+}
+
+/*
+    @crc is the running CRC of the bytes seen so far, start with 0
+ */
+pub fn update_crc (crc: u32, bytes: &[u8]) -> u32 {
+
+    let mut c = crc ^ 0xFFFFFFFF;
+
+    for byte in bytes {
+
+        c = CRC_TABLE[((c ^ *byte as u32) & 0xFF) as usize] ^ (c >> 8);
+    }
+
+    c ^ 0xFFFFFFFF
+}
+
+/*
+    CRC of a chunk, it covers the type field and the data field but not the length field
+ */
+pub fn chunk_crc (type_name: &[u8], data: &[u8]) -> u32 {
+
+    update_crc(update_crc(0, type_name), data)
+}
@@ -0,0 +1,300 @@
+/*
+    lib/rust/png/src/error.rs
+    Q@khaa.pk
+ */
+
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+/*
+    New variants can be added in any release, so match on PngError with a wildcard arm
+ */
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum PngError {
+
+    /* Reading or writing a file failed, the io::Error is shared so PngError stays cheap to clone */
+    Io(Arc<io::Error>),
+    /* First 8 bytes are not the PNG signature */
+    InvalidSignature,
+    /* Chunk starting at byte @offset of the file reaches past its end */
+    TruncatedChunk { offset: usize },
+    /* Stored CRC of chunk number @index, of type @type_name, doesn't match its type and data */
+    CrcMismatch { index: usize, type_name: String },
+
+    /* Color type is not one of 0, 2, 3, 4 or 6, or is not handled by the operation */
+    UnsupportedColorType(u8),
+    /* Only whole byte samples (8 and 16 bits) are handled on decoded pixels */
+    UnsupportedBitDepth(u8),
+    /* Bit depth is not one the PNG specification allows for the color type */
+    InvalidBitDepthForColorType { color_type: u8, bit_depth: u8 },
+    /* Width and height have to be between 1 and 2^31 - 1 */
+    InvalidDimensions { width: u32, height: u32 },
+    /* Length of a pixel buffer does not agree with its width, height, color type and bit depth */
+    BufferSizeMismatch { expected: usize, found: usize },
+    /* Two pixel buffers were combined but they don't share color type and bit depth */
+    IncompatiblePixelFormat { expected: (u8, u8), found: (u8, u8) },
+    /* Two images were expected to have the same (width, height) */
+    DimensionMismatch { expected: (u32, u32), found: (u32, u32) },
+    /* A sample of a Pixel doesn't fit in the bit depth of the buffer it is written to */
+    SampleOutOfRange { value: u32, max: u32 },
+    /* A chunk the operation depends on (named by its type) is not in the image */
+    MissingChunk(&'static str),
+    /* Data field of a chunk (named by its type) is not the length the specification gives it */
+    InvalidChunkLength { type_name: &'static str, expected: usize, found: usize },
+    /* Image already has a chunk of this type and the specification allows only one */
+    DuplicateChunk(String),
+    /* Chunks already in the image leave no position where this chunk type is allowed */
+    ChunkOrder(String),
+    /* Character has no Latin-1 byte, so tEXt and zTXt can't carry it */
+    NotLatin1(char),
+    /* Keyword of a text chunk breaks one of the rules of section 11.3.4.2, the rule is named */
+    InvalidKeyword(&'static str),
+    /* pHYs can't give zero pixels per unit along an axis */
+    InvalidResolution { x: u32, y: u32 },
+    /* Stereo pair this wide can't be split, it would need 8 or more columns of padding between the sub-images */
+    InvalidStereoWidth(u32),
+    /* Field of a text chunk other than its keyword can't be written as given, the reason is named */
+    InvalidTextField(&'static str),
+    /* Image has more distinct colors than the 256 a palette holds */
+    TooManyColors,
+    /* Tile at (@column, @row) lies outside a grid of @columns by @rows */
+    TileOutsideGrid { column: u32, row: u32, columns: u32, rows: u32 },
+    /* Chunk type is critical to decoding the image and can't be removed without forcing it */
+    CriticalChunk(String),
+    /* premultiply_alpha() was called on a buffer whose color samples are already multiplied by alpha */
+    AlphaAlreadyPremultiplied,
+    /* unpremultiply_alpha() was called on a buffer holding straight alpha */
+    AlphaNotPremultiplied,
+    /* Channel @index was asked for but pixels only have @channels channels */
+    ChannelOutOfRange { index: usize, channels: usize },
+    /* Only 1 (gray), 2 (gray+alpha), 3 (RGB) or 4 (RGBA) planes can be interleaved into pixels */
+    UnsupportedPlaneCount(usize),
+    /* Plane number @plane has @found bytes where width * height samples take @expected bytes */
+    PlaneSizeMismatch { plane: usize, expected: usize, found: usize },
+    /* A @width by @height block at (@x, @y) reaches past the edges of the image */
+    BlockOutOfBounds { x: u32, y: u32, width: usize, height: usize },
+    /* Block number @block_number ends past the end of a buffer of @buffer_len bytes */
+    SliceOutOfBounds { block_number: usize, buffer_len: usize },
+    /* Input in another image format is a variant of it this crate can't read, which one is described */
+    UnsupportedFormat(String),
+    /* Input in another image format is damaged or not in that format at all, what is wrong is named */
+    MalformedInput(&'static str),
+    /* Text is not a data URI of the form data:<mime type>;base64,<payload>, what is wrong is named */
+    InvalidDataUri(&'static str),
+    /* Data URI carries this MIME type rather than image/png */
+    DataUriMimeType(String),
+    /* Character at @position of a base64 payload is not valid there */
+    InvalidBase64 { position: usize },
+    /* Base64 payload ends in the middle of a byte */
+    TruncatedBase64,
+    /* Size computed from IHDR does not fit in 64 bits */
+    SizeOverflow,
+    /* Image would take @estimate bytes to decode, more than the @limit the caller allows */
+    DecodeLimitExceeded { limit: u64, estimate: u64 },
+    /* Image has no pixels (zero width or height) to work on */
+    EmptyImage,
+    /* No target block clear of the context block was found, the block scales leave too little room */
+    BlockSamplingFailed,
+    /* Block dimensions can't be worked out from the given input and BlockConfig, the rule broken is named */
+    InvalidBlockGeometry(&'static str),
+    /* Per channel values (such as mean and std) were given for @found channels where the tensor has @expected */
+    ChannelCountMismatch { expected: usize, found: usize },
+    /* Standard deviation of the channel is zero, normalizing by it would divide by zero */
+    ZeroStandardDeviation(usize),
+}
+
+impl fmt::Display for PngError {
+
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+
+        match self {
+
+            PngError::Io(error) => write!(f, "I/O error: {}", error),
+            PngError::InvalidSignature => write!(f, "not a PNG file, the first 8 bytes are not the PNG signature"),
+            PngError::TruncatedChunk { offset } => write!(f, "chunk at byte offset {} reaches past the end of the file", offset),
+            PngError::CrcMismatch { index, type_name } => write!(f, "CRC of chunk {} ({}) does not match its type and data", index, type_name),
+            PngError::UnsupportedColorType(color_type) => write!(f, "color type {} is not supported here (PNG color types are 0, 2, 3, 4 and 6)", color_type),
+            PngError::UnsupportedBitDepth(bit_depth) => write!(f, "bit depth {} is not supported here, only 8 and 16 bit samples are", bit_depth),
+            PngError::InvalidBitDepthForColorType { color_type, bit_depth } => write!(f, "bit depth {} is not allowed for color type {}", bit_depth, color_type),
+            PngError::InvalidDimensions { width, height } => write!(f, "invalid dimensions {}x{}, width and height have to be between 1 and 2147483647", width, height),
+            PngError::BufferSizeMismatch { expected, found } => write!(f, "pixel buffer is {} bytes, expected {} bytes", found, expected),
+            PngError::IncompatiblePixelFormat { expected, found } => write!(f, "pixel format (color type {}, bit depth {}) does not match expected (color type {}, bit depth {})", found.0, found.1, expected.0, expected.1),
+            PngError::DimensionMismatch { expected, found } => write!(f, "image is {}x{}, expected {}x{}", found.0, found.1, expected.0, expected.1),
+            PngError::SampleOutOfRange { value, max } => write!(f, "sample value {} is larger than the bit depth allows ({})", value, max),
+            PngError::MissingChunk(type_name) => write!(f, "{} chunk is missing", type_name),
+            PngError::InvalidChunkLength { type_name, expected, found } => write!(f, "{} chunk has {} data bytes, expected {}", type_name, found, expected),
+            PngError::DuplicateChunk(type_name) => write!(f, "image already has a {} chunk, only one is allowed", type_name),
+            PngError::ChunkOrder(type_name) => write!(f, "no position for a {} chunk satisfies the chunk ordering rules", type_name),
+            PngError::NotLatin1(character) => write!(f, "character {:?} (U+{:04X}) is not in Latin-1", character, *character as u32),
+            PngError::InvalidKeyword(rule) => write!(f, "invalid text keyword: {}", rule),
+            PngError::InvalidResolution { x, y } => write!(f, "resolution of {} by {} pixels per unit is invalid, neither can be 0", x, y),
+            PngError::InvalidStereoWidth(width) => write!(f, "a stereo pair can't be {} pixels wide", width),
+            PngError::InvalidTextField(problem) => write!(f, "invalid text chunk field: {}", problem),
+            PngError::TooManyColors => write!(f, "image has more than 256 colors, too many for a palette"),
+            PngError::TileOutsideGrid { column, row, columns, rows } => write!(f, "tile at column {}, row {} is outside a grid of {} by {}", column, row, columns, rows),
+            PngError::CriticalChunk(type_name) => write!(f, "{} is a critical chunk, removing it has to be forced", type_name),
+            PngError::AlphaAlreadyPremultiplied => write!(f, "alpha is already premultiplied"),
+            PngError::AlphaNotPremultiplied => write!(f, "alpha is not premultiplied"),
+            PngError::ChannelOutOfRange { index, channels } => write!(f, "channel {} asked for but pixels have {} channels", index, channels),
+            PngError::UnsupportedPlaneCount(planes) => write!(f, "{} planes can't be interleaved, only 1, 2, 3 or 4 can", planes),
+            PngError::PlaneSizeMismatch { plane, expected, found } => write!(f, "plane {} is {} bytes, expected {} bytes", plane, found, expected),
+            PngError::BlockOutOfBounds { x, y, width, height } => write!(f, "{}x{} block at ({}, {}) reaches past the edges of the image", width, height, x, y),
+            PngError::SliceOutOfBounds { block_number, buffer_len } => write!(f, "block {} ends past the end of a {} byte buffer", block_number, buffer_len),
+            PngError::UnsupportedFormat(description) => write!(f, "unsupported format: {}", description),
+            PngError::MalformedInput(problem) => write!(f, "malformed input: {}", problem),
+            PngError::InvalidDataUri(problem) => write!(f, "invalid data URI: {}", problem),
+            PngError::DataUriMimeType(mime_type) => write!(f, "data URI is of type {}, expected image/png", mime_type),
+            PngError::InvalidBase64 { position } => write!(f, "invalid base64 character at position {}", position),
+            PngError::TruncatedBase64 => write!(f, "base64 payload is truncated"),
+            PngError::SizeOverflow => write!(f, "image size computed from IHDR overflows 64 bits"),
+            PngError::DecodeLimitExceeded { limit, estimate } => write!(f, "decoding takes {} bytes, more than the limit of {} bytes", estimate, limit),
+            PngError::EmptyImage => write!(f, "image has no pixels"),
+            PngError::BlockSamplingFailed => write!(f, "no target block clear of the context block was found, the block scales leave too little room"),
+            PngError::InvalidBlockGeometry(rule) => write!(f, "invalid block geometry: {}", rule),
+            PngError::ChannelCountMismatch { expected, found } => write!(f, "values given for {} channels, expected {}", found, expected),
+            PngError::ZeroStandardDeviation(channel) => write!(f, "standard deviation of channel {} is zero", channel),
+        }
+    }
+}
+
+/*
+    Io errors compare by io::ErrorKind, io::Error itself can't be compared. Every other variant compares its fields, a new variant needs its arm here.
+ */
+impl PartialEq for PngError {
+
+    fn eq (&self, other: &Self) -> bool {
+
+        match (self, other) {
+
+            (PngError::Io(error), PngError::Io(other)) => error.kind() == other.kind(),
+            (PngError::InvalidSignature, PngError::InvalidSignature) => true,
+            (PngError::TruncatedChunk { offset }, PngError::TruncatedChunk { offset: other_offset }) => offset == other_offset,
+            (PngError::CrcMismatch { index, type_name }, PngError::CrcMismatch { index: other_index, type_name: other_type_name }) => index == other_index && type_name == other_type_name,
+            (PngError::UnsupportedColorType(value), PngError::UnsupportedColorType(other)) => value == other,
+            (PngError::UnsupportedBitDepth(value), PngError::UnsupportedBitDepth(other)) => value == other,
+            (PngError::InvalidBitDepthForColorType { color_type, bit_depth }, PngError::InvalidBitDepthForColorType { color_type: other_color_type, bit_depth: other_bit_depth }) => color_type == other_color_type && bit_depth == other_bit_depth,
+            (PngError::InvalidDimensions { width, height }, PngError::InvalidDimensions { width: other_width, height: other_height }) => width == other_width && height == other_height,
+            (PngError::BufferSizeMismatch { expected, found }, PngError::BufferSizeMismatch { expected: other_expected, found: other_found }) => expected == other_expected && found == other_found,
+            (PngError::IncompatiblePixelFormat { expected, found }, PngError::IncompatiblePixelFormat { expected: other_expected, found: other_found }) => expected == other_expected && found == other_found,
+            (PngError::DimensionMismatch { expected, found }, PngError::DimensionMismatch { expected: other_expected, found: other_found }) => expected == other_expected && found == other_found,
+            (PngError::SampleOutOfRange { value, max }, PngError::SampleOutOfRange { value: other_value, max: other_max }) => value == other_value && max == other_max,
+            (PngError::MissingChunk(value), PngError::MissingChunk(other)) => value == other,
+            (PngError::InvalidChunkLength { type_name, expected, found }, PngError::InvalidChunkLength { type_name: other_type_name, expected: other_expected, found: other_found }) => type_name == other_type_name && expected == other_expected && found == other_found,
+            (PngError::DuplicateChunk(value), PngError::DuplicateChunk(other)) => value == other,
+            (PngError::ChunkOrder(value), PngError::ChunkOrder(other)) => value == other,
+            (PngError::NotLatin1(value), PngError::NotLatin1(other)) => value == other,
+            (PngError::InvalidKeyword(value), PngError::InvalidKeyword(other)) => value == other,
+            (PngError::InvalidResolution { x, y }, PngError::InvalidResolution { x: other_x, y: other_y }) => x == other_x && y == other_y,
+            (PngError::InvalidStereoWidth(value), PngError::InvalidStereoWidth(other)) => value == other,
+            (PngError::InvalidTextField(value), PngError::InvalidTextField(other)) => value == other,
+            (PngError::TooManyColors, PngError::TooManyColors) => true,
+            (PngError::TileOutsideGrid { column, row, columns, rows }, PngError::TileOutsideGrid { column: other_column, row: other_row, columns: other_columns, rows: other_rows }) => column == other_column && row == other_row && columns == other_columns && rows == other_rows,
+            (PngError::CriticalChunk(value), PngError::CriticalChunk(other)) => value == other,
+            (PngError::AlphaAlreadyPremultiplied, PngError::AlphaAlreadyPremultiplied) => true,
+            (PngError::AlphaNotPremultiplied, PngError::AlphaNotPremultiplied) => true,
+            (PngError::ChannelOutOfRange { index, channels }, PngError::ChannelOutOfRange { index: other_index, channels: other_channels }) => index == other_index && channels == other_channels,
+            (PngError::UnsupportedPlaneCount(value), PngError::UnsupportedPlaneCount(other)) => value == other,
+            (PngError::PlaneSizeMismatch { plane, expected, found }, PngError::PlaneSizeMismatch { plane: other_plane, expected: other_expected, found: other_found }) => plane == other_plane && expected == other_expected && found == other_found,
+            (PngError::BlockOutOfBounds { x, y, width, height }, PngError::BlockOutOfBounds { x: other_x, y: other_y, width: other_width, height: other_height }) => x == other_x && y == other_y && width == other_width && height == other_height,
+            (PngError::SliceOutOfBounds { block_number, buffer_len }, PngError::SliceOutOfBounds { block_number: other_block_number, buffer_len: other_buffer_len }) => block_number == other_block_number && buffer_len == other_buffer_len,
+            (PngError::UnsupportedFormat(value), PngError::UnsupportedFormat(other)) => value == other,
+            (PngError::MalformedInput(value), PngError::MalformedInput(other)) => value == other,
+            (PngError::InvalidDataUri(value), PngError::InvalidDataUri(other)) => value == other,
+            (PngError::DataUriMimeType(value), PngError::DataUriMimeType(other)) => value == other,
+            (PngError::InvalidBase64 { position }, PngError::InvalidBase64 { position: other_position }) => position == other_position,
+            (PngError::TruncatedBase64, PngError::TruncatedBase64) => true,
+            (PngError::SizeOverflow, PngError::SizeOverflow) => true,
+            (PngError::DecodeLimitExceeded { limit, estimate }, PngError::DecodeLimitExceeded { limit: other_limit, estimate: other_estimate }) => limit == other_limit && estimate == other_estimate,
+            (PngError::EmptyImage, PngError::EmptyImage) => true,
+            (PngError::BlockSamplingFailed, PngError::BlockSamplingFailed) => true,
+            (PngError::InvalidBlockGeometry(value), PngError::InvalidBlockGeometry(other)) => value == other,
+            (PngError::ChannelCountMismatch { expected, found }, PngError::ChannelCountMismatch { expected: other_expected, found: other_found }) => expected == other_expected && found == other_found,
+            (PngError::ZeroStandardDeviation(value), PngError::ZeroStandardDeviation(other)) => value == other,
+            _ => false,
+        }
+    }
+}
+
+impl std::error::Error for PngError {
+
+    fn source (&self) -> Option<&(dyn std::error::Error + 'static)> {
+
+        match self {
+
+            PngError::Io(error) => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PngError {
+
+    fn from (error: io::Error) -> Self {
+
+        PngError::Io(Arc::new(error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{fixtures, CrcMode, ParseOptions, Png};
+
+    #[test]
+    fn errors_compare_by_value () {
+
+        assert_eq!(PngError::TruncatedChunk { offset: 8 }, PngError::TruncatedChunk { offset: 8 });
+        assert_ne!(PngError::TruncatedChunk { offset: 8 }, PngError::TruncatedChunk { offset: 9 });
+        assert_ne!(PngError::InvalidSignature, PngError::EmptyImage);
+        assert_eq!(PngError::from(io::Error::from(io::ErrorKind::NotFound)), PngError::from(io::Error::new(io::ErrorKind::NotFound, "missing.png")));
+        assert_ne!(PngError::from(io::Error::from(io::ErrorKind::NotFound)), PngError::from(io::Error::from(io::ErrorKind::PermissionDenied)));
+    }
+
+    #[test]
+    fn bad_signature_message () {
+
+        let error = Png::try_from(&b"GIF89a not a png"[..]).unwrap_err();
+
+        assert_eq!(error, PngError::InvalidSignature);
+        assert!(error.to_string().contains("not a PNG file"), "{}", error);
+    }
+
+    #[test]
+    fn truncated_chunk_message_gives_the_offset () {
+
+        let bytes = fixtures::rgb();
+        let error = Png::try_from(&bytes[.. bytes.len() - 4]).unwrap_err();
+
+        /* IEND is the last 12 bytes, cutting into its CRC truncates it */
+        let offset = bytes.len() - 12;
+
+        assert_eq!(error, PngError::TruncatedChunk { offset });
+        assert!(error.to_string().contains(&format!("byte offset {}", offset)), "{}", error);
+    }
+
+    #[test]
+    fn crc_mismatch_message_names_the_chunk () {
+
+        let mut bytes = fixtures::rgb();
+        let ihdr_crc = 8 + 8 + 13;
+
+        bytes[ihdr_crc] ^= 0xFF;
+
+        let error = Png::parse(&bytes, &ParseOptions { crc_mode: CrcMode::Verify }).err().unwrap();
+
+        assert_eq!(error, PngError::CrcMismatch { index: 0, type_name: String::from("IHDR") });
+        assert!(error.to_string().contains("CRC of chunk 0 (IHDR)"), "{}", error);
+    }
+
+    #[test]
+    fn io_errors_keep_their_source () {
+
+        let error = PngError::from(io::Error::new(io::ErrorKind::NotFound, "missing.png"));
+
+        assert!(std::error::Error::source(&error).is_some());
+        assert!(error.to_string().contains("missing.png"), "{}", error);
+    }
+}
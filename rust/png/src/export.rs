@@ -0,0 +1,219 @@
+/*
+    lib/rust/png/src/export.rs
+    Q@khaa.pk
+ */
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::constants::{COLOR_TYPE_GRAYSCALE, COLOR_TYPE_TRUECOLOR, COLOR_TYPE_GRAYSCALE_ALPHA, COLOR_TYPE_TRUECOLOR_ALPHA};
+use crate::error::PngError;
+use crate::pixels::PixelBuffer;
+
+/* BITMAPFILEHEADER followed by BITMAPINFOHEADER */
+const LENGTH_OF_BMP_HEADERS: usize = 14 + 40;
+/* 72 DPI */
+const BMP_PIXELS_PER_METER: u32 = 2835;
+
+/*
+    Raw formats PixelBuffer::export() writes
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ExportFormat {
+
+    /* P5 for gray, P6 for RGB */
+    Ppm,
+    /* P7, any color type but indexed */
+    Pam,
+    /* Uncompressed, 24 bits without alpha and 32 bits with */
+    Bmp,
+}
+
+impl PixelBuffer {
+
+    /*
+        Binary PGM (P5) for gray or PPM (P6) for RGB images, maxval 255 or 65535 by bit depth with 16 bit samples big-endian as the format has them.
+        Images with alpha or a palette can't be written as PPM, see write_pam().
+     */
+    pub fn write_ppm<W: Write> (&self, mut writer: W) -> Result<(), PngError> {
+
+        let magic = match self.color_type {
+
+            COLOR_TYPE_GRAYSCALE => "P5",
+            COLOR_TYPE_TRUECOLOR => "P6",
+            _ => return Err(PngError::UnsupportedColorType(self.color_type)),
+        };
+
+        write!(writer, "{}\n{} {}\n{}\n", magic, self.width, self.height, self.max_sample_value())?;
+        writer.write_all(&self.data)?;
+
+        Ok(())
+    }
+
+    /*
+        PAM (P7) with TUPLTYPE GRAYSCALE, GRAYSCALE_ALPHA, RGB or RGB_ALPHA, samples written as they are stored
+     */
+    pub fn write_pam<W: Write> (&self, mut writer: W) -> Result<(), PngError> {
+
+        let tuple_type = match self.color_type {
+
+            COLOR_TYPE_GRAYSCALE => "GRAYSCALE",
+            COLOR_TYPE_GRAYSCALE_ALPHA => "GRAYSCALE_ALPHA",
+            COLOR_TYPE_TRUECOLOR => "RGB",
+            COLOR_TYPE_TRUECOLOR_ALPHA => "RGB_ALPHA",
+            _ => return Err(PngError::UnsupportedColorType(self.color_type)),
+        };
+
+        write!(writer, "P7\nWIDTH {}\nHEIGHT {}\nDEPTH {}\nMAXVAL {}\nTUPLTYPE {}\nENDHDR\n", self.width, self.height, self.channels(), self.max_sample_value(), tuple_type)?;
+        writer.write_all(&self.data)?;
+
+        Ok(())
+    }
+
+    /*
+        Uncompressed BMP with a BITMAPINFOHEADER, rows bottom-up and padded to 4 bytes, pixels in BGR(A) order.
+        Gray is written as RGB, 16 bit samples are rounded to 8 bits. Images with alpha are 32 bits per pixel, which not every reader shows with transparency.
+     */
+    pub fn write_bmp<W: Write> (&self, mut writer: W) -> Result<(), PngError> {
+
+        let rgba = self.to_rgba()?.convert_bit_depth(8)?;
+
+        let bytes_per_pixel: usize = if self.has_alpha() { 4 } else { 3 };
+        let row_length = self.width as usize * bytes_per_pixel;
+        let padding = (4 - row_length % 4) % 4;
+        let image_size = (row_length + padding) * self.height as usize;
+        let file_size = LENGTH_OF_BMP_HEADERS + image_size;
+
+        if file_size > u32::MAX as usize {
+
+            return Err(PngError::SizeOverflow);
+        }
+
+        let mut header: Vec<u8> = Vec::with_capacity(LENGTH_OF_BMP_HEADERS);
+
+        header.extend_from_slice(b"BM");
+        header.extend_from_slice(&(file_size as u32).to_le_bytes());
+        header.extend_from_slice(&[0; 4]);
+        header.extend_from_slice(&(LENGTH_OF_BMP_HEADERS as u32).to_le_bytes());
+
+        header.extend_from_slice(&40u32.to_le_bytes());
+        header.extend_from_slice(&(self.width as i32).to_le_bytes());
+        header.extend_from_slice(&(self.height as i32).to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&(bytes_per_pixel as u16 * 8).to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&(image_size as u32).to_le_bytes());
+        header.extend_from_slice(&BMP_PIXELS_PER_METER.to_le_bytes());
+        header.extend_from_slice(&BMP_PIXELS_PER_METER.to_le_bytes());
+        header.extend_from_slice(&[0; 8]);
+
+        writer.write_all(&header)?;
+
+        let mut row: Vec<u8> = Vec::with_capacity(row_length + padding);
+
+        for line in rgba.data.chunks_exact(rgba.stride()).rev() {
+
+            row.clear();
+
+            for pixel in line.chunks_exact(4) {
+
+                row.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+
+                if bytes_per_pixel == 4 {
+
+                    row.push(pixel[3]);
+                }
+            }
+
+            row.resize(row_length + padding, 0);
+            writer.write_all(&row)?;
+        }
+
+        Ok(())
+    }
+
+    /*
+        Writes the pixels to the file at @path in @format, replacing the file if there is one
+     */
+    pub fn export<P: AsRef<Path>> (&self, path: P, format: ExportFormat) -> Result<(), PngError> {
+
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        match format {
+
+            ExportFormat::Ppm => self.write_ppm(&mut writer)?,
+            ExportFormat::Pam => self.write_pam(&mut writer)?,
+            ExportFormat::Bmp => self.write_bmp(&mut writer)?,
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn bmp (image: &PixelBuffer) -> Vec<u8> {
+
+        let mut bytes: Vec<u8> = Vec::new();
+
+        image.write_bmp(&mut bytes).unwrap();
+
+        bytes
+    }
+
+    #[test]
+    fn bmp_rows_are_bottom_up_and_padded () {
+
+        /* 1 pixel rows are 3 bytes, padded with 1 */
+        let narrow = PixelBuffer::from_data(1, 2, COLOR_TYPE_TRUECOLOR, 8, vec![1, 2, 3, 4, 5, 6]).unwrap();
+        let bytes = bmp(&narrow);
+
+        assert_eq!(bytes.len(), LENGTH_OF_BMP_HEADERS + 8);
+        assert_eq!(&bytes[0 .. 6], [b'B', b'M', 62, 0, 0, 0]);
+        assert_eq!(&bytes[18 .. 30], [1, 0, 0, 0, 2, 0, 0, 0, 1, 0, 24, 0]);
+        assert_eq!(&bytes[34 .. 38], 8u32.to_le_bytes());
+        assert_eq!(&bytes[LENGTH_OF_BMP_HEADERS ..], [6, 5, 4, 0, 3, 2, 1, 0]);
+
+        /* 3 pixel rows are 9 bytes, padded with 3 */
+        let wide = PixelBuffer::from_data(3, 2, COLOR_TYPE_TRUECOLOR, 8, (1 .. 19).collect()).unwrap();
+
+        assert_eq!(&bmp(&wide)[LENGTH_OF_BMP_HEADERS ..], [12, 11, 10, 15, 14, 13, 18, 17, 16, 0, 0, 0, 3, 2, 1, 6, 5, 4, 9, 8, 7, 0, 0, 0]);
+
+        /* 32 bit rows need no padding, gray becomes BGR */
+        let gray_alpha = PixelBuffer::from_data(1, 2, COLOR_TYPE_GRAYSCALE_ALPHA, 8, vec![10, 20, 30, 40]).unwrap();
+        let bytes = bmp(&gray_alpha);
+
+        assert_eq!(&bytes[28 .. 30], 32u16.to_le_bytes());
+        assert_eq!(&bytes[LENGTH_OF_BMP_HEADERS ..], [30, 30, 30, 40, 10, 10, 10, 20]);
+    }
+
+    #[test]
+    fn ppm_and_pam_headers () {
+
+        let mut bytes: Vec<u8> = Vec::new();
+
+        PixelBuffer::from_data(2, 1, COLOR_TYPE_TRUECOLOR, 8, vec![1, 2, 3, 4, 5, 6]).unwrap().write_ppm(&mut bytes).unwrap();
+
+        assert_eq!(bytes, b"P6\n2 1\n255\n\x01\x02\x03\x04\x05\x06");
+
+        bytes.clear();
+        PixelBuffer::from_data(1, 1, COLOR_TYPE_GRAYSCALE, 16, vec![0x12, 0x34]).unwrap().write_ppm(&mut bytes).unwrap();
+
+        assert_eq!(bytes, b"P5\n1 1\n65535\n\x12\x34");
+
+        bytes.clear();
+        PixelBuffer::from_data(1, 1, COLOR_TYPE_TRUECOLOR_ALPHA, 8, vec![1, 2, 3, 4]).unwrap().write_pam(&mut bytes).unwrap();
+
+        assert_eq!(bytes, b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n\x01\x02\x03\x04");
+
+        let alpha = PixelBuffer::from_data(1, 1, COLOR_TYPE_GRAYSCALE_ALPHA, 8, vec![1, 2]).unwrap();
+
+        assert!(matches!(alpha.write_ppm(Vec::new()), Err(PngError::UnsupportedColorType(COLOR_TYPE_GRAYSCALE_ALPHA))));
+    }
+}
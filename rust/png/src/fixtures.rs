@@ -0,0 +1,73 @@
+/*
+    lib/rust/png/src/fixtures.rs
+    Q@khaa.pk
+ */
+
+/*
+    Small PNG files put together chunk by chunk for the unit tests, so no binary fixtures have to live in the repository.
+    IDAT holds a few placeholder bytes, nothing in this crate inflates it.
+ */
+
+use crate::constants::PNG_SIGNATURE;
+use crate::Chunk;
+
+/*
+    IHDR data of a @width by @height image, deflate compression, adaptive filtering, no interlacing
+ */
+pub fn ihdr (width: u32, height: u32, bit_depth: u8, color_type: u8) -> Vec<u8> {
+
+    let mut data: Vec<u8> = Vec::with_capacity(13);
+
+    data.extend_from_slice(&width.to_be_bytes());
+    data.extend_from_slice(&height.to_be_bytes());
+    data.extend_from_slice(&[bit_depth, color_type, 0, 0, 0]);
+
+    data
+}
+
+/*
+    File bytes of the signature followed by @chunks, each given as type and data, lengths and CRCs filled in
+ */
+pub fn file (chunks: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+
+    let mut bytes: Vec<u8> = PNG_SIGNATURE.to_vec();
+
+    for (type_name, data) in chunks.iter() {
+
+        bytes.extend_from_slice(&Chunk::from_parts(type_name, data.clone()).to_bytes());
+    }
+
+    bytes
+}
+
+/*
+    2 by 2 RGB image, IHDR, tEXt, IDAT and IEND
+ */
+pub fn rgb () -> Vec<u8> {
+
+    file(&[
+
+        (b"IHDR", ihdr(2, 2, 8, 2)),
+        (b"tEXt", b"Title\0Fixture".to_vec()),
+        (b"IDAT", vec![0x78, 0x01, 0x01, 0x02, 0x03]),
+        (b"IEND", Vec::new()),
+    ])
+}
+
+/*
+    rgb() with its image data split over two IDAT chunks and an empty IDAT put at @position among them (0 leading, 1 middle, 2 trailing),
+    and an empty private ancillary chunk ahead of the image data
+ */
+pub fn rgb_with_empty_idat (position: usize) -> Vec<u8> {
+
+    let mut idat: Vec<Vec<u8>> = vec![vec![0x78, 0x01], vec![0x01, 0x02, 0x03]];
+
+    idat.insert(position, Vec::new());
+
+    let mut chunks: Vec<(&[u8; 4], Vec<u8>)> = vec![(b"IHDR", ihdr(2, 2, 8, 2)), (b"tEXt", b"Title\0Fixture".to_vec()), (b"prVt", Vec::new())];
+
+    chunks.extend(idat.into_iter().map(|data| (b"IDAT", data)));
+    chunks.push((b"IEND", Vec::new()));
+
+    file(&chunks)
+}
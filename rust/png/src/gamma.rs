@@ -0,0 +1,196 @@
+/*
+    lib/rust/png/src/gamma.rs
+    Q@khaa.pk
+ */
+
+use crate::constants::COLOR_TYPE_INDEXED;
+use crate::error::PngError;
+use crate::pixels::PixelBuffer;
+use crate::Png;
+
+/*
+    gAMA stores the file gamma times 100000
+ */
+const GAMA_SCALE: f32 = 100000.0;
+
+/*
+    How samples of an image encode light, see Png::transfer_function()
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TransferFunction {
+
+    /* The piecewise sRGB curve, linear near black and a 2.4 power law above */
+    Srgb,
+    /* Pure power law with the file gamma as gAMA gives it, e.g. 0.45455, decoding raises samples to 1 / gamma */
+    Gamma(f32),
+}
+
+impl TransferFunction {
+
+    /*
+        Linear light of an encoded @value, both in 0.0 - 1.0
+     */
+    pub fn to_linear (&self, value: f32) -> f32 {
+
+        match *self {
+
+            TransferFunction::Srgb if value <= 0.04045 => value / 12.92,
+            TransferFunction::Srgb => ((value + 0.055) / 1.055).powf(2.4),
+            TransferFunction::Gamma(gamma) => value.powf(1.0 / gamma),
+        }
+    }
+
+    /*
+        Encoded value of linear light @value, the inverse of to_linear()
+     */
+    pub fn from_linear (&self, value: f32) -> f32 {
+
+        match *self {
+
+            TransferFunction::Srgb if value <= 0.0031308 => value * 12.92,
+            TransferFunction::Srgb => 1.055 * value.powf(1.0 / 2.4) - 0.055,
+            TransferFunction::Gamma(gamma) => value.powf(gamma),
+        }
+    }
+}
+
+impl Png {
+
+    /*
+        Transfer function the image declares, sRGB takes precedence over gAMA as the specification has it (iCCP is not interpreted).
+        @default when there is neither chunk, or gAMA is not 4 bytes long or zero.
+     */
+    pub fn transfer_function (&self, default: TransferFunction) -> TransferFunction {
+
+        if self.iter().any(|chunk| chunk.type_name == b"sRGB") {
+
+            return TransferFunction::Srgb;
+        }
+
+        match self.iter().find(|chunk| chunk.type_name == b"gAMA").map(|chunk| chunk.data()) {
+
+            Some(data) if data.len() == 4 && data != [0; 4] => TransferFunction::Gamma(u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as f32 / GAMA_SCALE),
+            _ => default,
+        }
+    }
+}
+
+impl PixelBuffer {
+
+    /*
+        Every sample in linear light, 0.0 - 1.0, channels interleaved as in the buffer. Alpha is already linear and is only scaled.
+        A lookup table with one entry per sample value is built first, so @transfer is evaluated 256 (or 65536) times rather than once per sample.
+        Indexed buffers give UnsupportedColorType, their samples are palette indices.
+     */
+    pub fn to_linear_f32 (&self, transfer: TransferFunction) -> Result<Vec<f32>, PngError> {
+
+        if self.color_type == COLOR_TYPE_INDEXED {
+
+            return Err(PngError::UnsupportedColorType(self.color_type));
+        }
+
+        let max = self.max_sample_value() as f32;
+        let table: Vec<f32> = (0 ..= self.max_sample_value()).map(|value| transfer.to_linear(value as f32 / max)).collect();
+
+        let channels = self.channels();
+        let sample_size = self.bytes_per_sample();
+
+        Ok((0 .. self.data.len() / sample_size).map(|index| {
+
+            let value = self.sample(index * sample_size);
+
+            if self.has_alpha() && index % channels == channels - 1 { value as f32 / max } else { table[value as usize] }
+
+        }).collect())
+    }
+
+    /*
+        Encodes linear light @samples (0.0 - 1.0, clamped, channels interleaved) with @transfer into a buffer, the inverse of to_linear_f32()
+     */
+    pub fn from_linear_f32 (samples: &[f32], width: u32, height: u32, color_type: u8, bit_depth: u8, transfer: TransferFunction) -> Result<PixelBuffer, PngError> {
+
+        if color_type == COLOR_TYPE_INDEXED {
+
+            return Err(PngError::UnsupportedColorType(color_type));
+        }
+
+        let mut output = PixelBuffer::new(width, height, color_type, bit_depth)?;
+
+        let channels = output.channels();
+        let sample_size = output.bytes_per_sample();
+        let max = output.max_sample_value() as f32;
+
+        if samples.len() != output.data.len() / sample_size {
+
+            return Err(PngError::BufferSizeMismatch { expected: output.data.len() / sample_size, found: samples.len() });
+        }
+
+        for (index, value) in samples.iter().enumerate() {
+
+            let value = value.clamp(0.0, 1.0);
+            let value = if output.has_alpha() && index % channels == channels - 1 { value } else { transfer.from_linear(value) };
+
+            output.set_sample(index * sample_size, (value * max).round() as u32);
+        }
+
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::constants::{COLOR_TYPE_GRAYSCALE, COLOR_TYPE_GRAYSCALE_ALPHA};
+    use crate::fixtures;
+
+    fn close (found: &[f32], expected: &[f32]) -> bool {
+
+        found.len() == expected.len() && found.iter().zip(expected.iter()).all(|(found, expected)| (found - expected).abs() < 1e-5)
+    }
+
+    #[test]
+    fn known_values_at_0_128_and_255 () {
+
+        let gray = PixelBuffer::from_data(3, 1, COLOR_TYPE_GRAYSCALE, 8, vec![0, 128, 255]).unwrap();
+
+        let srgb = gray.to_linear_f32(TransferFunction::Srgb).unwrap();
+        let gamma = gray.to_linear_f32(TransferFunction::Gamma(0.45455)).unwrap();
+
+        assert!(close(&srgb, &[0.0, 0.2158605, 1.0]), "{:?}", srgb);
+        assert!(close(&gamma, &[0.0, 0.219523, 1.0]), "{:?}", gamma);
+
+        for (linear, transfer) in [(srgb, TransferFunction::Srgb), (gamma, TransferFunction::Gamma(0.45455))] {
+
+            assert_eq!(PixelBuffer::from_linear_f32(&linear, 3, 1, COLOR_TYPE_GRAYSCALE, 8, transfer).unwrap().data, gray.data);
+        }
+
+        /* Alpha is scaled but not decoded */
+        let alpha = PixelBuffer::from_data(1, 1, COLOR_TYPE_GRAYSCALE_ALPHA, 8, vec![128, 128]).unwrap();
+
+        assert!(close(&alpha.to_linear_f32(TransferFunction::Srgb).unwrap(), &[0.2158605, 128.0 / 255.0]));
+    }
+
+    #[test]
+    fn srgb_takes_precedence_over_gama () {
+
+        let png_with = |chunks: &[(&[u8; 4], Vec<u8>)]| {
+
+            let mut all: Vec<(&[u8; 4], Vec<u8>)> = vec![(b"IHDR", fixtures::ihdr(1, 1, 8, 0))];
+
+            all.extend(chunks.iter().cloned());
+            all.push((b"IEND", Vec::new()));
+
+            Png::try_from(fixtures::file(&all)).unwrap()
+        };
+
+        let gama = (b"gAMA", 45455u32.to_be_bytes().to_vec());
+        let default = TransferFunction::Gamma(1.0);
+
+        assert_eq!(png_with(&[gama.clone(), (b"sRGB", vec![0])]).transfer_function(default), TransferFunction::Srgb);
+        assert_eq!(png_with(&[(b"sRGB", vec![0]), gama.clone()]).transfer_function(default), TransferFunction::Srgb);
+        assert_eq!(png_with(&[gama]).transfer_function(default), TransferFunction::Gamma(0.45455));
+        assert_eq!(png_with(&[(b"gAMA", vec![0; 4])]).transfer_function(default), default);
+        assert_eq!(png_with(&[]).transfer_function(TransferFunction::Srgb), TransferFunction::Srgb);
+    }
+}
@@ -0,0 +1,150 @@
+/*
+    lib/rust/png/src/hash.rs
+    Q@khaa.pk
+ */
+
+use crate::constants::{COLOR_TYPE_GRAYSCALE, COLOR_TYPE_INDEXED};
+use crate::error::PngError;
+use crate::pixels::PixelBuffer;
+
+/*
+    Side of the square the image is shrunk to, one bit of the hash per pixel (per pair of neighbors for dHash)
+ */
+const HASH_SIDE: u32 = 8;
+
+/*
+    Which perceptual hash PixelBuffer::perceptual_hash() computes
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HashKind {
+
+    /* aHash, a bit is set for each of the 8x8 pixels brighter than their mean */
+    Average,
+    /* dHash, from 9x8 pixels a bit is set for each pixel brighter than its right neighbor */
+    Difference,
+}
+
+/*
+    Number of bits two hashes differ in, small distances (under about 10 of 64) mean visually similar images
+ */
+pub fn hamming_distance (a: u64, b: u64) -> u32 {
+
+    (a ^ b).count_ones()
+}
+
+impl PixelBuffer {
+
+    /*
+        64 bit perceptual hash of the image for finding duplicates, bits packed row by row from the top left, first bit the most significant.
+        The image is turned into 8 bit luma (Rec. 601 weights, alpha ignored) and shrunk with resize_bilinear() first,
+        so a gray image and an RGB or RGBA image of the same content hash identically whatever their bit depth.
+        Indexed buffers give UnsupportedColorType, expand_palette() them first. An image without pixels gives EmptyImage.
+     */
+    pub fn perceptual_hash (&self, kind: HashKind) -> Result<u64, PngError> {
+
+        if self.color_type == COLOR_TYPE_INDEXED {
+
+            return Err(PngError::UnsupportedColorType(self.color_type));
+        }
+
+        if self.width == 0 || self.height == 0 {
+
+            return Err(PngError::EmptyImage);
+        }
+
+        let rgba = self.to_rgba()?.convert_bit_depth(8)?;
+        let luma: Vec<u8> = rgba.data.chunks_exact(4).map(|pixel| ((pixel[0] as u32 * 299 + pixel[1] as u32 * 587 + pixel[2] as u32 * 114 + 500) / 1000) as u8).collect();
+        let gray = PixelBuffer::from_data(self.width, self.height, COLOR_TYPE_GRAYSCALE, 8, luma)?;
+
+        let mut hash: u64 = 0;
+
+        match kind {
+
+            HashKind::Average => {
+
+                let small = gray.resize_bilinear(HASH_SIDE, HASH_SIDE, false);
+                let sum: u32 = small.data.iter().map(|value| *value as u32).sum();
+
+                for value in small.data.iter() {
+
+                    hash = (hash << 1) | ((*value as u32 * small.data.len() as u32 > sum) as u64);
+                }
+            },
+            HashKind::Difference => {
+
+                let small = gray.resize_bilinear(HASH_SIDE + 1, HASH_SIDE, false);
+
+                for row in small.data.chunks_exact(small.stride()) {
+
+                    for pair in row.windows(2) {
+
+                        hash = (hash << 1) | ((pair[0] > pair[1]) as u64);
+                    }
+                }
+            },
+        }
+
+        Ok(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::constants::COLOR_TYPE_TRUECOLOR;
+
+    /*
+        16x16 RGB image of 256 distinct colors, red jumping about along rows and green growing downwards, few enough for a palette
+     */
+    fn gradient () -> PixelBuffer {
+
+        let data = (0 .. 16u8).flat_map(|y| (0 .. 16u8).flat_map(move |x| [x * 7 % 16 * 16, y * 16, 0x80])).collect();
+
+        PixelBuffer::from_data(16, 16, COLOR_TYPE_TRUECOLOR, 8, data).unwrap()
+    }
+
+    #[test]
+    fn rgb_and_palette_versions_hash_equally () {
+
+        let rgb = gradient();
+        let (indexed, palette, trns) = rgb.to_indexed().unwrap();
+
+        assert_eq!(palette.len(), 256 * 3);
+
+        let expanded = indexed.expand_palette(&palette, &trns).unwrap();
+
+        for kind in [HashKind::Average, HashKind::Difference] {
+
+            let hash = rgb.perceptual_hash(kind).unwrap();
+
+            assert!(hash != 0 && hash != u64::MAX);
+            assert_eq!(expanded.perceptual_hash(kind).unwrap(), hash);
+            assert!(matches!(indexed.perceptual_hash(kind), Err(PngError::UnsupportedColorType(COLOR_TYPE_INDEXED))));
+        }
+    }
+
+    #[test]
+    fn gray_rgb_and_16_bit_versions_hash_equally () {
+
+        let gray = PixelBuffer::from_data(4, 4, COLOR_TYPE_GRAYSCALE, 8, (0 .. 16).map(|value| value * 16).collect()).unwrap();
+        let rgb = PixelBuffer::from_data(4, 4, COLOR_TYPE_TRUECOLOR, 8, gray.data.iter().flat_map(|value| [*value; 3]).collect()).unwrap();
+        let wide = gray.convert_bit_depth(16).unwrap();
+
+        for kind in [HashKind::Average, HashKind::Difference] {
+
+            let hash = gray.perceptual_hash(kind).unwrap();
+
+            assert_eq!(rgb.perceptual_hash(kind).unwrap(), hash);
+            assert_eq!(wide.perceptual_hash(kind).unwrap(), hash);
+        }
+    }
+
+    #[test]
+    fn hamming_distance_counts_differing_bits () {
+
+        assert_eq!(hamming_distance(0, 0), 0);
+        assert_eq!(hamming_distance(0b1011, 0b0110), 3);
+        assert_eq!(hamming_distance(0, u64::MAX), 64);
+    }
+}
@@ -0,0 +1,1048 @@
+/*
+    lib/rust/png/src/images.rs
+    Q@khaa.pk
+ */
+
+use std::ops::Range;
+
+use rand::Rng;
+
+use crate::Png;
+use crate::constants::COLOR_TYPE_INDEXED;
+use crate::error::PngError;
+use crate::pixels::{PixelBuffer, channels_of_color_type};
+
+/*
+    sample_blocks() redraws a target block that overlaps the context block at most this many times before giving up
+ */
+const MAX_BLOCK_SAMPLING_ATTEMPTS: usize = 100;
+
+/*
+    Layout transposes move this many pixels at a time, one channel after another, so the reads of a block stay in cache while each output plane is written sequentially
+ */
+const TRANSPOSE_BLOCK_SIZE: usize = 4096;
+
+/*
+    Order in which the dimensions of an image tensor are laid out in memory.
+    HWC is how PNG (and PixelBuffer) stores pixels, channels interleaved. CHW keeps each channel as a plane of its own.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ImageDataTensorShapeFormat {
+
+    CHW,
+    HWC,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ImageDataTensorShape {
+
+    pub channels: usize,
+    pub height: usize,
+    pub width: usize,
+}
+
+impl ImageDataTensorShape {
+
+    pub fn new (channels: usize, height: usize, width: usize) -> Self {
+
+        Self {
+
+            channels,
+            height,
+            width,
+        }
+    }
+
+    /*
+        Number of elements in a tensor of this shape, channels * height * width
+     */
+    pub fn len (&self) -> usize {
+
+        self.channels * self.height * self.width
+    }
+
+    pub fn is_empty (&self) -> bool {
+
+        self.len() == 0
+    }
+
+    /*
+        Shape of the pixels of @png as given by its IHDR chunk, palette images count as 3 channels since their indices expand to RGB
+     */
+    pub fn from_png (png: &Png) -> Result<Self, PngError> {
+
+        png.ihdr()?;
+
+        let (width, height) = png.dimensions().ok_or(PngError::MissingChunk("IHDR"))?;
+        let color_type = png.color_type().ok_or(PngError::MissingChunk("IHDR"))?;
+
+        let channels = if color_type == COLOR_TYPE_INDEXED { 3 } else { channels_of_color_type(color_type)? };
+
+        Ok(Self::new(channels, height as usize, width as usize))
+    }
+
+    /*
+        Checks @data holds exactly one sample per element of this shape before it is handed to block extraction or tensor conversion
+     */
+    pub fn validate_buffer (&self, data: &[u8]) -> Result<(), PngError> {
+
+        check_tensor_length(data.len(), self)
+    }
+}
+
+/*
+    A rectangular block of an image, in pixels
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ImageBlock {
+
+    pub height: usize,
+    pub width: usize,
+}
+
+impl ImageBlock {
+
+    pub fn new (height: usize, width: usize) -> Self {
+
+        Self {
+
+            height,
+            width,
+        }
+    }
+
+    /*
+        Number of pixels in the block
+     */
+    pub fn get_size (&self) -> usize {
+
+        self.height * self.width
+    }
+
+    /*
+        Byte range of block number @block_number when a flat buffer of @buffer_len bytes is cut into consecutive blocks of get_size() pixels with @channels channels.
+        The whole range has to fit in the buffer, so consecutive block numbers give ranges that cover it without gaps or overlaps.
+     */
+    pub fn slice_range (&self, block_number: usize, channels: usize, buffer_len: usize) -> Result<Range<usize>, PngError> {
+
+        let length = self.get_size().checked_mul(channels);
+        let start = length.and_then(|length| length.checked_mul(block_number));
+        let end = start.zip(length).and_then(|(start, length)| start.checked_add(length));
+
+        match (start, end) {
+
+            (Some(start), Some(end)) if end <= buffer_len => Ok(start .. end),
+            _ => Err(PngError::SliceOutOfBounds { block_number, buffer_len }),
+        }
+    }
+}
+
+/*
+    What PixelBuffer::patches() does with the partial patches along the right and bottom edges when the image isn't a multiple of the patch size
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PatchEdge {
+
+    Drop,
+    ZeroPad,
+}
+
+/*
+    One patch of the grid, @data has its pixels row by row (HWC)
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub struct Patch {
+
+    pub grid_x: u32,
+    pub grid_y: u32,
+    pub data: Vec<u8>,
+}
+
+/*
+    Iterator over the patch grid of a PixelBuffer in row-major order, see PixelBuffer::patches()
+ */
+pub struct Patches<'a> {
+
+    buffer: &'a PixelBuffer,
+    patch_width: u32,
+    patch_height: u32,
+    columns: u32,
+    rows: u32,
+    /* Patch count and position are u64, columns * rows can pass u32::MAX with small patches on a large image */
+    next: u64,
+}
+
+impl<'a> Patches<'a> {
+
+    fn total (&self) -> u64 {
+
+        self.columns as u64 * self.rows as u64
+    }
+}
+
+impl<'a> Iterator for Patches<'a> {
+
+    type Item = Patch;
+
+    fn next (&mut self) -> Option<Patch> {
+
+        if self.next >= self.total() {
+
+            return None;
+        }
+
+        let grid_x = (self.next % self.columns as u64) as u32;
+        let grid_y = (self.next / self.columns as u64) as u32;
+
+        self.next += 1;
+
+        let bytes_per_pixel = self.buffer.bytes_per_pixel();
+        let stride = self.buffer.stride();
+        let x = grid_x as usize * self.patch_width as usize;
+        let y = grid_y as usize * self.patch_height as usize;
+        let row_length = self.patch_width as usize * bytes_per_pixel;
+
+        /* Zero filled so whatever lies past the image edges is padding */
+        let mut data = vec![0; self.patch_height as usize * row_length];
+
+        let rows = (self.patch_height as usize).min(self.buffer.height as usize - y);
+        let copied = (self.patch_width as usize).min(self.buffer.width as usize - x) * bytes_per_pixel;
+
+        for row in 0 .. rows {
+
+            let start = (y + row) * stride + x * bytes_per_pixel;
+
+            data[row * row_length .. row * row_length + copied].copy_from_slice(&self.buffer.data[start .. start + copied]);
+        }
+
+        Some(Patch { grid_x, grid_y, data })
+    }
+
+    fn size_hint (&self) -> (usize, Option<usize>) {
+
+        let remaining = usize::try_from(self.total() - self.next).unwrap_or(usize::MAX);
+
+        (remaining, Some(remaining))
+    }
+}
+
+impl<'a> ExactSizeIterator for Patches<'a> {}
+
+/*
+    How an image is cut into context and target blocks for JEPA training.
+    @aspect_ratio (width / height) is the fixed ratio block_height(), block_width() and block_size() work with.
+    Scales are fractions of the image area and @aspect_ratio_range bounds the ratios sample_blocks() draws, each an inclusive (min, max) range.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BlockConfig {
+
+    pub context_blocks: usize,
+    pub target_blocks: usize,
+    pub aspect_ratio: f64,
+    pub context_scale: (f64, f64),
+    pub target_scale: (f64, f64),
+    pub aspect_ratio_range: (f64, f64),
+    /* Keep target blocks clear of the context block */
+    pub avoid_overlap: bool,
+    /* Side of the square patches BlockSample::mask() is aligned to */
+    pub patch_size: usize,
+}
+
+impl Default for BlockConfig {
+
+    /*
+        One context block and four target blocks with the scales and aspect ratios of I-JEPA, square blocks and 16 pixel patches
+     */
+    fn default () -> Self {
+
+        Self {
+
+            context_blocks: 1,
+            target_blocks: 4,
+            aspect_ratio: 1.0,
+            context_scale: (0.85, 1.0),
+            target_scale: (0.15, 0.2),
+            aspect_ratio_range: (0.75, 1.5),
+            avoid_overlap: true,
+            patch_size: 16,
+        }
+    }
+}
+
+/*
+    Pixel area each block gets when an image of @input_len samples with @channels channels is shared out between all context and target blocks
+ */
+fn block_area (input_len: usize, channels: usize, cfg: &BlockConfig) -> Result<f64, PngError> {
+
+    if channels == 0 {
+
+        return Err(PngError::InvalidBlockGeometry("channels must be greater than zero"));
+    }
+
+    if input_len < channels {
+
+        return Err(PngError::InvalidBlockGeometry("input must hold at least one pixel"));
+    }
+
+    if cfg.context_blocks + cfg.target_blocks == 0 {
+
+        return Err(PngError::InvalidBlockGeometry("at least one context or target block is needed"));
+    }
+
+    if !(cfg.aspect_ratio > 0.0 && cfg.aspect_ratio.is_finite()) {
+
+        return Err(PngError::InvalidBlockGeometry("aspect ratio must be a positive finite number"));
+    }
+
+    Ok((input_len / channels) as f64 / (cfg.context_blocks + cfg.target_blocks) as f64)
+}
+
+/*
+    Height in pixels of a block with the configured aspect ratio, area = width * height and width = height * cfg.aspect_ratio
+ */
+pub fn block_height (input_len: usize, channels: usize, cfg: &BlockConfig) -> Result<f64, PngError> {
+
+    Ok((block_area(input_len, channels, cfg)? / cfg.aspect_ratio).sqrt())
+}
+
+pub fn block_width (input_len: usize, channels: usize, cfg: &BlockConfig) -> Result<f64, PngError> {
+
+    Ok(block_height(input_len, channels, cfg)? * cfg.aspect_ratio)
+}
+
+/*
+    Number of pixels in a block, the f64 area rounded once
+ */
+pub fn block_size (input_len: usize, channels: usize, cfg: &BlockConfig) -> Result<usize, PngError> {
+
+    Ok((block_height(input_len, channels, cfg)? * block_width(input_len, channels, cfg)?).round() as usize)
+}
+
+/*
+    A rectangle of an image, in pixels
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BlockRect {
+
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl BlockRect {
+
+    pub fn overlaps (&self, other: &BlockRect) -> bool {
+
+        self.x < other.x + other.width && other.x < self.x + self.width && self.y < other.y + other.height && other.y < self.y + self.height
+    }
+}
+
+/*
+    Pixels of a list of blocks, one Vec per block
+ */
+pub type BlockPixels = Vec<Vec<u8>>;
+
+/*
+    The context blocks and the target blocks to predict from them, all inside an image of @shape
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub struct BlockSample {
+
+    pub shape: ImageDataTensorShape,
+    pub patch_size: usize,
+    pub contexts: Vec<BlockRect>,
+    pub targets: Vec<BlockRect>,
+}
+
+impl BlockSample {
+
+    /*
+        Pixels of each context block and of each target block, every block row by row (HWC)
+     */
+    pub fn extract (&self, buffer: &PixelBuffer) -> Result<(BlockPixels, BlockPixels), PngError> {
+
+        let pixels_of = |blocks: &[BlockRect]| -> Result<BlockPixels, PngError> {
+
+            blocks.iter().map(|block| extract_block(buffer, &ImageBlock::new(block.height, block.width), block.x as u32, block.y as u32)).collect()
+        };
+
+        Ok((pixels_of(&self.contexts)?, pixels_of(&self.targets)?))
+    }
+
+    /*
+        One entry per patch_size * patch_size patch of the image in row-major order, true where the patch touches a target block.
+        Partial patches at the right and bottom edges are counted too.
+     */
+    pub fn mask (&self) -> Vec<bool> {
+
+        if self.patch_size == 0 {
+
+            return Vec::new();
+        }
+
+        let columns = self.shape.width.div_ceil(self.patch_size);
+        let rows = self.shape.height.div_ceil(self.patch_size);
+
+        (0 .. columns * rows).map(|index| {
+
+            let patch = BlockRect { x: (index % columns) * self.patch_size, y: (index / columns) * self.patch_size, width: self.patch_size, height: self.patch_size };
+
+            self.targets.iter().any(|target| target.overlaps(&patch))
+
+        }).collect()
+    }
+}
+
+/*
+    A block covering a random fraction of the image area from @scale with a random aspect ratio from @aspect_ratio, placed anywhere it fits
+ */
+fn sample_block (shape: &ImageDataTensorShape, scale: (f64, f64), aspect_ratio: (f64, f64), rng: &mut impl Rng) -> BlockRect {
+
+    let area = (scale.0 + (scale.1 - scale.0) * rng.gen::<f64>()) * (shape.height * shape.width) as f64;
+    let ratio = aspect_ratio.0 + (aspect_ratio.1 - aspect_ratio.0) * rng.gen::<f64>();
+
+    let width = ((area * ratio).sqrt().round() as usize).clamp(1, shape.width);
+    let height = ((area / ratio).sqrt().round() as usize).clamp(1, shape.height);
+
+    let x = ((rng.gen::<f64>() * (shape.width - width + 1) as f64) as usize).min(shape.width - width);
+    let y = ((rng.gen::<f64>() * (shape.height - height + 1) as f64) as usize).min(shape.height - height);
+
+    BlockRect { x, y, width, height }
+}
+
+/*
+    Draws cfg.context_blocks context blocks and cfg.target_blocks target blocks, every block clamped to lie completely inside the image.
+    With cfg.avoid_overlap a target that lands on any context block is drawn again, up to MAX_BLOCK_SAMPLING_ATTEMPTS times.
+    The same seeded @rng gives the same blocks.
+ */
+pub fn sample_blocks (shape: ImageDataTensorShape, cfg: &BlockConfig, rng: &mut impl Rng) -> Result<BlockSample, PngError> {
+
+    if shape.height == 0 || shape.width == 0 {
+
+        return Err(PngError::EmptyImage);
+    }
+
+    let contexts: Vec<BlockRect> = (0 .. cfg.context_blocks).map(|_| sample_block(&shape, cfg.context_scale, cfg.aspect_ratio_range, rng)).collect();
+    let mut targets: Vec<BlockRect> = Vec::with_capacity(cfg.target_blocks);
+
+    for _ in 0 .. cfg.target_blocks {
+
+        let mut attempts: usize = 0;
+
+        loop {
+
+            let target = sample_block(&shape, cfg.target_scale, cfg.aspect_ratio_range, rng);
+
+            if !cfg.avoid_overlap || !contexts.iter().any(|context| target.overlaps(context)) {
+
+                targets.push(target);
+
+                break;
+            }
+
+            attempts += 1;
+
+            if attempts == MAX_BLOCK_SAMPLING_ATTEMPTS {
+
+                return Err(PngError::BlockSamplingFailed);
+            }
+        }
+    }
+
+    Ok(BlockSample {
+
+        shape,
+        patch_size: cfg.patch_size,
+        contexts,
+        targets,
+    })
+}
+
+fn check_tensor_length (length: usize, shape: &ImageDataTensorShape) -> Result<(), PngError> {
+
+    if length != shape.len() {
+
+        return Err(PngError::BufferSizeMismatch { expected: shape.len(), found: length });
+    }
+
+    Ok(())
+}
+
+/*
+    HWC is a (height * width) by channels matrix and CHW its transpose, @to_chw picks the direction
+ */
+fn transpose_layout<T: Copy + Default> (data: &[T], shape: &ImageDataTensorShape, to_chw: bool) -> Result<Vec<T>, PngError> {
+
+    check_tensor_length(data.len(), shape)?;
+
+    let channels = shape.channels;
+    let plane = shape.height * shape.width;
+    let mut output = vec![T::default(); data.len()];
+
+    let mut block_start: usize = 0;
+
+    while block_start < plane {
+
+        let block_end = (block_start + TRANSPOSE_BLOCK_SIZE).min(plane);
+
+        for channel in 0 .. channels {
+
+            for pixel in block_start .. block_end {
+
+                if to_chw {
+
+                    output[channel * plane + pixel] = data[pixel * channels + channel];
+                }
+                else {
+
+                    output[pixel * channels + channel] = data[channel * plane + pixel];
+                }
+            }
+        }
+
+        block_start = block_end;
+    }
+
+    Ok(output)
+}
+
+/*
+    Interleaved (HWC) samples to planar (CHW), @data must hold exactly shape.len() elements
+ */
+pub fn hwc_to_chw (data: &[u8], shape: ImageDataTensorShape) -> Result<Vec<u8>, PngError> {
+
+    transpose_layout(data, &shape, true)
+}
+
+/*
+    Planar (CHW) samples to interleaved (HWC), the inverse of hwc_to_chw()
+ */
+pub fn chw_to_hwc (data: &[u8], shape: ImageDataTensorShape) -> Result<Vec<u8>, PngError> {
+
+    transpose_layout(data, &shape, false)
+}
+
+pub fn hwc_to_chw_f32 (data: &[f32], shape: ImageDataTensorShape) -> Result<Vec<f32>, PngError> {
+
+    transpose_layout(data, &shape, true)
+}
+
+pub fn chw_to_hwc_f32 (data: &[f32], shape: ImageDataTensorShape) -> Result<Vec<f32>, PngError> {
+
+    transpose_layout(data, &shape, false)
+}
+
+/*
+    Copies the pixels of @block placed with its top left corner at (@origin_x, @origin_y) out of @buffer, row by row into a contiguous HWC vector.
+    The whole block has to lie inside the image.
+ */
+pub fn extract_block (buffer: &PixelBuffer, block: &ImageBlock, origin_x: u32, origin_y: u32) -> Result<Vec<u8>, PngError> {
+
+    let (x, y) = (origin_x as usize, origin_y as usize);
+
+    if x + block.width > buffer.width as usize || y + block.height > buffer.height as usize {
+
+        return Err(PngError::BlockOutOfBounds { x: origin_x, y: origin_y, width: block.width, height: block.height });
+    }
+
+    let stride = buffer.stride();
+    let bytes_per_pixel = buffer.bytes_per_pixel();
+    let row_length = block.width * bytes_per_pixel;
+    let mut pixels: Vec<u8> = Vec::with_capacity(block.height * row_length);
+
+    for row in y .. y + block.height {
+
+        let start = row * stride + x * bytes_per_pixel;
+
+        pixels.extend_from_slice(&buffer.data[start .. start + row_length]);
+    }
+
+    Ok(pixels)
+}
+
+/*
+    Cuts the image into cfg.context_blocks + cfg.target_blocks vertical columns of equal width and copies out column @block_index,
+    block_width * channels bytes from each row at that row's offset, into a contiguous HWC vector.
+    When the width doesn't divide evenly the columns left over at the right edge belong to no block.
+ */
+pub fn extract_vertical_block (buffer: &PixelBuffer, block_index: usize, cfg: &BlockConfig) -> Result<Vec<u8>, PngError> {
+
+    let blocks = cfg.context_blocks + cfg.target_blocks;
+
+    if blocks == 0 {
+
+        return Err(PngError::InvalidBlockGeometry("at least one context or target block is needed"));
+    }
+
+    let block_width = buffer.width as usize / blocks;
+
+    if block_index >= blocks || block_width == 0 {
+
+        return Err(PngError::BlockOutOfBounds { x: (block_index * block_width) as u32, y: 0, width: block_width, height: buffer.height as usize });
+    }
+
+    extract_block(buffer, &ImageBlock::new(buffer.height as usize, block_width), (block_index * block_width) as u32, 0)
+}
+
+/*
+    Same block as extract_block() but laid out CHW, one plane per channel, for tensor consumers.
+    Samples stay as they are in the buffer (whole bytes, 16 bit samples as two big-endian bytes).
+ */
+pub fn extract_block_chw (buffer: &PixelBuffer, block: &ImageBlock, origin_x: u32, origin_y: u32) -> Result<Vec<u8>, PngError> {
+
+    let pixels = extract_block(buffer, block, origin_x, origin_y)?;
+
+    if buffer.bit_depth == 16 {
+
+        let samples: Vec<u16> = pixels.chunks_exact(2).map(|sample| u16::from_be_bytes([sample[0], sample[1]])).collect();
+        let planar = transpose_layout(&samples, &ImageDataTensorShape::new(buffer.channels(), block.height, block.width), true)?;
+
+        return Ok(planar.iter().flat_map(|sample| sample.to_be_bytes()).collect());
+    }
+
+    hwc_to_chw(&pixels, ImageDataTensorShape::new(buffer.channels(), block.height, block.width))
+}
+
+/*
+    Channel of element @index of a tensor of @shape laid out as @format
+ */
+fn channel_of_element (index: usize, shape: &ImageDataTensorShape, format: ImageDataTensorShapeFormat) -> usize {
+
+    match format {
+
+        ImageDataTensorShapeFormat::CHW => index / (shape.height * shape.width),
+        ImageDataTensorShapeFormat::HWC => index % shape.channels,
+    }
+}
+
+/*
+    Per channel standardization, x = (x - mean[c]) / std[c], for tensors already scaled to 0.0 - 1.0 (ImageNet style preprocessing).
+    @mean and @std need one entry per channel and no std can be zero.
+ */
+pub fn normalize (tensor: &mut [f32], shape: ImageDataTensorShape, format: ImageDataTensorShapeFormat, mean: &[f32], std: &[f32]) -> Result<(), PngError> {
+
+    check_tensor_length(tensor.len(), &shape)?;
+
+    for statistics in [mean, std] {
+
+        if statistics.len() != shape.channels {
+
+            return Err(PngError::ChannelCountMismatch { expected: shape.channels, found: statistics.len() });
+        }
+    }
+
+    if let Some(channel) = std.iter().position(|deviation| *deviation == 0.0) {
+
+        return Err(PngError::ZeroStandardDeviation(channel));
+    }
+
+    for (index, value) in tensor.iter_mut().enumerate() {
+
+        let channel = channel_of_element(index, &shape, format);
+
+        *value = (*value - mean[channel]) / std[channel];
+    }
+
+    Ok(())
+}
+
+/*
+    Mean and (population) standard deviation of every channel over all the tensors of @batch, each of @shape and laid out as @format.
+    Sums are accumulated in f64 so large datasets don't lose precision.
+ */
+pub fn compute_mean_std (batch: &[&[f32]], shape: ImageDataTensorShape, format: ImageDataTensorShapeFormat) -> Result<(Vec<f32>, Vec<f32>), PngError> {
+
+    let mut sums = vec![0f64; shape.channels];
+    let mut squares = vec![0f64; shape.channels];
+
+    for tensor in batch.iter() {
+
+        check_tensor_length(tensor.len(), &shape)?;
+
+        for (index, value) in tensor.iter().enumerate() {
+
+            let channel = channel_of_element(index, &shape, format);
+
+            sums[channel] += *value as f64;
+            squares[channel] += (*value as f64) * (*value as f64);
+        }
+    }
+
+    let count = (batch.len() * shape.height * shape.width) as f64;
+
+    if count == 0.0 {
+
+        return Err(PngError::EmptyImage);
+    }
+
+    let mean: Vec<f32> = sums.iter().map(|sum| (sum / count) as f32).collect();
+    let std: Vec<f32> = sums.iter().zip(squares.iter()).map(|(sum, square)| (square / count - (sum / count) * (sum / count)).max(0.0).sqrt() as f32).collect();
+
+    Ok((mean, std))
+}
+
+impl PixelBuffer {
+
+    /*
+        Samples of the image as f32 in the range 0.0 to 1.0 (divided by 255, or 65535 for 16 bit buffers), laid out as @format.
+        In CHW layout the sample of channel c of the pixel at (x, y) is at c * H * W + y * W + x, in HWC layout at (y * W + x) * C + c.
+        Palette indices are scaled like gray samples, expand an indexed image first to get its colors.
+     */
+    pub fn to_tensor (&self, format: ImageDataTensorShapeFormat) -> (Vec<f32>, ImageDataTensorShape) {
+
+        let shape = ImageDataTensorShape::new(self.channels(), self.height as usize, self.width as usize);
+        let sample_size = self.bytes_per_sample();
+        let max = self.max_sample_value() as f32;
+        let plane = shape.height * shape.width;
+
+        let mut tensor = vec![0f32; shape.len()];
+
+        for (index, sample) in self.data.chunks_exact(sample_size).enumerate() {
+
+            let value = if sample_size == 2 { u16::from_be_bytes([sample[0], sample[1]]) as f32 } else { sample[0] as f32 } / max;
+
+            let position = match format {
+
+                ImageDataTensorShapeFormat::HWC => index,
+                ImageDataTensorShapeFormat::CHW => (index % shape.channels) * plane + index / shape.channels,
+            };
+
+            tensor[position] = value;
+        }
+
+        (tensor, shape)
+    }
+
+    /*
+        Splits the image into a grid of @patch_width by @patch_height patches (ViT style), each patch a contiguous copy of its pixels.
+        @edge decides if partial patches at the right and bottom edges are dropped or zero padded to full size.
+        The iterator knows its length up front so a num_patches * patch_dim tensor can be allocated before iterating.
+     */
+    pub fn patches (&self, patch_width: u32, patch_height: u32, edge: PatchEdge) -> Patches<'_> {
+
+        let (columns, rows) = if patch_width == 0 || patch_height == 0 {
+
+            (0, 0)
+        }
+        else {
+
+            match edge {
+
+                PatchEdge::Drop => (self.width / patch_width, self.height / patch_height),
+                PatchEdge::ZeroPad => (self.width.div_ceil(patch_width), self.height.div_ceil(patch_height)),
+            }
+        };
+
+        Patches {
+
+            buffer: self,
+            patch_width,
+            patch_height,
+            columns,
+            rows,
+            next: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::constants::{COLOR_TYPE_GRAYSCALE, COLOR_TYPE_TRUECOLOR};
+    use crate::fixtures;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn sample_blocks_draws_every_configured_context_block () {
+
+        let shape = ImageDataTensorShape::new(3, 48, 64);
+        let inside = |block: &BlockRect| block.width > 0 && block.height > 0 && block.x + block.width <= shape.width && block.y + block.height <= shape.height;
+
+        for context_blocks in [0, 1, 3] {
+
+            let cfg = BlockConfig { context_blocks, target_blocks: 2, context_scale: (0.05, 0.1), ..BlockConfig::default() };
+            let sample = sample_blocks(shape, &cfg, &mut StdRng::seed_from_u64(7)).unwrap();
+
+            assert_eq!(sample.contexts.len(), context_blocks);
+            assert_eq!(sample.targets.len(), 2);
+            assert!(sample.contexts.iter().chain(sample.targets.iter()).all(inside));
+            assert!(sample.targets.iter().all(|target| sample.contexts.iter().all(|context| !target.overlaps(context))));
+            assert_eq!(sample, sample_blocks(shape, &cfg, &mut StdRng::seed_from_u64(7)).unwrap());
+        }
+    }
+
+    #[test]
+    fn sample_blocks_clamps_oversized_blocks_to_the_image () {
+
+        let shape = ImageDataTensorShape::new(1, 4, 6);
+        let cfg = BlockConfig { context_blocks: 2, target_blocks: 0, context_scale: (4.0, 4.0), aspect_ratio_range: (3.0, 3.0), ..BlockConfig::default() };
+        let sample = sample_blocks(shape, &cfg, &mut StdRng::seed_from_u64(1)).unwrap();
+
+        assert!(sample.contexts.iter().all(|context| *context == BlockRect { x: 0, y: 0, width: 6, height: 4 }));
+
+        let buffer = PixelBuffer::new(6, 4, COLOR_TYPE_GRAYSCALE, 8).unwrap();
+        let (contexts, targets) = sample.extract(&buffer).unwrap();
+
+        assert_eq!(contexts.iter().map(|pixels| pixels.len()).collect::<Vec<usize>>(), vec![24, 24]);
+        assert!(targets.is_empty());
+    }
+
+    #[test]
+    fn slice_ranges_cover_the_buffer_without_gaps_or_overlaps () {
+
+        for (height, width) in [(1, 1), (2, 3), (4, 4), (7, 5)] {
+
+            for channels in 1 .. 5 {
+
+                let block = ImageBlock::new(height, width);
+                let length = height * width * channels;
+
+                for buffer_len in [0, length - 1, length, length * 3, length * 3 + 1, length * 10 + length / 2] {
+
+                    let ranges: Vec<Range<usize>> = (0 ..).map_while(|block_number| block.slice_range(block_number, channels, buffer_len).ok()).collect();
+
+                    assert_eq!(ranges.len(), buffer_len / length);
+
+                    let mut covered: usize = 0;
+
+                    for range in ranges.iter() {
+
+                        assert_eq!(range.start, covered, "gap or overlap before {:?}", range);
+                        assert_eq!(range.len(), length);
+
+                        covered = range.end;
+                    }
+
+                    /* Whatever is left over is shorter than a block and belongs to none */
+                    assert!(buffer_len - covered < length);
+                    assert!(block.slice_range(ranges.len(), channels, buffer_len).is_err());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn slice_range_refuses_overflowing_block_numbers () {
+
+        assert!(ImageBlock::new(16, 16).slice_range(usize::MAX / 2, 4, usize::MAX).is_err());
+    }
+
+    #[test]
+    fn to_tensor_puts_each_sample_at_its_index () {
+
+        let (width, height, channels) = (3, 2, 3);
+        let buffer = PixelBuffer::from_data(width, height, COLOR_TYPE_TRUECOLOR, 8, (0 .. 18).map(|index| index * 10).collect()).unwrap();
+
+        let (chw, shape) = buffer.to_tensor(ImageDataTensorShapeFormat::CHW);
+        let (hwc, _) = buffer.to_tensor(ImageDataTensorShapeFormat::HWC);
+
+        assert_eq!(shape, ImageDataTensorShape::new(channels, height as usize, width as usize));
+
+        for c in 0 .. channels {
+
+            for y in 0 .. height as usize {
+
+                for x in 0 .. width as usize {
+
+                    let source = buffer.data[(y * width as usize + x) * channels + c] as f32 / 255.0;
+
+                    assert_eq!(chw[c * shape.height * shape.width + y * shape.width + x], source, "[{}][{}][{}]", c, y, x);
+                    assert_eq!(hwc[(y * shape.width + x) * channels + c], source, "[{}][{}][{}]", y, x, c);
+                }
+            }
+        }
+
+        let wide = PixelBuffer::from_data(2, 1, COLOR_TYPE_GRAYSCALE, 16, vec![0xFF, 0xFF, 0x80, 0x00]).unwrap();
+
+        assert_eq!(wide.to_tensor(ImageDataTensorShapeFormat::CHW).0, [1.0, 32768.0 / 65535.0]);
+    }
+
+    #[test]
+    fn layout_transposes_round_trip () {
+
+        let shape = ImageDataTensorShape::new(3, 1, 2);
+
+        assert_eq!(hwc_to_chw(&[1, 2, 3, 4, 5, 6], shape).unwrap(), [1, 4, 2, 5, 3, 6]);
+        assert_eq!(chw_to_hwc(&[1, 4, 2, 5, 3, 6], shape).unwrap(), [1, 2, 3, 4, 5, 6]);
+
+        /* Large enough to span several transpose blocks, the last one partial */
+        let shape = ImageDataTensorShape::new(4, 65, 130);
+        let data: Vec<u8> = (0 .. shape.len()).map(|index| (index * 31 % 251) as u8).collect();
+        let floats: Vec<f32> = data.iter().map(|value| *value as f32 / 255.0).collect();
+
+        assert!(shape.height * shape.width > TRANSPOSE_BLOCK_SIZE);
+        assert_eq!(chw_to_hwc(&hwc_to_chw(&data, shape).unwrap(), shape).unwrap(), data);
+        assert_eq!(hwc_to_chw(&chw_to_hwc(&data, shape).unwrap(), shape).unwrap(), data);
+        assert_eq!(chw_to_hwc_f32(&hwc_to_chw_f32(&floats, shape).unwrap(), shape).unwrap(), floats);
+
+        assert!(matches!(hwc_to_chw(&data[1 ..], shape), Err(PngError::BufferSizeMismatch { .. })));
+    }
+
+    #[test]
+    fn extract_block_follows_the_stride () {
+
+        /* 5 by 3 RGB, every sample numbered by its byte offset */
+        let buffer = PixelBuffer::from_data(5, 3, COLOR_TYPE_TRUECOLOR, 8, (0 .. 45).collect()).unwrap();
+        let block = ImageBlock::new(2, 3);
+
+        assert_eq!(extract_block(&buffer, &block, 1, 1).unwrap(), [18, 19, 20, 21, 22, 23, 24, 25, 26, 33, 34, 35, 36, 37, 38, 39, 40, 41]);
+        assert_eq!(extract_block(&buffer, &ImageBlock::new(3, 1), 4, 0).unwrap(), [12, 13, 14, 27, 28, 29, 42, 43, 44]);
+        assert_eq!(extract_block_chw(&buffer, &block, 1, 1).unwrap(), [18, 21, 24, 33, 36, 39, 19, 22, 25, 34, 37, 40, 20, 23, 26, 35, 38, 41]);
+
+        assert!(matches!(extract_block(&buffer, &block, 3, 1), Err(PngError::BlockOutOfBounds { x: 3, y: 1, width: 3, height: 2 })));
+        assert!(matches!(extract_block(&buffer, &block, 0, 2), Err(PngError::BlockOutOfBounds { .. })));
+    }
+
+    #[test]
+    fn patches_drop_or_zero_pad_the_edges () {
+
+        /* 5 by 3 gray numbered 0 .. 15, 2 by 2 patches leave a partial column and row */
+        let buffer = PixelBuffer::from_data(5, 3, COLOR_TYPE_GRAYSCALE, 8, (0 .. 15).collect()).unwrap();
+
+        let dropped: Vec<Patch> = buffer.patches(2, 2, PatchEdge::Drop).collect();
+
+        assert_eq!(dropped, [
+
+            Patch { grid_x: 0, grid_y: 0, data: vec![0, 1, 5, 6] },
+            Patch { grid_x: 1, grid_y: 0, data: vec![2, 3, 7, 8] },
+        ]);
+
+        let padded = buffer.patches(2, 2, PatchEdge::ZeroPad);
+
+        assert_eq!(padded.len(), 6);
+
+        let padded: Vec<Patch> = padded.collect();
+
+        assert_eq!(padded[2], Patch { grid_x: 2, grid_y: 0, data: vec![4, 0, 9, 0] });
+        assert_eq!(padded[3], Patch { grid_x: 0, grid_y: 1, data: vec![10, 11, 0, 0] });
+        assert_eq!(padded[5], Patch { grid_x: 2, grid_y: 1, data: vec![14, 0, 0, 0] });
+
+        assert_eq!(buffer.patches(0, 2, PatchEdge::ZeroPad).len(), 0);
+    }
+
+    #[test]
+    fn patch_count_does_not_wrap_at_u32 () {
+
+        /* The grid is never iterated so the buffer needs no pixels */
+        let buffer = PixelBuffer { width: 65536, height: 65536, color_type: COLOR_TYPE_GRAYSCALE, bit_depth: 8, data: Vec::new(), premultiplied: false };
+
+        assert_eq!(buffer.patches(1, 1, PatchEdge::Drop).len() as u64, 1 << 32);
+    }
+
+    #[test]
+    fn block_geometry_shares_the_image_out () {
+
+        let cfg = BlockConfig::default();
+
+        assert_eq!((cfg.context_blocks, cfg.target_blocks, cfg.aspect_ratio, cfg.patch_size), (1, 4, 1.0, 16));
+
+        /* 100 by 100 RGB shared by 5 square blocks */
+        assert_eq!(block_area(30000, 3, &cfg).unwrap(), 2000.0);
+        assert!((block_height(30000, 3, &cfg).unwrap() - 2000f64.sqrt()).abs() < 1e-9);
+        assert_eq!(block_height(30000, 3, &cfg).unwrap(), block_width(30000, 3, &cfg).unwrap());
+        assert_eq!(block_size(30000, 3, &cfg).unwrap(), 2000);
+
+        /* Two blocks twice as wide as they are high */
+        let wide = BlockConfig { context_blocks: 1, target_blocks: 1, aspect_ratio: 2.0, ..cfg };
+
+        assert_eq!(block_height(10000, 1, &wide).unwrap(), 50.0);
+        assert_eq!(block_width(10000, 1, &wide).unwrap(), 100.0);
+        assert_eq!(block_size(10000, 1, &wide).unwrap(), 5000);
+    }
+
+    #[test]
+    fn block_geometry_refuses_bad_input () {
+
+        let cfg = BlockConfig::default();
+
+        assert!(matches!(block_area(300, 0, &cfg), Err(PngError::InvalidBlockGeometry(_))));
+        assert!(matches!(block_height(2, 3, &cfg), Err(PngError::InvalidBlockGeometry(_))));
+        assert!(matches!(block_width(300, 3, &BlockConfig { context_blocks: 0, target_blocks: 0, ..cfg }), Err(PngError::InvalidBlockGeometry(_))));
+        assert!(matches!(block_size(300, 3, &BlockConfig { aspect_ratio: 0.0, ..cfg }), Err(PngError::InvalidBlockGeometry(_))));
+        assert!(matches!(block_size(300, 3, &BlockConfig { aspect_ratio: f64::NAN, ..cfg }), Err(PngError::InvalidBlockGeometry(_))));
+    }
+
+    #[test]
+    fn tensor_shape_of_a_png_and_buffer_validation () {
+
+        let shape = ImageDataTensorShape::from_png(&Png::try_from(fixtures::rgb()).unwrap()).unwrap();
+
+        assert_eq!(shape, ImageDataTensorShape::new(3, 2, 2));
+
+        assert_eq!(shape.validate_buffer(&[0; 12]), Ok(()));
+        assert_eq!(shape.validate_buffer(&[0; 11]), Err(PngError::BufferSizeMismatch { expected: 12, found: 11 }));
+        assert_eq!(shape.validate_buffer(&[0; 13]), Err(PngError::BufferSizeMismatch { expected: 12, found: 13 }));
+
+        /* Palette indices count as RGB, gray with alpha as two channels */
+        let shape_of = |bit_depth: u8, color_type: u8| {
+
+            let png = Png::try_from(fixtures::file(&[(b"IHDR", fixtures::ihdr(5, 3, bit_depth, color_type)), (b"IEND", Vec::new())])).unwrap();
+
+            ImageDataTensorShape::from_png(&png)
+        };
+
+        assert_eq!(shape_of(8, 3), Ok(ImageDataTensorShape::new(3, 3, 5)));
+        assert_eq!(shape_of(16, 4), Ok(ImageDataTensorShape::new(2, 3, 5)));
+        assert!(shape_of(8, 5).is_err());
+    }
+
+    #[test]
+    fn normalized_batches_have_zero_mean_and_unit_variance () {
+
+        let shape = ImageDataTensorShape::new(3, 8, 8);
+
+        /* Gray 0 and 1 samples have mean and deviation 0.5 */
+        let halves = ImageDataTensorShape::new(1, 1, 2);
+
+        assert_eq!(compute_mean_std(&[&[0.0, 1.0]], halves, ImageDataTensorShapeFormat::CHW).unwrap(), (vec![0.5], vec![0.5]));
+
+        for format in [ImageDataTensorShapeFormat::CHW, ImageDataTensorShapeFormat::HWC] {
+
+            let mut rng = StdRng::seed_from_u64(7);
+
+            /* Each channel gets its own offset and spread so a mix up of channels shows */
+            let mut batch: Vec<Vec<f32>> = (0 .. 2).map(|_| (0 .. shape.len()).map(|index| {
+
+                let channel = channel_of_element(index, &shape, format) as f32;
+
+                0.1 * channel + rng.gen_range(0.0 .. 0.2 + 0.3 * channel)
+
+            }).collect()).collect();
+
+            let (mean, std) = compute_mean_std(&batch.iter().map(|tensor| tensor.as_slice()).collect::<Vec<&[f32]>>(), shape, format).unwrap();
+
+            for tensor in batch.iter_mut() {
+
+                normalize(tensor, shape, format, &mean, &std).unwrap();
+            }
+
+            let (mean, std) = compute_mean_std(&batch.iter().map(|tensor| tensor.as_slice()).collect::<Vec<&[f32]>>(), shape, format).unwrap();
+
+            for channel in 0 .. shape.channels {
+
+                assert!(mean[channel].abs() < 1e-5, "{:?} mean of channel {} is {}", format, channel, mean[channel]);
+                assert!((std[channel] - 1.0).abs() < 1e-4, "{:?} deviation of channel {} is {}", format, channel, std[channel]);
+            }
+        }
+
+        assert_eq!(normalize(&mut [0.0; 3], ImageDataTensorShape::new(3, 1, 1), ImageDataTensorShapeFormat::HWC, &[0.0; 3], &[1.0, 0.0, 1.0]), Err(PngError::ZeroStandardDeviation(1)));
+    }
+
+    #[test]
+    fn extract_vertical_block_copies_one_column () {
+
+        let buffer = PixelBuffer::from_data(4, 4, COLOR_TYPE_GRAYSCALE, 8, (0 .. 16).collect()).unwrap();
+        let quarters = BlockConfig { context_blocks: 1, target_blocks: 3, ..BlockConfig::default() };
+        let halves = BlockConfig { context_blocks: 1, target_blocks: 1, ..BlockConfig::default() };
+
+        assert_eq!(extract_vertical_block(&buffer, 0, &quarters).unwrap(), [0, 4, 8, 12]);
+        assert_eq!(extract_vertical_block(&buffer, 2, &quarters).unwrap(), [2, 6, 10, 14]);
+        assert_eq!(extract_vertical_block(&buffer, 1, &halves).unwrap(), [2, 3, 6, 7, 10, 11, 14, 15]);
+
+        assert!(matches!(extract_vertical_block(&buffer, 4, &quarters), Err(PngError::BlockOutOfBounds { .. })));
+        assert!(matches!(extract_vertical_block(&buffer, 0, &BlockConfig { context_blocks: 0, target_blocks: 0, ..quarters }), Err(PngError::InvalidBlockGeometry(_))));
+    }
+}
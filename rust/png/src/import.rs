@@ -0,0 +1,272 @@
+/*
+    lib/rust/png/src/import.rs
+    Q@khaa.pk
+ */
+
+use crate::constants::{COLOR_TYPE_GRAYSCALE, COLOR_TYPE_TRUECOLOR, COLOR_TYPE_TRUECOLOR_ALPHA};
+use crate::error::PngError;
+use crate::pixels::PixelBuffer;
+
+/* Offset of the BITMAPINFOHEADER, right after the 14 byte BITMAPFILEHEADER */
+const BMP_INFO_HEADER_OFFSET: usize = 14;
+const LENGTH_OF_BMP_INFO_HEADER: usize = 40;
+
+/*
+    Next header token of a PNM file starting at @position, whitespace and comments (from # to the end of the line) are skipped
+ */
+fn pnm_token<'a> (bytes: &'a [u8], position: &mut usize) -> Result<&'a [u8], PngError> {
+
+    loop {
+
+        match bytes.get(*position) {
+
+            Some(byte) if byte.is_ascii_whitespace() => *position += 1,
+            Some(b'#') => {
+
+                while *position < bytes.len() && bytes[*position] != b'\n' && bytes[*position] != b'\r' {
+
+                    *position += 1;
+                }
+            }
+            Some(_) => break,
+            None => return Err(PngError::MalformedInput("PNM header ends early")),
+        }
+    }
+
+    let start = *position;
+
+    while *position < bytes.len() && !bytes[*position].is_ascii_whitespace() && bytes[*position] != b'#' {
+
+        *position += 1;
+    }
+
+    Ok(&bytes[start .. *position])
+}
+
+fn pnm_number (bytes: &[u8], position: &mut usize) -> Result<u32, PngError> {
+
+    std::str::from_utf8(pnm_token(bytes, position)?).ok().and_then(|token| token.parse::<u32>().ok()).ok_or(PngError::MalformedInput("PNM header field is not a number"))
+}
+
+fn read_u16_le (bytes: &[u8], offset: usize) -> u16 {
+
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32_le (bytes: &[u8], offset: usize) -> u32 {
+
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+impl PixelBuffer {
+
+    /*
+        Binary PGM (P5) or PPM (P6) file, gray or RGB. A maxval of 255 or 65535 is taken as is, any other is scaled up to the full range
+        of 8 (maxval below 256) or 16 bits. The ASCII variants P2 and P3 and the bitmap formats P1 and P4 are not supported.
+     */
+    pub fn from_ppm (bytes: &[u8]) -> Result<PixelBuffer, PngError> {
+
+        let mut position: usize = 0;
+
+        let color_type = match pnm_token(bytes, &mut position)? {
+
+            b"P5" => COLOR_TYPE_GRAYSCALE,
+            b"P6" => COLOR_TYPE_TRUECOLOR,
+            b"P1" | b"P2" | b"P3" | b"P4" => return Err(PngError::UnsupportedFormat("ASCII and bitmap PNM (P1 to P4), only binary P5 and P6 are supported".to_string())),
+            _ => return Err(PngError::MalformedInput("not a PGM or PPM file")),
+        };
+
+        let width = pnm_number(bytes, &mut position)?;
+        let height = pnm_number(bytes, &mut position)?;
+        let maxval = pnm_number(bytes, &mut position)?;
+
+        if maxval == 0 || maxval > 65535 {
+
+            return Err(PngError::MalformedInput("PNM maxval is not between 1 and 65535"));
+        }
+
+        /* Exactly one whitespace byte separates the header from the samples */
+        position += 1;
+
+        let bit_depth: u8 = if maxval < 256 { 8 } else { 16 };
+        let expected = PixelBuffer::new(width, height, color_type, bit_depth)?.data.len();
+        let samples = bytes.get(position .. position + expected).ok_or(PngError::BufferSizeMismatch { expected, found: bytes.len().saturating_sub(position) })?;
+
+        let mut buffer = PixelBuffer::from_data(width, height, color_type, bit_depth, samples.to_vec())?;
+
+        let max = buffer.max_sample_value();
+
+        if maxval != max {
+
+            let sample_size = buffer.bytes_per_sample();
+
+            for offset in (0 .. buffer.data.len()).step_by(sample_size) {
+
+                let value = buffer.sample(offset);
+
+                if value > maxval {
+
+                    return Err(PngError::SampleOutOfRange { value, max: maxval });
+                }
+
+                buffer.set_sample(offset, (value * max + maxval / 2) / maxval);
+            }
+        }
+
+        Ok(buffer)
+    }
+
+    /*
+        Uncompressed (BI_RGB) BMP of 24 or 32 bits per pixel with a BITMAPINFOHEADER or a later, longer header. Rows may be bottom-up or top-down.
+        32 bit images become RGBA, unless every alpha byte is 0, which is how most writers leave the unused fourth byte, then they become RGB.
+        Palette and compressed BMPs are not supported.
+     */
+    pub fn from_bmp (bytes: &[u8]) -> Result<PixelBuffer, PngError> {
+
+        if bytes.len() < BMP_INFO_HEADER_OFFSET + LENGTH_OF_BMP_INFO_HEADER || &bytes[0 .. 2] != b"BM" {
+
+            return Err(PngError::MalformedInput("not a BMP file"));
+        }
+
+        let pixel_offset = read_u32_le(bytes, 10) as usize;
+        let info = &bytes[BMP_INFO_HEADER_OFFSET ..];
+
+        if (read_u32_le(info, 0) as usize) < LENGTH_OF_BMP_INFO_HEADER {
+
+            return Err(PngError::UnsupportedFormat("BMP with a header older than BITMAPINFOHEADER".to_string()));
+        }
+
+        let width = read_u32_le(info, 4) as i32;
+        let height = read_u32_le(info, 8) as i32;
+        let bits_per_pixel = read_u16_le(info, 14);
+        let compression = read_u32_le(info, 16);
+
+        if compression != 0 {
+
+            return Err(PngError::UnsupportedFormat(format!("compressed BMP (compression {})", compression)));
+        }
+
+        if bits_per_pixel != 24 && bits_per_pixel != 32 {
+
+            return Err(PngError::UnsupportedFormat(format!("BMP with {} bits per pixel, only 24 and 32 are supported", bits_per_pixel)));
+        }
+
+        if width <= 0 || height == 0 || height == i32::MIN {
+
+            return Err(PngError::InvalidDimensions { width: width as u32, height: height as u32 });
+        }
+
+        let (width, top_down, height) = (width as u32, height < 0, height.unsigned_abs());
+
+        let bytes_per_pixel = bits_per_pixel as usize / 8;
+        let row_length = (width as usize * bytes_per_pixel).div_ceil(4) * 4;
+        let expected = row_length * height as usize;
+
+        let rows = bytes.get(pixel_offset .. pixel_offset + expected).ok_or(PngError::BufferSizeMismatch { expected, found: bytes.len().saturating_sub(pixel_offset) })?;
+
+        let mut buffer = PixelBuffer::new(width, height, COLOR_TYPE_TRUECOLOR_ALPHA, 8)?;
+        let stride = buffer.stride();
+
+        for (index, row) in rows.chunks_exact(row_length).enumerate() {
+
+            let y = if top_down { index } else { height as usize - 1 - index };
+            let line = &mut buffer.data[y * stride .. (y + 1) * stride];
+
+            for (pixel, source) in line.chunks_exact_mut(4).zip(row.chunks_exact(bytes_per_pixel)) {
+
+                pixel.copy_from_slice(&[source[2], source[1], source[0], if bytes_per_pixel == 4 { source[3] } else { 0xFF }]);
+            }
+        }
+
+        if bytes_per_pixel == 3 || buffer.data.chunks_exact(4).all(|pixel| pixel[3] == 0) {
+
+            let data: Vec<u8> = buffer.data.chunks_exact(4).flat_map(|pixel| pixel[.. 3].to_vec()).collect();
+
+            return PixelBuffer::from_data(width, height, COLOR_TYPE_TRUECOLOR, 8, data);
+        }
+
+        Ok(buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn bmp (image: &PixelBuffer) -> Vec<u8> {
+
+        let mut bytes: Vec<u8> = Vec::new();
+
+        image.write_bmp(&mut bytes).unwrap();
+
+        bytes
+    }
+
+    #[test]
+    fn ppm_maxval_other_than_255_is_scaled () {
+
+        let gray = PixelBuffer::from_ppm(b"P5\n# four bits\n3 1\n15\n\x00\x07\x0F").unwrap();
+
+        assert_eq!((gray.color_type, gray.bit_depth), (COLOR_TYPE_GRAYSCALE, 8));
+        assert_eq!(gray.data, [0, 119, 255]);
+
+        let wide = PixelBuffer::from_ppm(b"P6 1 1 1023 \x03\xFF\x00\x00\x02\x00").unwrap();
+
+        assert_eq!((wide.color_type, wide.bit_depth), (COLOR_TYPE_TRUECOLOR, 16));
+        assert_eq!(wide.data, [0xFF, 0xFF, 0x00, 0x00, 0x80, 0x20]);
+
+        assert_eq!(PixelBuffer::from_ppm(b"P5 1 1 15 \x10").err(), Some(PngError::SampleOutOfRange { value: 16, max: 15 }));
+    }
+
+    #[test]
+    fn truncated_ppm_is_an_error () {
+
+        assert_eq!(PixelBuffer::from_ppm(b"P6 2 1 255 \x01\x02\x03\x04\x05").err(), Some(PngError::BufferSizeMismatch { expected: 6, found: 5 }));
+        assert_eq!(PixelBuffer::from_ppm(b"P6 2 1").err(), Some(PngError::MalformedInput("PNM header ends early")));
+        assert_eq!(PixelBuffer::from_ppm(b"").err(), Some(PngError::MalformedInput("PNM header ends early")));
+    }
+
+    #[test]
+    fn top_down_bmp_reads_like_bottom_up () {
+
+        let image = PixelBuffer::from_data(3, 2, COLOR_TYPE_TRUECOLOR, 8, (1 .. 19).collect()).unwrap();
+        let bottom_up = bmp(&image);
+
+        assert_eq!(PixelBuffer::from_bmp(&bottom_up).unwrap().data, image.data);
+
+        /* Negative height, rows stored first to last */
+        let (headers, rows) = bottom_up.split_at(54);
+        let mut top_down = headers.to_vec();
+
+        top_down[22 .. 26].copy_from_slice(&(-2i32).to_le_bytes());
+        top_down.extend_from_slice(&rows[12 ..]);
+        top_down.extend_from_slice(&rows[.. 12]);
+
+        assert_eq!(PixelBuffer::from_bmp(&top_down).unwrap().data, image.data);
+    }
+
+    #[test]
+    fn bmp_with_all_alpha_bytes_zero_is_rgb () {
+
+        let unused = PixelBuffer::from_data(2, 1, COLOR_TYPE_TRUECOLOR_ALPHA, 8, vec![1, 2, 3, 0, 4, 5, 6, 0]).unwrap();
+        let read = PixelBuffer::from_bmp(&bmp(&unused)).unwrap();
+
+        assert_eq!(read.color_type, COLOR_TYPE_TRUECOLOR);
+        assert_eq!(read.data, [1, 2, 3, 4, 5, 6]);
+
+        /* One non-zero alpha byte and the alpha is real */
+        let used = PixelBuffer::from_data(2, 1, COLOR_TYPE_TRUECOLOR_ALPHA, 8, vec![1, 2, 3, 0, 4, 5, 6, 9]).unwrap();
+
+        assert_eq!(PixelBuffer::from_bmp(&bmp(&used)).unwrap().data, used.data);
+    }
+
+    #[test]
+    fn truncated_bmp_is_an_error () {
+
+        let bytes = bmp(&PixelBuffer::from_data(3, 2, COLOR_TYPE_TRUECOLOR, 8, vec![0; 18]).unwrap());
+
+        assert_eq!(PixelBuffer::from_bmp(&bytes[.. bytes.len() - 1]).err(), Some(PngError::BufferSizeMismatch { expected: 24, found: 23 }));
+        assert_eq!(PixelBuffer::from_bmp(&bytes[.. 40]).err(), Some(PngError::MalformedInput("not a BMP file")));
+    }
+}
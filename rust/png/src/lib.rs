@@ -3,24 +3,74 @@
     Q@khaa.pk
  */
 
-use std::collections::LinkedList; 
-use crate::constants::LENGTH_OF_CRC_FIELD;
+use std::borrow::Cow;
+use std::collections::LinkedList;
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use crate::constants::{LENGTH_OF_CRC_FIELD, ADAM7_PASSES};
 
+mod ancillary;
+mod base64;
 mod constants;
+mod crc;
+mod error;
+mod pixels;
+mod apng;
+#[cfg(test)]
+mod fixtures;
+pub mod analysis;
+pub mod compare;
+pub mod export;
+pub mod gamma;
+pub mod hash;
+pub mod images;
+pub mod import;
+pub mod metadata;
+pub mod palette;
+pub mod patterns;
+pub mod stream;
+pub mod tiles;
+pub mod validate;
+pub mod watermark;
 
+/* Values of the IHDR color type byte, which PixelBuffer, Pixel and the IHDR setters take as a plain u8 */
+pub use constants::{COLOR_TYPE_GRAYSCALE, COLOR_TYPE_TRUECOLOR, COLOR_TYPE_INDEXED, COLOR_TYPE_GRAYSCALE_ALPHA, COLOR_TYPE_TRUECOLOR_ALPHA};
+pub use ancillary::{ImageOffset, OffsetUnit, PhysicalDimensions, PhysicalUnit, StereoMode, stereo_subimage_width};
+pub use error::PngError;
+pub use pixels::{PixelBuffer, Pixel, Filter, MaskMode, Rotation, channels_of_color_type, is_valid_bit_depth};
+
+#[cfg(not(any(target_arch = "wasm32", feature = "pure-rust")))]
 #[link(name = "sundry", kind = "dylib")]
 /* Native function call */
-extern {
+extern "C" {
  
      fn big_endian_read_u32(ptr: *const u8) -> u32;     
 }
 
-#[derive(Clone)]
+/*
+    Stands in for the native function where there is no sundry library to link, on wasm32 and with the pure-rust feature.
+    Same contract, @ptr has to point at 4 readable bytes.
+ */
+#[cfg(any(target_arch = "wasm32", feature = "pure-rust"))]
+unsafe fn big_endian_read_u32 (ptr: *const u8) -> u32 {
+
+    u32::from_be_bytes(std::ptr::read_unaligned(ptr as *const [u8; 4]))
+}
+
+/*
+    Fields are kept exactly as they are on disk. length is the 4 byte big-endian length of data and crc the 4 byte big-endian CRC of type_name followed by data,
+    whoever changes data through data_mut() must keep both in step, set_data() and update_crc() do that.
+    data is shared between clones of a chunk (and so of a Png), cloning copies no chunk data and a clone gets its own copy only when its data is changed.
+    It is private so that sharing stays an implementation detail, data() reads it and data_mut() or set_data() change it.
+ */
+#[derive(Clone, PartialEq)]
 pub struct Chunk {
      
     pub length: Vec<u8>,
     pub type_name: Vec<u8>,
-    pub data: Vec<u8>,
+    data: Arc<Vec<u8>>,
     pub crc: Vec<u8>,
 }
 
@@ -28,13 +78,13 @@ impl Chunk {
 
     pub fn new (data: Vec<u8>) -> Self {
 
-        if data.len() > 0 {
+        if !data.is_empty() {
                         
             Self {
 
                 length: data[0 .. 4].to_vec(),
                 type_name: data[4 .. 8].to_vec(),
-                data: data[8 .. unsafe { big_endian_read_u32 (data[0 .. 4].as_ptr()) } as usize + 8 ].to_vec(),
+                data: Arc::new(data[8 .. unsafe { big_endian_read_u32 (data[0 .. 4].as_ptr()) } as usize + 8 ].to_vec()),
                 crc: data[ unsafe { big_endian_read_u32 (data[0 .. 4].as_ptr()) } as usize + 8 .. unsafe { big_endian_read_u32 (data[0 .. 4].as_ptr()) } as usize + 4 + 8 ].to_vec(),                
             }
         }
@@ -44,25 +94,249 @@ impl Chunk {
                 
                 length: Vec::new(),
                 type_name: Vec::new(),
-                data: Vec::new(),
+                data: Arc::new(Vec::new()),
                 crc: Vec::new(),                
             }
         }
     }        
+
+    /*
+        Borrowed data field, the bytes are not copied
+     */
+    pub fn data (&self) -> &[u8] {
+
+        &self.data
+    }
+
+    /*
+        Data field for changing in place, copied first if a clone still shares it. Follow changes of its length with set_data() and any change with update_crc().
+     */
+    pub fn data_mut (&mut self) -> &mut Vec<u8> {
+
+        Arc::make_mut(&mut self.data)
+    }
+
+    /*
+        Borrowed type field, all zeros for the empty chunk Chunk::new() makes out of no bytes
+     */
+    pub fn type_bytes (&self) -> &[u8; 4] {
+
+        self.type_name.as_slice().try_into().unwrap_or(&[0; 4])
+    }
+
+    /*
+        Type field as text, bytes that are not valid UTF-8 (a corrupt file can have any) come out as U+FFFD rather than panicking.
+        Compare type_name or type_bytes() with a byte string to test for a type, that allocates nothing.
+     */
+    pub fn get_type_name (&self) -> Cow<'_, str> {
+
+        String::from_utf8_lossy(&self.type_name)
+    }
+
+    /*
+        New chunk of type @type_name carrying @data, length and CRC are filled in here so no caller has to work out a CRC
+     */
+    pub fn from_parts (type_name: &[u8; 4], data: Vec<u8>) -> Self {
+
+        let mut chunk = Self {
+
+            length: Vec::new(),
+            type_name: type_name.to_vec(),
+            data: Arc::new(Vec::new()),
+            crc: Vec::new(),
+        };
+
+        chunk.set_data(data);
+
+        chunk
+    }
+
+    /*
+        The chunk as it is written to a file, length, type, data and CRC
+     */
+    pub fn to_bytes (&self) -> Vec<u8> {
+
+        let mut bytes: Vec<u8> = Vec::with_capacity(constants::LENGTH_OF_THREE_FIELDS + self.data.len());
+
+        bytes.extend_from_slice(&self.length);
+        bytes.extend_from_slice(&self.type_name);
+        bytes.extend_from_slice(&self.data);
+        bytes.extend_from_slice(&self.crc);
+
+        bytes
+    }
+
+    /*
+        Writes to_bytes() to @writer field by field without building it, gives back the number of bytes written
+     */
+    pub fn write_to<W: Write> (&self, writer: &mut W) -> Result<u64, PngError> {
+
+        for field in [&self.length[..], &self.type_name, &self.data, &self.crc] {
+
+            writer.write_all(field)?;
+        }
+
+        Ok(self.size_in_bytes() as u64)
+    }
+
+    /*
+        Length of to_bytes() without building it, 12 bytes of length, type and CRC plus the data
+     */
+    pub fn size_in_bytes (&self) -> usize {
+
+        self.length.len().saturating_add(self.type_name.len()).saturating_add(self.data.len()).saturating_add(self.crc.len())
+    }
+
+    /*
+        Replaces the data field, the length field and the CRC are updated to match
+     */
+    pub fn set_data (&mut self, data: Vec<u8>) {
+
+        self.length = (data.len() as u32).to_be_bytes().to_vec();
+        self.data = Arc::new(data);
+
+        self.update_crc();
+    }
+
+    /*
+        Recomputes the CRC over type and data, for when a few bytes of data were patched in place
+     */
+    pub fn update_crc (&mut self) {
+
+        self.crc = crc::chunk_crc(&self.type_name, &self.data).to_be_bytes().to_vec();
+    }
+
+    /*
+        Stored CRC agrees with the type and data fields
+     */
+    pub fn is_crc_valid (&self) -> bool {
+
+        self.crc == crc::chunk_crc(&self.type_name, &self.data).to_be_bytes()
+    }
+
+    /*
+        Property bits of the chunk type, bit 5 (the lowercase bit) of each of the four type bytes.
+        Ancillary chunks have a lowercase first letter, they can be dropped without losing the ability to show the image.
+     */
+    pub fn is_ancillary (&self) -> bool {
+
+        self.type_name.first().is_some_and(|byte| byte & 0x20 != 0)
+    }
+
+    pub fn is_critical (&self) -> bool {
+
+        !self.is_ancillary()
+    }
+
+    pub fn is_private (&self) -> bool {
+
+        self.type_name.get(1).is_some_and(|byte| byte & 0x20 != 0)
+    }
+
+    pub fn is_safe_to_copy (&self) -> bool {
+
+        self.type_name.get(3).is_some_and(|byte| byte & 0x20 != 0)
+    }
+}
+
+/*
+    What Png::strip_metadata() removed
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub struct StripReport {
+
+    /* Type of every removed chunk, in the order they were in the file */
+    pub removed: Vec<String>,
+    /* Bytes the file is smaller by, length, type and CRC fields included */
+    pub bytes_saved: usize,
+}
+
+/*
+    Chunk types the PNG and APNG specifications allow at most once per image, Png::add_chunk() won't add a second
+ */
+const SINGLE_INSTANCE_CHUNKS: [&[u8; 4]; 16] = [b"IHDR", b"PLTE", b"IEND", b"gAMA", b"cHRM", b"sRGB", b"iCCP", b"sBIT", b"tRNS", b"bKGD", b"hIST", b"pHYs", b"oFFs", b"sTER", b"tIME", b"acTL"];
+
+const DATA_URI_PREFIX: &str = "data:image/png;base64,";
+
+/*
+    Budget a caller sets for images from untrusted sources, checked from IHDR alone before anything is inflated
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DecodeLimits {
+
+    /* Largest Png::estimated_decoded_size() accepted */
+    pub max_decoded_bytes: u64,
+}
+
+impl Default for DecodeLimits {
+
+    /*
+        256 MiB
+     */
+    fn default () -> Self {
+
+        Self { max_decoded_bytes: 256 * 1024 * 1024 }
+    }
+}
+
+impl DecodeLimits {
+
+    /*
+        Ok with the estimate when @png fits the budget, DecodeLimitExceeded when it doesn't
+     */
+    pub fn check (&self, png: &Png) -> Result<u64, PngError> {
+
+        let estimate = png.estimated_decoded_size()?;
+
+        if estimate > self.max_decoded_bytes {
+
+            return Err(PngError::DecodeLimitExceeded { limit: self.max_decoded_bytes, estimate });
+        }
+
+        Ok(estimate)
+    }
+}
+
+/*
+    What parsing does about a chunk whose stored CRC doesn't match its type and data. The chunks parsed are the same in every mode.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CrcMode {
+
+    /* Stop at the first mismatch with CrcMismatch */
+    Verify,
+    /* Report every mismatch as a warning and carry on */
+    Warn,
+    /* Don't compute CRCs at all, as Png::try_from() does */
+    Ignore,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ParseOptions {
+
+    pub crc_mode: CrcMode,
+}
+
+impl Default for ParseOptions {
+
+    fn default () -> Self {
+
+        Self { crc_mode: CrcMode::Verify }
+    }
 }
 
 #[derive(Clone)]
 pub struct Png {
     
     pub signature: Vec<u8>,            
-    pub chunks: LinkedList<Chunk>,
+    pub chunks: Vec<Chunk>,
 }
 
 impl Png {
 
     pub fn new (data: Vec<u8>) -> Self {
 
-        let mut head: LinkedList<Chunk> = LinkedList::new();
+        let mut head: Vec<Chunk> = Vec::new();
                         
         //let mut signature = vec![0; constants::LENGTH_OF_SIGNATURE];
 
@@ -78,10 +352,10 @@ impl Png {
             
                 let chunk = Chunk::new(
 
-                    (&data[index .. (index + constants::LENGTH_OF_LENGTH_FIELD + constants::LENGTH_OF_TYPE_FIELD + unsafe { big_endian_read_u32(data[index .. (index + 4)].as_ptr()) } as usize + LENGTH_OF_CRC_FIELD)]).to_vec()
+                    data[index .. (index + constants::LENGTH_OF_LENGTH_FIELD + constants::LENGTH_OF_TYPE_FIELD + unsafe { big_endian_read_u32(data[index .. (index + 4)].as_ptr()) } as usize + LENGTH_OF_CRC_FIELD)].to_vec()
                 );
 
-                head.push_back(chunk);
+                head.push(chunk);
             
                 index = index + constants::LENGTH_OF_LENGTH_FIELD + constants::LENGTH_OF_TYPE_FIELD + unsafe { big_endian_read_u32(data[index .. (index + 4)].as_ptr()) } as usize + LENGTH_OF_CRC_FIELD;
             }
@@ -126,4 +400,1451 @@ impl Png {
             chunks: head,
         }*/
     }
+
+    /*
+        Borrowed 8 byte signature, empty when the data Png::new() was given was shorter than that
+     */
+    pub fn signature (&self) -> &[u8] {
+
+        &self.signature
+    }
+
+    /*
+        The whole file, signature followed by every chunk in order
+     */
+    pub fn to_bytes (&self) -> Vec<u8> {
+
+        let mut bytes: Vec<u8> = self.signature.clone();
+
+        for chunk in self {
+
+            bytes.extend_from_slice(&chunk.to_bytes());
+        }
+
+        bytes
+    }
+
+    /*
+        Png::try_from() with control over CRC checking, see CrcMode. Warnings (only CrcMode::Warn gives any) come back with the image,
+        e.g. to read recovered files that buggy tools wrote with wrong CRCs.
+     */
+    pub fn parse (data: &[u8], options: &ParseOptions) -> Result<(Png, Vec<validate::ValidationIssue>), PngError> {
+
+        let png = Png::try_from(data)?;
+        let mut warnings: Vec<validate::ValidationIssue> = Vec::new();
+
+        if options.crc_mode != CrcMode::Ignore {
+
+            for (index, chunk) in png.iter().enumerate().filter(|(_, chunk)| !chunk.is_crc_valid()) {
+
+                let type_name = chunk.get_type_name().into_owned();
+
+                if options.crc_mode == CrcMode::Verify {
+
+                    return Err(PngError::CrcMismatch { index, type_name });
+                }
+
+                warnings.push(validate::ValidationIssue { severity: validate::Severity::Warning, chunk_index: Some(index), message: format!("{} CRC does not match its type and data", type_name), spec: "5.3" });
+            }
+        }
+
+        Ok((png, warnings))
+    }
+
+    /*
+        Length of to_bytes() without building it, the signature plus size_in_bytes() of every chunk.
+        Sums saturate at usize::MAX rather than wrapping, so a pathological chunk list can't come out small.
+     */
+    pub fn size_in_bytes (&self) -> usize {
+
+        self.signature.len().saturating_add(self.chunks_size(|_| true))
+    }
+
+    /*
+        Byte offset in to_bytes() of each chunk's length field, in chunk order. Worked out from the current chunks rather than kept from parsing,
+        so for an image that hasn't been edited these are the offsets in the file it was read from, and after editing they are where to_bytes() puts each chunk.
+     */
+    pub fn chunk_offsets (&self) -> Vec<u64> {
+
+        let mut offset = self.signature.len() as u64;
+
+        self.iter().map(|chunk| {
+
+            let start = offset;
+            offset = offset.saturating_add(chunk.size_in_bytes() as u64);
+
+            start
+
+        }).collect()
+    }
+
+    /*
+        Bytes of the file taken by IDAT chunks, framing included
+     */
+    pub fn idat_size (&self) -> usize {
+
+        self.chunks_size(|chunk| chunk.type_name == b"IDAT")
+    }
+
+    /*
+        Bytes of the file taken by ancillary chunks, framing included, what strip_metadata() with nothing kept would save
+     */
+    pub fn metadata_size (&self) -> usize {
+
+        self.chunks_size(|chunk| chunk.is_ancillary())
+    }
+
+    fn chunks_size (&self, include: impl Fn(&Chunk) -> bool) -> usize {
+
+        self.iter().filter(|chunk| include(chunk)).fold(0, |size: usize, chunk| size.saturating_add(chunk.size_in_bytes()))
+    }
+
+    /*
+        Writes the whole file to @writer (a Vec<u8>, a file, a socket...) chunk by chunk, the same bytes as to_bytes() without that copy of the file in memory.
+        Gives back the number of bytes written. @writer is not flushed.
+     */
+    pub fn write_to<W: Write> (&self, writer: &mut W) -> Result<u64, PngError> {
+
+        writer.write_all(&self.signature)?;
+
+        let mut written = self.signature.len() as u64;
+
+        for chunk in self {
+
+            written += chunk.write_to(writer)?;
+        }
+
+        Ok(written)
+    }
+
+    /*
+        The whole file as a data URI, data:image/png;base64, followed by the padded base64 of to_bytes()
+     */
+    pub fn to_data_uri (&self) -> String {
+
+        format!("{}{}", DATA_URI_PREFIX, base64::encode(&self.to_bytes()))
+    }
+
+    /*
+        Parses a data URI, it has to carry image/png as base64 (padded or not, see base64::decode() for what is rejected) and hold a well formed PNG
+     */
+    pub fn from_data_uri (uri: &str) -> Result<Png, PngError> {
+
+        let (header, payload) = match uri.strip_prefix("data:").and_then(|rest| rest.split_once(',')) {
+
+            Some(parts) => parts,
+            None => return Err(PngError::InvalidDataUri("it does not start with data: or has no comma before the payload")),
+        };
+
+        let mime_type = match header.strip_suffix(";base64") {
+
+            Some(mime_type) => mime_type,
+            None => return Err(PngError::InvalidDataUri("payload is not base64 encoded")),
+        };
+
+        if !mime_type.eq_ignore_ascii_case("image/png") {
+
+            return Err(PngError::DataUriMimeType(mime_type.to_string()));
+        }
+
+        Png::try_from(base64::decode(payload)?)
+    }
+
+    /*
+        Chunks in file order, signature excluded
+     */
+    pub fn get_chunks (&self) -> &[Chunk] {
+
+        &self.chunks
+    }
+
+    /*
+        Chunks in file order, `for chunk in &png` does the same
+     */
+    pub fn iter (&self) -> std::slice::Iter<'_, Chunk> {
+
+        self.chunks.iter()
+    }
+
+    /*
+        Chunks in file order for changing in place, see Chunk::data_mut() and Chunk::update_crc() to keep them consistent
+     */
+    pub fn iter_mut (&mut self) -> std::slice::IterMut<'_, Chunk> {
+
+        self.chunks.iter_mut()
+    }
+
+    /*
+        Chunk number @index in file order (IHDR is 0), None past the last chunk
+     */
+    pub fn chunk_at (&self, index: usize) -> Option<&Chunk> {
+
+        self.chunks.get(index)
+    }
+
+    /*
+        Position of the first chunk of type @type_name, for use with chunk_at(), insert_chunk() and remove_chunk()
+     */
+    pub fn chunk_index_of_type (&self, type_name: &str) -> Option<usize> {
+
+        self.iter().position(|chunk| chunk.type_name == type_name.as_bytes())
+    }
+
+    /*
+        Places @chunk at position @index, chunks from @index on move one place up. Panics if @index is past the number of chunks.
+        Nothing checks the resulting chunk order is one the PNG specification allows, e.g. that IHDR stays first.
+     */
+    pub fn insert_chunk (&mut self, index: usize, chunk: Chunk) {
+
+        self.chunks.insert(index, chunk);
+    }
+
+    /*
+        Inserts @chunk at the latest position the PNG (and APNG) chunk ordering rules allow and returns that position.
+        gAMA, cHRM, sRGB, iCCP and sBIT go before PLTE and IDAT, tRNS, bKGD and hIST after PLTE but before IDAT, pHYs, oFFs, sTER, sPLT and acTL before IDAT.
+        IDAT joins the existing IDAT chunks, tIME, text, fcTL, fdAT and unknown chunks go just before IEND.
+        Chunk types the specification allows only once give DuplicateChunk when the image already has one, ChunkOrder when the chunks already present leave no legal position.
+     */
+    pub fn add_chunk (&mut self, chunk: Chunk) -> Result<usize, PngError> {
+
+        let type_name = *chunk.type_bytes();
+        let name = || String::from_utf8_lossy(&type_name).into_owned();
+
+        let first = |type_name: &[u8]| self.chunks.iter().position(|chunk| chunk.type_name == type_name);
+        let last = |type_name: &[u8]| self.chunks.iter().rposition(|chunk| chunk.type_name == type_name);
+
+        if SINGLE_INSTANCE_CHUNKS.contains(&&type_name) && first(&type_name).is_some() {
+
+            return Err(PngError::DuplicateChunk(name()));
+        }
+
+        let mut lo = first(b"IHDR").map(|index| index + 1).unwrap_or(0);
+        let mut hi = first(b"IEND").unwrap_or(self.chunks.len());
+
+        let mut after = |type_names: &[&[u8; 4]]| type_names.iter().filter_map(|type_name| last(*type_name)).for_each(|index| lo = lo.max(index + 1));
+        let mut before = |type_names: &[&[u8; 4]]| type_names.iter().filter_map(|type_name| first(*type_name)).for_each(|index| hi = hi.min(index));
+
+        match &type_name {
+
+            b"IHDR" => {
+
+                lo = 0;
+                hi = 0;
+            }
+            b"IEND" => lo = self.chunks.len(),
+            b"gAMA" | b"cHRM" | b"sRGB" | b"iCCP" | b"sBIT" => before(&[b"PLTE", b"IDAT"]),
+            b"PLTE" => {
+
+                after(&[b"gAMA", b"cHRM", b"sRGB", b"iCCP", b"sBIT"]);
+                before(&[b"tRNS", b"bKGD", b"hIST", b"IDAT"]);
+            }
+            b"tRNS" | b"bKGD" | b"hIST" => {
+
+                after(&[b"PLTE"]);
+                before(&[b"IDAT"]);
+            }
+            b"pHYs" | b"oFFs" | b"sTER" | b"sPLT" | b"acTL" => before(&[b"IDAT"]),
+            b"IDAT" => {
+
+                match last(b"IDAT") {
+
+                    Some(index) => {
+
+                        lo = index + 1;
+                        hi = lo;
+                    }
+                    None => before(&[b"fdAT"]),
+                }
+            }
+            _ => {},
+        }
+
+        if lo > hi {
+
+            return Err(PngError::ChunkOrder(name()));
+        }
+
+        self.chunks.insert(hi, chunk);
+
+        Ok(hi)
+    }
+
+    /*
+        Gives the chunk of type @type_name @data when there is one, otherwise adds it with add_chunk(). Position of the chunk either way.
+        For chunk types the specification allows only once.
+     */
+    pub(crate) fn upsert_chunk (&mut self, type_name: &[u8; 4], data: Vec<u8>) -> Result<usize, PngError> {
+
+        match self.iter().position(|chunk| chunk.type_name == type_name) {
+
+            Some(index) => {
+
+                self.chunks[index].set_data(data);
+
+                Ok(index)
+            }
+            None => self.add_chunk(Chunk::from_parts(type_name, data)),
+        }
+    }
+
+    /*
+        Takes chunk number @index out of the image, None past the last chunk
+     */
+    pub fn remove_chunk (&mut self, index: usize) -> Option<Chunk> {
+
+        if index < self.chunks.len() {
+
+            Some(self.chunks.remove(index))
+        }
+        else {
+
+            None
+        }
+    }
+
+    /*
+        Chunks as the LinkedList they were kept in before version 0.2.0
+     */
+    pub fn into_linked_list (self) -> LinkedList<Chunk> {
+
+        self.chunks.into_iter().collect()
+    }
+
+    /*
+        Data field of the IHDR chunk, an error when there is none or it is not 13 bytes long.
+        Every property helper below reads IHDR through here.
+     */
+    pub(crate) fn ihdr (&self) -> Result<&[u8], PngError> {
+
+        let ihdr = match self.iter().find(|chunk| chunk.type_name == b"IHDR") {
+
+            Some(chunk) => chunk.data(),
+            None => return Err(PngError::MissingChunk("IHDR")),
+        };
+
+        if ihdr.len() != 13 {
+
+            return Err(PngError::InvalidChunkLength { type_name: "IHDR", expected: 13, found: ihdr.len() });
+        }
+
+        Ok(ihdr)
+    }
+
+    /*
+        (width, height) in pixels, None when there is no (well formed) IHDR chunk, as for every helper below
+     */
+    pub fn dimensions (&self) -> Option<(u32, u32)> {
+
+        let ihdr = self.ihdr().ok()?;
+
+        Some((u32::from_be_bytes([ihdr[0], ihdr[1], ihdr[2], ihdr[3]]), u32::from_be_bytes([ihdr[4], ihdr[5], ihdr[6], ihdr[7]])))
+    }
+
+    pub fn color_type (&self) -> Option<u8> {
+
+        self.ihdr().ok().map(|ihdr| ihdr[9])
+    }
+
+    /*
+        Bits per sample, or per palette index for indexed images
+     */
+    pub fn bit_depth (&self) -> Option<u8> {
+
+        self.ihdr().ok().map(|ihdr| ihdr[8])
+    }
+
+    /*
+        Samples per pixel as stored, 1 for indexed images. None also for a color type the specification doesn't define.
+     */
+    pub fn channels (&self) -> Option<usize> {
+
+        channels_of_color_type(self.color_type()?).ok()
+    }
+
+    pub fn bits_per_pixel (&self) -> Option<usize> {
+
+        Some(self.channels()? * self.bit_depth()? as usize)
+    }
+
+    /*
+        Bytes of one row of pixels, rounded up for bit depths below 8. The filter type byte in front of every row of image data is not counted.
+     */
+    pub fn bytes_per_scanline (&self) -> Option<usize> {
+
+        let (width, _) = self.dimensions()?;
+
+        Some((width as usize * self.bits_per_pixel()?).div_ceil(8))
+    }
+
+    /*
+        Data fields of all IDAT chunks joined in file order, that is the complete zlib stream of the image.
+        The total size is added up first so the stream is copied into a single allocation.
+     */
+    pub fn get_all_idat_data_as_vec (&self) -> Vec<u8> {
+
+        let idat = || self.iter().filter(|chunk| chunk.type_name == b"IDAT");
+
+        let mut data: Vec<u8> = Vec::with_capacity(idat().map(|chunk| chunk.data.len()).sum());
+
+        idat().for_each(|chunk| data.extend_from_slice(&chunk.data));
+
+        data
+    }
+
+    /*
+        Replaces all IDAT chunks with a single one, at the position of the first, carrying their joined data.
+        The zlib stream itself is not touched, only the 12 bytes of framing each extra IDAT chunk costs go away.
+     */
+    pub fn merge_idat (&mut self) -> Result<(), PngError> {
+
+        if !self.iter().any(|chunk| chunk.type_name == b"IDAT") {
+
+            return Err(PngError::MissingChunk("IDAT"));
+        }
+
+        let mut data = Some(self.get_all_idat_data_as_vec());
+        let mut kept: Vec<Chunk> = Vec::with_capacity(self.chunks.len());
+
+        for chunk in self.chunks.drain(..) {
+
+            if chunk.type_name != b"IDAT" {
+
+                kept.push(chunk);
+            }
+            else if let Some(data) = data.take() {
+
+                kept.push(Chunk::from_parts(b"IDAT", data));
+            }
+        }
+
+        self.chunks = kept;
+
+        Ok(())
+    }
+
+    /*
+        Rewrites width, height, bit depth and color type in the IHDR chunk and recomputes its CRC, compression, filter and interlace methods are kept.
+        The only writer of IHDR, like the tRNS, pHYs, oFFs, sTER and text writers it stores the new data with Chunk::set_data() so length and CRC can't be left stale.
+     */
+    pub fn set_ihdr (&mut self, width: u32, height: u32, bit_depth: u8, color_type: u8) -> Result<(), PngError> {
+
+        if width == 0 || height == 0 || width > i32::MAX as u32 || height > i32::MAX as u32 {
+
+            return Err(PngError::InvalidDimensions { width, height });
+        }
+
+        channels_of_color_type(color_type)?;
+
+        if !is_valid_bit_depth(color_type, bit_depth) {
+
+            return Err(PngError::InvalidBitDepthForColorType { color_type, bit_depth });
+        }
+
+        let ihdr = match self.iter_mut().find(|chunk| chunk.type_name == b"IHDR") {
+
+            Some(chunk) => chunk,
+            None => return Err(PngError::MissingChunk("IHDR")),
+        };
+
+        if ihdr.data.len() != 13 {
+
+            return Err(PngError::InvalidChunkLength { type_name: "IHDR", expected: 13, found: ihdr.data.len() });
+        }
+
+        let mut data = ihdr.data().to_vec();
+
+        data[0 .. 4].copy_from_slice(&width.to_be_bytes());
+        data[4 .. 8].copy_from_slice(&height.to_be_bytes());
+        data[8] = bit_depth;
+        data[9] = color_type;
+        ihdr.set_data(data);
+
+        Ok(())
+    }
+
+    /*
+        Bytes the image data inflates to, every scanline plus its filter type byte, worked out from IHDR alone.
+        For interlaced images each of the seven Adam7 passes (that has pixels) is counted with its own scanlines and filter bytes.
+        Checked arithmetic throughout, dimensions whose product doesn't fit in 64 bits give SizeOverflow rather than a wrapped size.
+     */
+    pub fn estimated_decoded_size (&self) -> Result<u64, PngError> {
+
+        let ihdr = self.ihdr()?;
+
+        let (width, height) = self.dimensions().ok_or(PngError::MissingChunk("IHDR"))?;
+        let bits_per_pixel = channels_of_color_type(ihdr[9])? as u64 * ihdr[8] as u64;
+
+        let passes: &[(u32, u32, u32, u32)] = if ihdr[12] == 1 { &ADAM7_PASSES } else { &[(0, 0, 1, 1)] };
+
+        let mut size: u64 = 0;
+
+        for (x, y, step_x, step_y) in passes.iter() {
+
+            let pass_width = (width as u64 + (step_x - 1 - x) as u64) / *step_x as u64;
+            let pass_height = (height as u64 + (step_y - 1 - y) as u64) / *step_y as u64;
+
+            if pass_width == 0 || pass_height == 0 {
+
+                continue;
+            }
+
+            let scanline = pass_width.checked_mul(bits_per_pixel).ok_or(PngError::SizeOverflow)?.checked_add(7).ok_or(PngError::SizeOverflow)? / 8 + 1;
+
+            size = pass_height.checked_mul(scanline).and_then(|bytes| size.checked_add(bytes)).ok_or(PngError::SizeOverflow)?;
+        }
+
+        Ok(size)
+    }
+
+    /*
+        Deletes every chunk of type @type_name and returns how many were deleted, the remaining chunks keep their order.
+        Critical chunks (IHDR, IDAT, IEND and, for indexed images, PLTE) are only deleted when @force is true.
+     */
+    pub fn remove_chunks_by_type (&mut self, type_name: &str, force: bool) -> Result<usize, PngError> {
+
+        let critical = matches!(type_name, "IHDR" | "IDAT" | "IEND") || (type_name == "PLTE" && self.color_type() == Some(COLOR_TYPE_INDEXED));
+
+        if critical && !force {
+
+            return Err(PngError::CriticalChunk(type_name.to_string()));
+        }
+
+        let mut removed: usize = 0;
+        let mut kept: Vec<Chunk> = Vec::with_capacity(self.chunks.len());
+
+        for chunk in self.chunks.drain(..) {
+
+            if chunk.type_name == type_name.as_bytes() {
+
+                removed += 1;
+            }
+            else {
+
+                kept.push(chunk);
+            }
+        }
+
+        self.chunks = kept;
+
+        Ok(removed)
+    }
+
+    /*
+        Removes every ancillary chunk whose type is not in @keep, e.g. keep tRNS and gAMA as they change how the image renders.
+        Critical chunks are never touched.
+     */
+    pub fn strip_metadata (&mut self, keep: &[&str]) -> StripReport {
+
+        let mut report = StripReport { removed: Vec::new(), bytes_saved: 0 };
+        let mut kept: Vec<Chunk> = Vec::with_capacity(self.chunks.len());
+
+        for chunk in self.chunks.drain(..) {
+
+            if chunk.is_ancillary() && !keep.iter().any(|type_name| chunk.type_name == type_name.as_bytes()) {
+
+                report.removed.push(chunk.get_type_name().into_owned());
+                report.bytes_saved += constants::LENGTH_OF_THREE_FIELDS + chunk.data.len();
+            }
+            else {
+
+                kept.push(chunk);
+            }
+        }
+
+        self.chunks = kept;
+
+        report
+    }
+
+    /*
+        Inverts the colors of an indexed image by rewriting the entries of its PLTE chunk (and that chunk's CRC), image data is not touched.
+        Only indexed (color type 3) images can be inverted this way, any other color type gives back an error.
+     */
+    pub fn inverted (&self) -> Result<Png, PngError> {
+
+        let color_type = match self.color_type() {
+
+            Some(color_type) => color_type,
+            None => return Err(PngError::MissingChunk("IHDR")),
+        };
+
+        if color_type != COLOR_TYPE_INDEXED {
+
+            return Err(PngError::UnsupportedColorType(color_type));
+        }
+
+        let mut png = self.clone();
+
+        match png.iter_mut().find(|chunk| chunk.type_name == b"PLTE") {
+
+            Some(chunk) => {
+
+                let data = chunk.data().iter().map(|entry| 0xFF - entry).collect();
+
+                chunk.set_data(data);
+            }
+            None => return Err(PngError::MissingChunk("PLTE")),
+        }
+
+        Ok(png)
+    }
+
+    /*
+        Recomputes the CRC of every chunk over its type and data and gives back how many stored CRCs changed.
+        The editing methods keep CRCs up to date themselves, this is the safety net after changing chunk fields directly.
+     */
+    pub fn recompute_all_crcs (&mut self) -> usize {
+
+        let mut changed: usize = 0;
+
+        for chunk in self.iter_mut().filter(|chunk| !chunk.is_crc_valid()) {
+
+            chunk.update_crc();
+            changed += 1;
+        }
+
+        changed
+    }
+
+    /*
+        Panics in debug builds, naming the first chunk whose CRC is stale, does nothing in release builds.
+        For code about to write a file it built, to_bytes() itself doesn't call it as it also reproduces files read with bad CRCs.
+     */
+    pub fn debug_assert_crcs_valid (&self) {
+
+        if cfg!(debug_assertions) {
+
+            if let Some((index, chunk)) = self.iter().enumerate().find(|(_, chunk)| !chunk.is_crc_valid()) {
+
+                panic!("CRC of chunk {} ({}) does not match its type and data", index, chunk.get_type_name());
+            }
+        }
+    }
+
+    /*
+        Strict equality, same signature and the same chunks (byte for byte) in the same order
+     */
+    pub fn chunks_eq (&self, other: &Png) -> bool {
+
+        self.signature == other.signature && self.chunks == other.chunks
+    }
+
+    /*
+        Data field of the first chunk of type @type_name, None when the image has no such chunk
+     */
+    fn chunk_data (&self, type_name: &[u8]) -> Option<&Vec<u8>> {
+
+        self.iter().find(|chunk| chunk.type_name == type_name).map(|chunk| &*chunk.data)
+    }
+
+    /*
+        Equality of what the image looks like, IHDR, PLTE, tRNS and the image data have to agree while text, time and other ancillary chunks are ignored.
+        A metadata stripped copy of an image is semantically equal to the original but not chunks_eq() to it.
+        Image data is compared as the joined IDAT stream, two files carrying the same pixels compressed differently are reported unequal.
+     */
+    pub fn semantic_eq (&self, other: &Png) -> bool {
+
+        if self.chunk_data(b"IHDR").is_none() {
+
+            return false;
+        }
+
+        [&b"IHDR"[..], b"PLTE", b"tRNS"].iter().all(|type_name| self.chunk_data(type_name) == other.chunk_data(type_name))
+            && self.get_all_idat_data_as_vec() == other.get_all_idat_data_as_vec()
+    }
+}
+
+impl PartialEq for Png {
+
+    fn eq (&self, other: &Png) -> bool {
+
+        self.chunks_eq(other)
+    }
+}
+
+impl<'a> IntoIterator for &'a Png {
+
+    type Item = &'a Chunk;
+    type IntoIter = std::slice::Iter<'a, Chunk>;
+
+    fn into_iter (self) -> Self::IntoIter {
+
+        self.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut Png {
+
+    type Item = &'a mut Chunk;
+    type IntoIter = std::slice::IterMut<'a, Chunk>;
+
+    fn into_iter (self) -> Self::IntoIter {
+
+        self.iter_mut()
+    }
+}
+
+impl IntoIterator for Png {
+
+    type Item = Chunk;
+    type IntoIter = std::vec::IntoIter<Chunk>;
+
+    fn into_iter (self) -> Self::IntoIter {
+
+        self.chunks.into_iter()
+    }
+}
+
+/**
+    Checks the signature and that every chunk lies within @data before handing it to Png::new(), which trusts its input.
+    Input cut short in the middle of a chunk is an error rather than a panic.
+
+    ```
+    use png::{Chunk, Png};
+
+    let mut bytes = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+    bytes.extend(Chunk::from_parts(b"IHDR", vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]).to_bytes());
+    bytes.extend(Chunk::from_parts(b"IEND", Vec::new()).to_bytes());
+
+    let png = Png::try_from(&bytes[..]).unwrap();
+
+    assert_eq!(png.dimensions(), Some((1, 1)));
+    assert!(Png::try_from(&bytes[.. bytes.len() - 5]).is_err());
+    assert!(Png::try_from(&b"GIF89a"[..]).is_err());
+    ```
+ */
+impl TryFrom<&[u8]> for Png {
+
+    type Error = PngError;
+
+    fn try_from (data: &[u8]) -> Result<Self, Self::Error> {
+
+        if data.len() < constants::LENGTH_OF_SIGNATURE || data[0 .. constants::LENGTH_OF_SIGNATURE] != constants::PNG_SIGNATURE {
+
+            return Err(PngError::InvalidSignature);
+        }
+
+        let mut index: usize = constants::LENGTH_OF_SIGNATURE;
+
+        while index < data.len() {
+
+            if data.len() - index < constants::LENGTH_OF_THREE_FIELDS {
+
+                return Err(PngError::TruncatedChunk { offset: index });
+            }
+
+            let length = u32::from_be_bytes([data[index], data[index + 1], data[index + 2], data[index + 3]]) as usize;
+
+            if data.len() - index - constants::LENGTH_OF_THREE_FIELDS < length {
+
+                return Err(PngError::TruncatedChunk { offset: index });
+            }
+
+            index = index + constants::LENGTH_OF_THREE_FIELDS + length;
+        }
+
+        Ok(Png::new(data.to_vec()))
+    }
+}
+
+/**
+    Same checks as for a slice, for bytes already owned
+
+    ```
+    use png::{Chunk, Png};
+
+    let mut bytes = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+    bytes.extend(Chunk::from_parts(b"IEND", Vec::new()).to_bytes());
+
+    assert_eq!(Png::try_from(bytes).unwrap().get_chunks().len(), 1);
+    ```
+ */
+impl TryFrom<Vec<u8>> for Png {
+
+    type Error = PngError;
+
+    fn try_from (data: Vec<u8>) -> Result<Self, Self::Error> {
+
+        Png::try_from(data.as_slice())
+    }
+}
+
+/**
+    Reads the file at @path, a file that can't be read gives PngError::Io
+
+    ```
+    use png::{Chunk, Png};
+    use std::path::Path;
+
+    let mut bytes = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+    bytes.extend(Chunk::from_parts(b"IEND", Vec::new()).to_bytes());
+
+    let path = std::env::temp_dir().join("png-try-from-path-doctest.png");
+
+    std::fs::write(&path, &bytes).unwrap();
+
+    assert_eq!(Png::try_from(path.as_path()).unwrap().to_bytes(), bytes);
+    assert!(Png::try_from(Path::new("/no/such/file.png")).is_err());
+
+    std::fs::remove_file(&path).unwrap();
+    ```
+ */
+impl TryFrom<&Path> for Png {
+
+    type Error = PngError;
+
+    fn try_from (path: &Path) -> Result<Self, Self::Error> {
+
+        Png::try_from(std::fs::read(path)?)
+    }
+}
+
+/**
+    The file bytes, as Png::to_bytes() gives them
+
+    ```
+    use png::{Chunk, Png};
+
+    let mut bytes = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+    bytes.extend(Chunk::from_parts(b"IEND", Vec::new()).to_bytes());
+
+    let png = Png::try_from(&bytes[..]).unwrap();
+    let written: Vec<u8> = (&png).into();
+
+    assert_eq!(written, bytes);
+    ```
+ */
+impl From<&Png> for Vec<u8> {
+
+    fn from (png: &Png) -> Self {
+
+        png.to_bytes()
+    }
+}
+
+/*
+    Number of data bytes Debug shows of a chunk before cutting it short with ".."
+ */
+const DEBUG_DATA_PREVIEW_LENGTH: usize = 16;
+
+impl fmt::Debug for Chunk {
+
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+
+        let preview = &self.data[.. self.data.len().min(DEBUG_DATA_PREVIEW_LENGTH)];
+
+        f.debug_struct("Chunk")
+            .field("type", &self.get_type_name())
+            .field("length", &self.data.len())
+            .field("crc", &format_args!("{:02x?}", self.crc))
+            .field("data", &format_args!("{:02x?}{}", preview, if self.data.len() > preview.len() { ".." } else { "" }))
+            .finish()
+    }
+}
+
+impl fmt::Debug for Png {
+
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+
+        f.debug_struct("Png")
+            .field("signature", &format_args!("{:02x?}", self.signature))
+            .field("chunks", &self.chunks)
+            .finish()
+    }
+}
+
+/*
+    One line per chunk, its type and data length, e.g. "IHDR 13 bytes (800x600, RGB8)". IHDR lines also give dimensions and pixel format.
+ */
+impl fmt::Display for Png {
+
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+
+        for chunk in self {
+
+            write!(f, "{} {} bytes", chunk.get_type_name(), chunk.data.len())?;
+
+            if chunk.type_name == b"IHDR" && chunk.data.len() == 13 {
+
+                let width = u32::from_be_bytes([chunk.data[0], chunk.data[1], chunk.data[2], chunk.data[3]]);
+                let height = u32::from_be_bytes([chunk.data[4], chunk.data[5], chunk.data[6], chunk.data[7]]);
+
+                let format = match chunk.data[9] {
+
+                    COLOR_TYPE_GRAYSCALE => "Gray",
+                    COLOR_TYPE_TRUECOLOR => "RGB",
+                    COLOR_TYPE_INDEXED => "Indexed",
+                    COLOR_TYPE_GRAYSCALE_ALPHA => "GrayAlpha",
+                    COLOR_TYPE_TRUECOLOR_ALPHA => "RGBA",
+                    _ => "Unknown",
+                };
+
+                write!(f, " ({}x{}, {}{})", width, height, format, chunk.data[8])?;
+            }
+
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::hint::black_box;
+
+    /*
+        The system allocator, counting the allocations each thread makes so a test can check a call allocates nothing
+     */
+    struct CountingAllocator;
+
+    thread_local! {
+
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+
+        unsafe fn alloc (&self, layout: Layout) -> *mut u8 {
+
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc (&self, ptr: *mut u8, layout: Layout) {
+
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations () -> usize {
+
+        ALLOCATIONS.with(|count| count.get())
+    }
+
+    /*
+        The fixture with the last CRC byte of its tEXt chunk (chunk 1) flipped
+     */
+    fn corrupted_text_crc () -> Vec<u8> {
+
+        let mut bytes = fixtures::rgb();
+        let png = Png::try_from(&bytes[..]).unwrap();
+        let end = png.chunk_offsets()[1] as usize + png.chunk_at(1).unwrap().size_in_bytes();
+
+        bytes[end - 1] ^= 0xFF;
+
+        bytes
+    }
+
+    #[test]
+    fn corrupted_crc_fails_under_verify () {
+
+        let error = Png::parse(&corrupted_text_crc(), &ParseOptions { crc_mode: CrcMode::Verify }).err().unwrap();
+
+        assert_eq!(error, PngError::CrcMismatch { index: 1, type_name: "tEXt".to_string() });
+    }
+
+    #[test]
+    fn corrupted_crc_is_a_warning_under_warn () {
+
+        let (png, warnings) = Png::parse(&corrupted_text_crc(), &ParseOptions { crc_mode: CrcMode::Warn }).unwrap();
+
+        assert_eq!(png.get_chunks().len(), 4);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].chunk_index, Some(1));
+        assert_eq!(warnings[0].severity, validate::Severity::Warning);
+    }
+
+    #[test]
+    fn corrupted_crc_is_not_looked_at_under_ignore () {
+
+        let bytes = corrupted_text_crc();
+        let (png, warnings) = Png::parse(&bytes, &ParseOptions { crc_mode: CrcMode::Ignore }).unwrap();
+        let (warned, _) = Png::parse(&bytes, &ParseOptions { crc_mode: CrcMode::Warn }).unwrap();
+
+        assert!(warnings.is_empty());
+        assert!(png == warned);
+        assert_eq!(png.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn add_chunk_in_scrambled_order_keeps_the_file_valid () {
+
+        let mut png = Png::try_from(fixtures::rgb()).unwrap();
+
+        for (type_name, data) in [
+
+            (b"hIST", vec![0; 4]),
+            (b"tIME", vec![0x07, 0xEA, 10, 16, 12, 0, 0]),
+            (b"tRNS", vec![0; 6]),
+            (b"pHYs", vec![0, 0, 0x0B, 0x13, 0, 0, 0x0B, 0x13, 1]),
+            (b"bKGD", vec![0; 6]),
+            (b"PLTE", vec![0; 6]),
+            (b"zzZz", vec![1, 2, 3]),
+            (b"gAMA", 45455u32.to_be_bytes().to_vec()),
+            (b"sBIT", vec![8, 8, 8]),
+        ] {
+
+            png.add_chunk(Chunk::from_parts(type_name, data)).unwrap();
+        }
+
+        let report = png.validate();
+        let order: Vec<String> = png.iter().map(|chunk| chunk.get_type_name().into_owned()).collect();
+
+        assert!(report.is_valid(), "{}", report);
+        assert_eq!(order, ["IHDR", "tEXt", "gAMA", "sBIT", "PLTE", "hIST", "tRNS", "pHYs", "bKGD", "IDAT", "tIME", "zzZz", "IEND"]);
+    }
+
+    #[test]
+    fn add_chunk_refuses_a_second_ihdr_or_iend () {
+
+        let mut png = Png::try_from(fixtures::rgb()).unwrap();
+
+        assert_eq!(png.add_chunk(Chunk::from_parts(b"IHDR", fixtures::ihdr(1, 1, 8, 0))), Err(PngError::DuplicateChunk("IHDR".to_string())));
+        assert_eq!(png.add_chunk(Chunk::from_parts(b"IEND", Vec::new())), Err(PngError::DuplicateChunk("IEND".to_string())));
+        assert_eq!(png.get_chunks().len(), 4);
+    }
+
+
+    #[test]
+    fn non_ascii_chunk_type_does_not_panic () {
+
+        let bytes = fixtures::file(&[
+
+            (b"IHDR", fixtures::ihdr(2, 2, 8, 2)),
+            (&[0xFF, 0xFE, 0x00, 0x41], vec![1, 2, 3]),
+            (b"IDAT", vec![0x78, 0x01, 0x01, 0x02, 0x03]),
+            (b"IEND", Vec::new()),
+        ]);
+
+        let png = Png::try_from(&bytes[..]).unwrap();
+        let chunk = png.chunk_at(1).unwrap();
+        let report = png.validate();
+
+        assert_eq!(chunk.get_type_name(), "\u{FFFD}\u{FFFD}\u{0}A");
+        assert!(chunk.is_crc_valid());
+        assert_eq!(report.errors().filter(|issue| issue.chunk_index == Some(1)).count(), 1, "{}", report);
+        assert_eq!(png.to_bytes(), bytes);
+        assert_eq!(png.get_all_idat_data_as_vec(), [0x78, 0x01, 0x01, 0x02, 0x03]);
+        assert_eq!(png.metadata(true).chunks.len(), 4);
+        assert!(png.to_string().contains("\u{FFFD}\u{FFFD}"));
+    }
+
+
+    #[test]
+    fn iend_offset_is_twelve_bytes_from_the_end () {
+
+        let bytes = fixtures::rgb();
+        let mut png = Png::try_from(&bytes[..]).unwrap();
+
+        assert_eq!(png.chunk_offsets(), [8, 33, 58, 75]);
+        assert_eq!(png.chunk_offsets().last(), Some(&(bytes.len() as u64 - 12)));
+        assert_eq!(png.metadata(false).chunks.last().map(|chunk| chunk.offset), Some(bytes.len() as u64 - 12));
+
+        png.add_text("Comment", "moves IEND").unwrap();
+
+        assert_eq!(png.chunk_offsets().last(), Some(&(png.to_bytes().len() as u64 - 12)));
+    }
+
+    #[test]
+    fn remove_chunks_by_type_refuses_critical_chunks_without_force () {
+
+        let bytes = fixtures::file(&[
+
+            (b"IHDR", fixtures::ihdr(2, 2, 8, 3)),
+            (b"tEXt", b"Title\0One".to_vec()),
+            (b"PLTE", vec![0; 6]),
+            (b"tEXt", b"Title\0Two".to_vec()),
+            (b"IDAT", vec![0x78, 0x01, 0x01, 0x02, 0x03]),
+            (b"IEND", Vec::new()),
+        ]);
+
+        let mut png = Png::try_from(bytes).unwrap();
+
+        for type_name in ["IHDR", "PLTE", "IDAT", "IEND"] {
+
+            assert_eq!(png.remove_chunks_by_type(type_name, false), Err(PngError::CriticalChunk(type_name.to_string())));
+        }
+
+        assert_eq!(png.get_chunks().len(), 6);
+        assert_eq!(png.remove_chunks_by_type("tEXt", false), Ok(2));
+        assert_eq!(png.remove_chunks_by_type("tEXt", false), Ok(0));
+        assert_eq!(png.remove_chunks_by_type("PLTE", true), Ok(1));
+
+        let order: Vec<String> = png.iter().map(|chunk| chunk.get_type_name().into_owned()).collect();
+
+        assert_eq!(order, ["IHDR", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn strip_metadata_keeps_critical_chunks_and_the_keep_list () {
+
+        let bytes = fixtures::file(&[
+
+            (b"IHDR", fixtures::ihdr(2, 2, 8, 3)),
+            (b"gAMA", 45455u32.to_be_bytes().to_vec()),
+            (b"tEXt", b"Title\0Fixture".to_vec()),
+            (b"PLTE", vec![0; 6]),
+            (b"pHYs", vec![0, 0, 0x0B, 0x13, 0, 0, 0x0B, 0x13, 1]),
+            (b"IDAT", vec![0x78, 0x01, 0x01, 0x02, 0x03]),
+            (b"tIME", vec![0x07, 0xEA, 10, 16, 12, 0, 0]),
+            (b"IEND", Vec::new()),
+        ]);
+
+        let original = Png::try_from(&bytes[..]).unwrap();
+        let mut stripped = original.clone();
+        let report = stripped.strip_metadata(&["gAMA"]);
+        let order: Vec<String> = stripped.iter().map(|chunk| chunk.get_type_name().into_owned()).collect();
+
+        assert_eq!(order, ["IHDR", "gAMA", "PLTE", "IDAT", "IEND"]);
+        assert_eq!(report.removed, ["tEXt", "pHYs", "tIME"]);
+        assert_eq!(report.bytes_saved, (12 + 13) + (12 + 9) + (12 + 7));
+        assert_eq!(stripped.to_bytes().len(), bytes.len() - report.bytes_saved);
+        assert_eq!(original.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn set_ihdr_refreshes_length_and_crc () {
+
+        let mut png = Png::try_from(fixtures::rgb()).unwrap();
+
+        png.set_ihdr(640, 480, 16, COLOR_TYPE_TRUECOLOR).unwrap();
+
+        let ihdr = png.chunk_at(0).unwrap();
+
+        assert_eq!(ihdr.data(), &fixtures::ihdr(640, 480, 16, COLOR_TYPE_TRUECOLOR)[..]);
+        assert_eq!(ihdr.length, 13u32.to_be_bytes());
+        assert!(ihdr.is_crc_valid());
+        assert_eq!(png.dimensions(), Some((640, 480)));
+
+        /* Every chunk, the rewritten IHDR and the writers' chunks, passes CRC verification when read back */
+        png.set_dpi(300).unwrap();
+        png.set_transparent_color(Pixel::Rgb(1, 2, 3)).unwrap();
+
+        assert!(Png::parse(&png.to_bytes(), &ParseOptions { crc_mode: CrcMode::Verify }).is_ok());
+
+        assert_eq!(png.set_ihdr(1, 1, 8, COLOR_TYPE_INDEXED), Ok(()));
+        assert_eq!(png.set_ihdr(1, 1, 16, COLOR_TYPE_INDEXED), Err(PngError::InvalidBitDepthForColorType { color_type: COLOR_TYPE_INDEXED, bit_depth: 16 }));
+        assert_eq!(png.set_ihdr(0, 1, 8, COLOR_TYPE_INDEXED), Err(PngError::InvalidDimensions { width: 0, height: 1 }));
+
+        png.remove_chunk(0);
+
+        assert_eq!(png.set_ihdr(1, 1, 8, COLOR_TYPE_GRAYSCALE), Err(PngError::MissingChunk("IHDR")));
+    }
+
+    #[test]
+    fn update_crc_and_set_data_keep_the_fields_consistent () {
+
+        let mut chunk = Chunk::from_parts(b"tEXt", b"Title\0Fixture".to_vec());
+
+        assert!(chunk.is_crc_valid());
+
+        chunk.data_mut()[0] = b't';
+
+        assert!(!chunk.is_crc_valid());
+
+        chunk.update_crc();
+
+        assert!(chunk.is_crc_valid());
+        assert_eq!(chunk.crc, crc::chunk_crc(b"tEXt", b"title\0Fixture").to_be_bytes());
+
+        chunk.set_data(b"Comment\0longer than before".to_vec());
+
+        assert!(chunk.is_crc_valid());
+        assert_eq!(chunk.length, 26u32.to_be_bytes());
+        assert_eq!(chunk.size_in_bytes(), 12 + 26);
+        assert_eq!(Chunk::new(chunk.to_bytes()).data(), b"Comment\0longer than before");
+    }
+
+    #[test]
+    fn merge_idat_leaves_one_chunk_with_the_same_data () {
+
+        let bytes = fixtures::file(&[
+
+            (b"IHDR", fixtures::ihdr(2, 2, 8, 2)),
+            (b"IDAT", vec![0x78, 0x01]),
+            (b"IDAT", vec![0x01, 0x02]),
+            (b"IDAT", Vec::new()),
+            (b"IDAT", vec![0x03]),
+            (b"tIME", vec![0x07, 0xEA, 10, 16, 12, 0, 0]),
+            (b"IEND", Vec::new()),
+        ]);
+
+        let mut png = Png::try_from(&bytes[..]).unwrap();
+        let joined = png.get_all_idat_data_as_vec();
+
+        png.merge_idat().unwrap();
+
+        let order: Vec<String> = png.iter().map(|chunk| chunk.get_type_name().into_owned()).collect();
+
+        assert_eq!(order, ["IHDR", "IDAT", "tIME", "IEND"]);
+        assert_eq!(png.get_all_idat_data_as_vec(), joined);
+        assert_eq!(joined, [0x78, 0x01, 0x01, 0x02, 0x03]);
+        assert_eq!(png.to_bytes().len(), bytes.len() - 3 * 12);
+        assert!(png.chunk_at(1).unwrap().is_crc_valid());
+
+        png.remove_chunks_by_type("IDAT", true).unwrap();
+
+        assert_eq!(png.merge_idat(), Err(PngError::MissingChunk("IDAT")));
+    }
+
+    #[test]
+    fn stripped_copy_is_semantically_but_not_chunk_equal () {
+
+        let original = Png::try_from(fixtures::rgb()).unwrap();
+        let mut stripped = original.clone();
+
+        assert!(original.chunks_eq(&stripped));
+
+        stripped.strip_metadata(&[]);
+
+        assert!(original.semantic_eq(&stripped));
+        assert!(stripped.semantic_eq(&original));
+        assert!(!original.chunks_eq(&stripped));
+
+        stripped.set_ihdr(2, 2, 8, COLOR_TYPE_TRUECOLOR_ALPHA).unwrap();
+
+        assert!(!original.semantic_eq(&stripped));
+    }
+
+    #[test]
+    fn borrowing_accessors_do_not_allocate () {
+
+        let png = Png::try_from(fixtures::rgb()).unwrap();
+        let chunk = png.chunk_at(1).unwrap();
+        let before = allocations();
+
+        black_box(chunk.data());
+        black_box(chunk.type_bytes());
+        black_box(png.signature());
+        black_box(chunk.type_name == b"tEXt");
+
+        assert_eq!(allocations(), before);
+
+        /* The counter does see allocations */
+        black_box(chunk.to_bytes());
+
+        assert!(allocations() > before);
+    }
+
+    #[test]
+    fn clones_share_data_until_one_is_changed () {
+
+        let png = Png::try_from(fixtures::rgb()).unwrap();
+        let mut copy = png.clone();
+
+        for (chunk, cloned) in png.iter().zip(copy.iter()) {
+
+            assert!(Arc::ptr_eq(&chunk.data, &cloned.data));
+        }
+
+        copy.iter_mut().nth(1).unwrap().data_mut()[0] = b't';
+
+        assert!(!Arc::ptr_eq(&png.chunk_at(1).unwrap().data, &copy.chunk_at(1).unwrap().data));
+        assert!(Arc::ptr_eq(&png.chunk_at(2).unwrap().data, &copy.chunk_at(2).unwrap().data));
+        assert_eq!(png.chunk_at(1).unwrap().data(), b"Title\0Fixture");
+        assert_eq!(copy.chunk_at(1).unwrap().data(), b"title\0Fixture");
+        assert_eq!(png.to_bytes(), fixtures::rgb());
+    }
+
+    #[test]
+    fn joined_idat_data_is_allocated_exactly () {
+
+        let bytes = fixtures::file(&[
+
+            (b"IHDR", fixtures::ihdr(2, 2, 8, 2)),
+            (b"IDAT", vec![0x78, 0x01, 0x01]),
+            (b"tEXt", b"Title\0Between".to_vec()),
+            (b"IDAT", vec![0x02; 37]),
+            (b"IDAT", vec![0x03]),
+            (b"IEND", Vec::new()),
+        ]);
+
+        let data = Png::try_from(bytes).unwrap().get_all_idat_data_as_vec();
+
+        assert_eq!(data.len(), 41);
+        assert_eq!(data.capacity(), data.len());
+    }
+
+    #[test]
+    fn debug_and_display_snapshots () {
+
+        let png = Png::try_from(fixtures::rgb()).unwrap();
+        let debug = format!("{:?}", png);
+
+        assert_eq!(png.to_string(), "IHDR 13 bytes (2x2, RGB8)\ntEXt 13 bytes\nIDAT 5 bytes\nIEND 0 bytes\n");
+        assert_eq!(format!("{:?}", png.chunk_at(2).unwrap()), "Chunk { type: \"IDAT\", length: 5, crc: [97, 6f, cc, ad], data: [78, 01, 01, 02, 03] }");
+        assert!(debug.starts_with("Png { signature: [89, 50, 4e, 47, 0d, 0a, 1a, 0a], chunks: [Chunk { type: \"IHDR\", length: 13, "), "{}", debug);
+        assert!(debug.ends_with("Chunk { type: \"IEND\", length: 0, crc: [ae, 42, 60, 82], data: [] }] }"), "{}", debug);
+
+        /* Data is cut off after 16 bytes, ".." marks the cut */
+        assert_eq!(format!("{:?}", Chunk::from_parts(b"tEXt", (0 .. 16).collect())), "Chunk { type: \"tEXt\", length: 16, crc: [4b, ce, 88, 6b], data: [00, 01, 02, 03, 04, 05, 06, 07, 08, 09, 0a, 0b, 0c, 0d, 0e, 0f] }");
+        assert_eq!(format!("{:?}", Chunk::from_parts(b"tEXt", (0 .. 17).collect())), "Chunk { type: \"tEXt\", length: 17, crc: [15, 9e, 89, b1], data: [00, 01, 02, 03, 04, 05, 06, 07, 08, 09, 0a, 0b, 0c, 0d, 0e, 0f].. }");
+    }
+
+    #[test]
+    fn truncated_input_is_an_error_not_a_panic () {
+
+        let bytes = fixtures::rgb();
+        let png = Png::try_from(&bytes[..]).unwrap();
+        let boundaries: Vec<usize> = png.chunk_offsets().iter().map(|offset| *offset as usize).chain([bytes.len()]).collect();
+
+        for length in 0 .. bytes.len() {
+
+            let result = Png::try_from(&bytes[.. length]);
+
+            /* Cut exactly between two chunks the file is still well framed, just missing its tail */
+            if boundaries.contains(&length) {
+
+                assert!(result.is_ok(), "{} bytes", length);
+            }
+            else if length < 8 {
+
+                assert_eq!(result, Err(PngError::InvalidSignature), "{} bytes", length);
+            }
+            else {
+
+                assert!(matches!(result, Err(PngError::TruncatedChunk { .. })), "{} bytes", length);
+            }
+        }
+    }
+
+    #[test]
+    fn pixel_and_scanline_sizes () {
+
+        let png_of = |width: u32, bit_depth: u8, color_type: u8| Png::try_from(fixtures::file(&[(b"IHDR", fixtures::ihdr(width, 1, bit_depth, color_type)), (b"IEND", Vec::new())])).unwrap();
+
+        /* 1 bit gray packs 8 pixels a byte, a partial byte rounds up */
+        let gray = png_of(9, 1, COLOR_TYPE_GRAYSCALE);
+
+        assert_eq!(gray.bits_per_pixel(), Some(1));
+        assert_eq!(gray.bytes_per_scanline(), Some(2));
+        assert_eq!(png_of(8, 1, COLOR_TYPE_GRAYSCALE).bytes_per_scanline(), Some(1));
+
+        let rgba = png_of(3, 16, COLOR_TYPE_TRUECOLOR_ALPHA);
+
+        assert_eq!(rgba.bits_per_pixel(), Some(64));
+        assert_eq!(rgba.bytes_per_scanline(), Some(24));
+
+        assert_eq!(png_of(3, 8, 5).bits_per_pixel(), None);
+    }
+
+    #[test]
+    fn decoded_size_estimates_do_not_wrap () {
+
+        let png_of = |width: u32, height: u32, bit_depth: u8, color_type: u8, interlace: u8| {
+
+            let mut ihdr = fixtures::ihdr(width, height, bit_depth, color_type);
+
+            ihdr[12] = interlace;
+
+            Png::try_from(fixtures::file(&[(b"IHDR", ihdr), (b"IEND", Vec::new())])).unwrap()
+        };
+
+        /* Every scanline has its filter type byte, Adam7 passes each have their own */
+        assert_eq!(png_of(8, 8, 8, COLOR_TYPE_TRUECOLOR, 0).estimated_decoded_size(), Ok(8 * (24 + 1)));
+        assert_eq!(png_of(8, 8, 8, COLOR_TYPE_TRUECOLOR, 1).estimated_decoded_size(), Ok(4 + 4 + 7 + 14 + 26 + 52 + 100));
+
+        /* Past u32 but within u64 */
+        assert_eq!(png_of(65536, 65536, 8, COLOR_TYPE_TRUECOLOR_ALPHA, 0).estimated_decoded_size(), Ok(65536 * (65536 * 4 + 1)));
+
+        let huge = png_of(u32::MAX, u32::MAX, 1, COLOR_TYPE_GRAYSCALE, 1);
+        let estimate = huge.estimated_decoded_size().unwrap();
+
+        assert!(estimate > u32::MAX as u64 * (u32::MAX as u64 / 8));
+        assert_eq!(DecodeLimits::default().check(&huge), Err(PngError::DecodeLimitExceeded { limit: 256 * 1024 * 1024, estimate }));
+
+        /* Past u64, with and without interlacing */
+        for interlace in [0, 1] {
+
+            let png = png_of(u32::MAX, u32::MAX, 16, COLOR_TYPE_TRUECOLOR_ALPHA, interlace);
+
+            assert_eq!(png.estimated_decoded_size(), Err(PngError::SizeOverflow));
+            assert_eq!(DecodeLimits { max_decoded_bytes: u64::MAX }.check(&png), Err(PngError::SizeOverflow));
+        }
+    }
+
+    #[test]
+    fn chunk_sizes_add_up_to_the_file () {
+
+        let bytes = fixtures::file(&[
+
+            (b"IHDR", fixtures::ihdr(2, 2, 8, 2)),
+            (b"gAMA", 45455u32.to_be_bytes().to_vec()),
+            (b"IDAT", vec![0x78, 0x01]),
+            (b"IDAT", vec![0x01, 0x02, 0x03]),
+            (b"tEXt", b"Title\0Fixture".to_vec()),
+            (b"IEND", Vec::new()),
+        ]);
+
+        let png = Png::try_from(&bytes[..]).unwrap();
+        let chunks: usize = png.iter().map(|chunk| chunk.size_in_bytes()).sum();
+
+        assert_eq!(chunks + 8, png.to_bytes().len());
+        assert_eq!(png.size_in_bytes(), bytes.len());
+        assert_eq!(png.idat_size(), (12 + 2) + (12 + 3));
+        assert_eq!(png.metadata_size(), (12 + 4) + (12 + 13));
+        assert_eq!(png.chunk_at(5).unwrap().size_in_bytes(), 12);
+    }
+
+    #[test]
+    fn empty_idat_and_ancillary_chunks_change_nothing () {
+
+        let (expected, _) = Png::parse(&fixtures::rgb(), &ParseOptions { crc_mode: CrcMode::Verify }).unwrap();
+
+        for position in 0 .. 3 {
+
+            let bytes = fixtures::rgb_with_empty_idat(position);
+            let (png, warnings) = Png::parse(&bytes, &ParseOptions { crc_mode: CrcMode::Verify }).unwrap();
+            let report = png.validate();
+
+            assert!(warnings.is_empty(), "empty IDAT at {}", position);
+            assert!(report.is_valid(), "empty IDAT at {}: {}", position, report);
+            assert_eq!(png.get_chunks().len(), 7);
+            assert_eq!(png.get_all_idat_data_as_vec(), expected.get_all_idat_data_as_vec());
+            assert_eq!(png.to_bytes(), bytes);
+            assert!(png.semantic_eq(&expected));
+
+            let empty: Vec<&Chunk> = png.iter().filter(|chunk| chunk.data().is_empty()).collect();
+
+            assert_eq!(empty.len(), 3);
+            assert!(empty.iter().all(|chunk| chunk.is_crc_valid() && chunk.size_in_bytes() == 12));
+        }
+    }
+
+    #[test]
+    fn write_to_writes_the_same_bytes_as_to_bytes () {
+
+        let png = Png::try_from(fixtures::rgb_with_empty_idat(1)).unwrap();
+        let mut written: Vec<u8> = Vec::new();
+
+        assert_eq!(png.write_to(&mut written).unwrap(), written.len() as u64);
+        assert_eq!(written, png.to_bytes());
+
+        for chunk in png.iter() {
+
+            let mut written: Vec<u8> = Vec::new();
+
+            assert_eq!(chunk.write_to(&mut written).unwrap(), chunk.size_in_bytes() as u64);
+            assert_eq!(written, chunk.to_bytes());
+        }
+
+        /* A writer that fails hands its error back */
+        let mut full = [0u8; 20];
+
+        assert!(matches!(png.write_to(&mut &mut full[..]), Err(PngError::Io(_))));
+    }
+
 }
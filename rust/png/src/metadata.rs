@@ -0,0 +1,468 @@
+/*
+    lib/rust/png/src/metadata.rs
+    Q@khaa.pk
+ */
+
+use crate::base64;
+use crate::error::PngError;
+use crate::{Chunk, Png};
+
+/*
+    Type, offset, length and CRC of one chunk, the data itself only when Png::metadata() is asked for it
+ */
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkSummary {
+
+    pub type_name: String,
+    /* Byte offset of the chunk's length field, see Png::chunk_offsets() */
+    pub offset: u64,
+    pub length: usize,
+    pub crc: u32,
+    pub crc_valid: bool,
+    /* Data field as base64 */
+    pub payload: Option<String>,
+}
+
+/*
+    Fields of the IHDR chunk
+ */
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct IhdrDetails {
+
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: u8,
+    pub compression_method: u8,
+    pub filter_method: u8,
+    pub interlace_method: u8,
+}
+
+/*
+    Keyword and text of a tEXt chunk or of an iTXt chunk whose text is not compressed
+ */
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextEntry {
+
+    pub keyword: String,
+    pub text: String,
+}
+
+/*
+    Structure of a Png without its pixels, what Png::metadata() gives back
+ */
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PngMetadata {
+
+    /* None when there is no IHDR chunk or it is not 13 bytes long */
+    pub ihdr: Option<IhdrDetails>,
+    pub chunks: Vec<ChunkSummary>,
+    pub text: Vec<TextEntry>,
+}
+
+/*
+    Keywords the PNG specification registers (section 11.3.4.2)
+ */
+pub const KEYWORD_TITLE: &str = "Title";
+pub const KEYWORD_AUTHOR: &str = "Author";
+pub const KEYWORD_DESCRIPTION: &str = "Description";
+pub const KEYWORD_COPYRIGHT: &str = "Copyright";
+pub const KEYWORD_CREATION_TIME: &str = "Creation Time";
+pub const KEYWORD_SOFTWARE: &str = "Software";
+pub const KEYWORD_DISCLAIMER: &str = "Disclaimer";
+pub const KEYWORD_WARNING: &str = "Warning";
+pub const KEYWORD_SOURCE: &str = "Source";
+pub const KEYWORD_COMMENT: &str = "Comment";
+
+pub const REGISTERED_KEYWORDS: [&str; 10] = [KEYWORD_TITLE, KEYWORD_AUTHOR, KEYWORD_DESCRIPTION, KEYWORD_COPYRIGHT, KEYWORD_CREATION_TIME, KEYWORD_SOFTWARE, KEYWORD_DISCLAIMER, KEYWORD_WARNING, KEYWORD_SOURCE, KEYWORD_COMMENT];
+
+const MAX_KEYWORD_LENGTH: usize = 79;
+
+/*
+    Keyword of a text chunk that keeps the rules of section 11.3.4.2, only TextKeyword::new() makes one
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextKeyword(String);
+
+impl TextKeyword {
+
+    /*
+        1 to 79 printable Latin-1 characters (32 - 126 and 161 - 255), no space at either end and never two in a row.
+        InvalidKeyword names the first rule @keyword breaks.
+     */
+    pub fn new (keyword: &str) -> Result<Self, PngError> {
+
+        let length = keyword.chars().count();
+
+        if length == 0 {
+
+            return Err(PngError::InvalidKeyword("keyword is empty, it needs at least 1 character"));
+        }
+
+        if length > MAX_KEYWORD_LENGTH {
+
+            return Err(PngError::InvalidKeyword("keyword is longer than 79 characters"));
+        }
+
+        if !keyword.chars().all(|character| matches!(character as u32, 32 ..= 126 | 161 ..= 255)) {
+
+            return Err(PngError::InvalidKeyword("keyword has a character that is not printable Latin-1"));
+        }
+
+        if keyword.starts_with(' ') {
+
+            return Err(PngError::InvalidKeyword("keyword starts with a space"));
+        }
+
+        if keyword.ends_with(' ') {
+
+            return Err(PngError::InvalidKeyword("keyword ends with a space"));
+        }
+
+        if keyword.contains("  ") {
+
+            return Err(PngError::InvalidKeyword("keyword has consecutive spaces"));
+        }
+
+        Ok(Self(keyword.to_string()))
+    }
+
+    pub fn as_str (&self) -> &str {
+
+        &self.0
+    }
+
+    pub fn is_registered (&self) -> bool {
+
+        REGISTERED_KEYWORDS.contains(&self.0.as_str())
+    }
+
+    /*
+        Latin-1 bytes of the keyword as a text chunk stores them, every character is known to have one
+     */
+    fn to_latin1 (&self) -> Vec<u8> {
+
+        self.0.chars().map(|character| character as u8).collect()
+    }
+}
+
+/*
+    tEXt is Latin-1, a keyword and the text separated by a zero byte
+ */
+fn text_entry (data: &[u8]) -> Option<TextEntry> {
+
+    let separator = data.iter().position(|byte| *byte == 0)?;
+    let latin1 = |bytes: &[u8]| bytes.iter().map(|byte| *byte as char).collect::<String>();
+
+    Some(TextEntry { keyword: latin1(&data[.. separator]), text: latin1(&data[separator + 1 ..]) })
+}
+
+/*
+    Latin-1 bytes of @text, one per character. Characters past U+00FF have no Latin-1 byte and give NotLatin1, they are never replaced or dropped.
+ */
+pub fn encode_latin1 (text: &str) -> Result<Vec<u8>, PngError> {
+
+    text.chars().map(|character| u8::try_from(character as u32).map_err(|_| PngError::NotLatin1(character))).collect()
+}
+
+/*
+    Data field of a tEXt chunk, keyword, zero byte and the text as Latin-1.
+    A zero byte in @text would be read back as a second separator, so it gives InvalidTextField.
+ */
+fn text_chunk_data (keyword: &TextKeyword, text: &str) -> Result<Vec<u8>, PngError> {
+
+    if text.contains('\0') {
+
+        return Err(PngError::InvalidTextField("text has a zero byte"));
+    }
+
+    let mut data = keyword.to_latin1();
+
+    data.push(0);
+    data.extend(encode_latin1(text)?);
+
+    Ok(data)
+}
+
+/*
+    iTXt is keyword, 0, compression flag, compression method, language tag, 0, translated keyword, 0, UTF-8 text.
+    Compressed text needs inflating, which this crate doesn't do, so those entries are left out.
+ */
+fn international_text_entry (data: &[u8]) -> Option<TextEntry> {
+
+    let keyword_end = data.iter().position(|byte| *byte == 0)?;
+
+    if *data.get(keyword_end + 1)? != 0 {
+
+        return None;
+    }
+
+    let rest = data.get(keyword_end + 3 ..)?;
+    let language_end = rest.iter().position(|byte| *byte == 0)?;
+    let translated_end = language_end + 1 + rest[language_end + 1 ..].iter().position(|byte| *byte == 0)?;
+
+    Some(TextEntry {
+
+        keyword: data[.. keyword_end].iter().map(|byte| *byte as char).collect(),
+        text: String::from_utf8_lossy(&rest[translated_end + 1 ..]).into_owned(),
+    })
+}
+
+/*
+    Text entry of a tEXt or (uncompressed) iTXt chunk, None for any other chunk
+ */
+fn chunk_text_entry (chunk: &Chunk) -> Option<TextEntry> {
+
+    match chunk.type_name.as_slice() {
+
+        b"tEXt" => text_entry(chunk.data()),
+        b"iTXt" => international_text_entry(chunk.data()),
+        _ => None,
+    }
+}
+
+impl Png {
+
+    /*
+        Every tEXt and uncompressed iTXt entry in file order, what metadata() puts in PngMetadata::text without the rest of it.
+        zTXt and compressed iTXt entries are skipped, reading (and writing) them waits on a deflate implementation this crate doesn't have.
+     */
+    pub fn get_text_entries (&self) -> Vec<TextEntry> {
+
+        self.iter().filter_map(chunk_text_entry).collect()
+    }
+
+    /*
+        Chunk types, lengths, CRCs, IHDR fields and text entries for cataloging, pixel data is not decoded.
+        With @include_payload every ChunkSummary also carries the chunk's data as base64.
+     */
+    pub fn metadata (&self, include_payload: bool) -> PngMetadata {
+
+        let mut metadata = PngMetadata { ihdr: None, chunks: Vec::with_capacity(self.chunks.len()), text: Vec::new() };
+
+        for (chunk, offset) in self.iter().zip(self.chunk_offsets()) {
+
+            let data = &chunk.data;
+
+            if chunk.type_name == b"IHDR" && data.len() == 13 {
+
+                metadata.ihdr = Some(IhdrDetails {
+
+                    width: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+                    height: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+                    bit_depth: data[8],
+                    color_type: data[9],
+                    compression_method: data[10],
+                    filter_method: data[11],
+                    interlace_method: data[12],
+                });
+            }
+
+            metadata.text.extend(chunk_text_entry(chunk));
+
+            metadata.chunks.push(ChunkSummary {
+
+                type_name: chunk.get_type_name().into_owned(),
+                offset,
+                length: data.len(),
+                crc: chunk.crc.as_slice().try_into().map(u32::from_be_bytes).unwrap_or(0),
+                crc_valid: chunk.is_crc_valid(),
+                payload: if include_payload { Some(base64::encode(data)) } else { None },
+            });
+        }
+
+        metadata
+    }
+
+    /*
+        Adds a tEXt chunk of @keyword and @text, both written as Latin-1 (see encode_latin1()), at the position add_chunk() picks.
+        @keyword has to pass TextKeyword::new(). For text outside Latin-1 an iTXt chunk, which is UTF-8, is the one to use.
+     */
+    pub fn add_text (&mut self, keyword: &str, text: &str) -> Result<usize, PngError> {
+
+        let data = text_chunk_data(&TextKeyword::new(keyword)?, text)?;
+
+        self.add_chunk(Chunk::from_parts(b"tEXt", data))
+    }
+
+    /*
+        Adds an uncompressed iTXt chunk, @keyword has to pass TextKeyword::new() while @translated_keyword and @text are UTF-8 and can hold any character.
+        @language is a tag such as "en-GB" (or empty when not known), letters, digits and hyphens only. Gives back the chunk's position.
+     */
+    pub fn add_international_text (&mut self, keyword: &str, language: &str, translated_keyword: &str, text: &str) -> Result<usize, PngError> {
+
+        let keyword = TextKeyword::new(keyword)?;
+
+        if !language.chars().all(|character| character.is_ascii_alphanumeric() || character == '-') {
+
+            return Err(PngError::InvalidTextField("language tag has a character other than a letter, digit or hyphen"));
+        }
+
+        if translated_keyword.contains('\0') {
+
+            return Err(PngError::InvalidTextField("translated keyword has a zero byte"));
+        }
+
+        let mut data = keyword.to_latin1();
+
+        data.extend_from_slice(&[0, 0, 0]);
+        data.extend_from_slice(language.as_bytes());
+        data.push(0);
+        data.extend_from_slice(translated_keyword.as_bytes());
+        data.push(0);
+        data.extend_from_slice(text.as_bytes());
+
+        self.add_chunk(Chunk::from_parts(b"iTXt", data))
+    }
+
+    /*
+        Replaces the text of the first tEXt chunk with @keyword and deletes any other, or adds one when there is none. Gives back the chunk's position.
+     */
+    pub fn set_text (&mut self, keyword: &str, text: &str) -> Result<usize, PngError> {
+
+        let keyword = TextKeyword::new(keyword)?;
+        let data = text_chunk_data(&keyword, text)?;
+
+        let mut prefix = keyword.to_latin1();
+        prefix.push(0);
+
+        let has_keyword = |chunk: &Chunk| chunk.type_name == b"tEXt" && chunk.data().starts_with(&prefix);
+
+        let index = match self.iter().position(has_keyword) {
+
+            Some(index) => index,
+            None => return self.add_chunk(Chunk::from_parts(b"tEXt", data)),
+        };
+
+        self.chunks[index].set_data(data);
+
+        let duplicates: Vec<usize> = self.iter().enumerate().skip(index + 1).filter(|(_, chunk)| has_keyword(chunk)).map(|(duplicate, _)| duplicate).collect();
+
+        for duplicate in duplicates.into_iter().rev() {
+
+            self.chunks.remove(duplicate);
+        }
+
+        Ok(index)
+    }
+
+    pub fn set_title (&mut self, title: &str) -> Result<usize, PngError> {
+
+        self.set_text(KEYWORD_TITLE, title)
+    }
+
+    pub fn set_author (&mut self, author: &str) -> Result<usize, PngError> {
+
+        self.set_text(KEYWORD_AUTHOR, author)
+    }
+
+    pub fn set_description (&mut self, description: &str) -> Result<usize, PngError> {
+
+        self.set_text(KEYWORD_DESCRIPTION, description)
+    }
+
+    pub fn set_copyright (&mut self, copyright: &str) -> Result<usize, PngError> {
+
+        self.set_text(KEYWORD_COPYRIGHT, copyright)
+    }
+
+    pub fn set_software (&mut self, software: &str) -> Result<usize, PngError> {
+
+        self.set_text(KEYWORD_SOFTWARE, software)
+    }
+
+    pub fn set_comment (&mut self, comment: &str) -> Result<usize, PngError> {
+
+        self.set_text(KEYWORD_COMMENT, comment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn text_with_a_zero_byte_is_refused () {
+
+        let mut png = Png::try_from(fixtures::rgb()).unwrap();
+
+        assert_eq!(png.add_text("Comment", "before\0after"), Err(PngError::InvalidTextField("text has a zero byte")));
+        assert_eq!(png.set_text("Title", "\0"), Err(PngError::InvalidTextField("text has a zero byte")));
+        assert_eq!(png.get_chunks().len(), 4);
+    }
+
+    #[test]
+    fn latin1_text_round_trips () {
+
+        let mut png = Png::try_from(fixtures::rgb()).unwrap();
+
+        png.add_text("T\u{ED}tulo", "Se\u{F1}or \u{BF}qu\u{E9}?").unwrap();
+
+        let reread = Png::try_from(png.to_bytes()).unwrap();
+        let entry = reread.get_text_entries().into_iter().find(|entry| entry.keyword == "T\u{ED}tulo").unwrap();
+
+        assert_eq!(entry.text, "Se\u{F1}or \u{BF}qu\u{E9}?");
+        assert!(reread.iter().any(|chunk| chunk.data().starts_with(b"T\xEDtulo\0Se\xF1or")));
+        assert_eq!(png.add_text("Comment", "\u{20AC}"), Err(PngError::NotLatin1('\u{20AC}')));
+    }
+
+    #[test]
+    fn keyword_length_limit_is_79 () {
+
+        assert_eq!(TextKeyword::new(&"k".repeat(79)).unwrap().as_str().len(), 79);
+        assert_eq!(TextKeyword::new(&"k".repeat(80)), Err(PngError::InvalidKeyword("keyword is longer than 79 characters")));
+        assert_eq!(TextKeyword::new(&"\u{E9}".repeat(79)).unwrap().as_str().chars().count(), 79);
+    }
+
+    #[test]
+    fn keyword_rules_are_named () {
+
+        assert_eq!(TextKeyword::new(""), Err(PngError::InvalidKeyword("keyword is empty, it needs at least 1 character")));
+        assert_eq!(TextKeyword::new(" Title"), Err(PngError::InvalidKeyword("keyword starts with a space")));
+        assert_eq!(TextKeyword::new("Title "), Err(PngError::InvalidKeyword("keyword ends with a space")));
+        assert_eq!(TextKeyword::new("Creation  Time"), Err(PngError::InvalidKeyword("keyword has consecutive spaces")));
+        assert_eq!(TextKeyword::new("Tab\there"), Err(PngError::InvalidKeyword("keyword has a character that is not printable Latin-1")));
+        assert!(TextKeyword::new(KEYWORD_CREATION_TIME).unwrap().is_registered());
+        assert!(!TextKeyword::new("Camera").unwrap().is_registered());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn metadata_round_trips_through_json () {
+
+        let mut png = Png::try_from(fixtures::rgb()).unwrap();
+
+        png.add_text("Comment", "round trip").unwrap();
+
+        let metadata = png.metadata(true);
+        let json = serde_json::to_string(&metadata).unwrap();
+
+        assert_eq!(serde_json::from_str::<PngMetadata>(&json).unwrap(), metadata);
+        assert!(json.contains("\"round trip\""), "{}", json);
+    }
+
+    #[test]
+    fn text_entries_of_text_and_international_text () {
+
+        let mut png = Png::try_from(fixtures::rgb()).unwrap();
+
+        png.add_text("Software", "png-rust 0.2").unwrap();
+        png.add_international_text("Description", "ja", "\u{8AAC}\u{660E}", "\u{753B}\u{50CF}").unwrap();
+        png.add_international_text("Author", "", "", "Q").unwrap();
+
+        /* Compressed text can't be read, it is left out */
+        png.add_chunk(Chunk::from_parts(b"zTXt", b"Comment\0\0\x78\x01".to_vec())).unwrap();
+
+        let entries = Png::try_from(png.to_bytes()).unwrap().get_text_entries();
+        let pairs: Vec<(&str, &str)> = entries.iter().map(|entry| (entry.keyword.as_str(), entry.text.as_str())).collect();
+
+        assert_eq!(pairs, [("Title", "Fixture"), ("Software", "png-rust 0.2"), ("Description", "\u{753B}\u{50CF}"), ("Author", "Q")]);
+    }
+
+}
@@ -0,0 +1,290 @@
+/*
+    lib/rust/png/src/palette.rs
+    Q@khaa.pk
+ */
+
+use std::collections::{HashMap, HashSet};
+
+use crate::constants::{COLOR_TYPE_TRUECOLOR, COLOR_TYPE_INDEXED, COLOR_TYPE_TRUECOLOR_ALPHA};
+use crate::error::PngError;
+use crate::pixels::PixelBuffer;
+
+/*
+    How the pixels of an indexed image use its palette, what PixelBuffer::palette_usage() gives back
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub struct PaletteUsage {
+
+    /* Number of pixels referring to each palette entry, one count per entry */
+    pub counts: Vec<usize>,
+    /* Entries no pixel refers to */
+    pub unused: Vec<usize>,
+    /* (entry, earlier entry with the same color and alpha) */
+    pub duplicates: Vec<(usize, usize)>,
+}
+
+/*
+    Color and alpha of palette entry @index, entries past the end of @trns are opaque
+ */
+fn palette_entry (palette: &[u8], trns: &[u8], index: usize) -> [u8; 4] {
+
+    [palette[index * 3], palette[index * 3 + 1], palette[index * 3 + 2], *trns.get(index).unwrap_or(&0xFF)]
+}
+
+impl PixelBuffer {
+
+    fn check_indexed (&self, palette: &[u8]) -> Result<(), PngError> {
+
+        if self.color_type != COLOR_TYPE_INDEXED {
+
+            return Err(PngError::UnsupportedColorType(self.color_type));
+        }
+
+        if palette.is_empty() || !palette.len().is_multiple_of(3) || palette.len() > 256 * 3 {
+
+            return Err(PngError::InvalidChunkLength { type_name: "PLTE", expected: (palette.len() / 3).clamp(1, 256) * 3, found: palette.len() });
+        }
+
+        Ok(())
+    }
+
+    /*
+        Counts the pixels of an indexed buffer referring to each entry of @palette (the data field of PLTE), and finds entries
+        no pixel uses and entries repeating an earlier color. @trns is the data field of tRNS, empty when there is none.
+        An index past the end of the palette is reported as SampleOutOfRange.
+     */
+    pub fn palette_usage (&self, palette: &[u8], trns: &[u8]) -> Result<PaletteUsage, PngError> {
+
+        self.check_indexed(palette)?;
+
+        let entries = palette.len() / 3;
+        let mut counts: Vec<usize> = vec![0; entries];
+
+        for index in self.data.iter() {
+
+            match counts.get_mut(*index as usize) {
+
+                Some(count) => *count += 1,
+                None => return Err(PngError::SampleOutOfRange { value: *index as u32, max: entries as u32 - 1 }),
+            }
+        }
+
+        let unused = (0 .. entries).filter(|entry| counts[*entry] == 0).collect();
+
+        let mut duplicates: Vec<(usize, usize)> = Vec::new();
+
+        for entry in 1 .. entries {
+
+            let color = palette_entry(palette, trns, entry);
+
+            if let Some(first) = (0 .. entry).find(|earlier| palette_entry(palette, trns, *earlier) == color) {
+
+                duplicates.push((entry, first));
+            }
+        }
+
+        Ok(PaletteUsage { counts, unused, duplicates })
+    }
+
+    /*
+        Drops unused palette entries, merges duplicate ones and renumbers the pixel indices to match, the colors of the image don't change.
+        Returns the data fields of the new PLTE and tRNS chunks, the tRNS one empty when every remaining entry is opaque.
+        Entries keep their relative order, so entries with alpha stay at the front only if they were there before.
+     */
+    pub fn compact_palette (&mut self, palette: &[u8], trns: &[u8]) -> Result<(Vec<u8>, Vec<u8>), PngError> {
+
+        let usage = self.palette_usage(palette, trns)?;
+
+        let mut remap: Vec<u8> = vec![0; usage.counts.len()];
+        let mut kept: Vec<[u8; 4]> = Vec::new();
+
+        for (entry, slot) in remap.iter_mut().enumerate() {
+
+            let color = palette_entry(palette, trns, entry);
+
+            let target = match kept.iter().position(|other| *other == color) {
+
+                Some(position) => position,
+                None => {
+
+                    /* Unused, unless a later duplicate of it is used */
+                    if !(entry .. usage.counts.len()).any(|other| usage.counts[other] > 0 && palette_entry(palette, trns, other) == color) {
+
+                        continue;
+                    }
+
+                    kept.push(color);
+
+                    kept.len() - 1
+                }
+            };
+
+            *slot = target as u8;
+        }
+
+        /* An image may use none of its entries only when it has no pixels, keep one so PLTE stays valid */
+        if kept.is_empty() {
+
+            kept.push(palette_entry(palette, trns, 0));
+        }
+
+        self.data.iter_mut().for_each(|index| *index = remap[*index as usize]);
+
+        let new_palette: Vec<u8> = kept.iter().flat_map(|color| color[.. 3].to_vec()).collect();
+        let last_translucent = kept.iter().rposition(|color| color[3] != 0xFF);
+        let new_trns: Vec<u8> = match last_translucent {
+
+            Some(last) => kept[..= last].iter().map(|color| color[3]).collect(),
+            None => Vec::new(),
+        };
+
+        Ok((new_palette, new_trns))
+    }
+
+    /*
+        Indexed copy of an 8 bit image of at most 256 distinct colors (alpha included), no color changes. Returns it with the data fields of its PLTE and tRNS chunks.
+        Entries with alpha come first so tRNS ends at the last of them, and it is empty when every color is opaque. More colors than a palette holds give TooManyColors.
+     */
+    pub fn to_indexed (&self) -> Result<(PixelBuffer, Vec<u8>, Vec<u8>), PngError> {
+
+        if self.color_type == COLOR_TYPE_INDEXED {
+
+            return Err(PngError::UnsupportedColorType(self.color_type));
+        }
+
+        if self.bit_depth != 8 {
+
+            return Err(PngError::UnsupportedBitDepth(self.bit_depth));
+        }
+
+        let rgba = self.to_rgba()?;
+
+        let mut colors: Vec<[u8; 4]> = Vec::new();
+        let mut seen: HashSet<[u8; 4]> = HashSet::new();
+
+        for pixel in rgba.data.chunks_exact(4) {
+
+            let color = [pixel[0], pixel[1], pixel[2], pixel[3]];
+
+            if seen.insert(color) {
+
+                if colors.len() == 256 {
+
+                    return Err(PngError::TooManyColors);
+                }
+
+                colors.push(color);
+            }
+        }
+
+        /* Stable, colors keep the order they first appear in within each group */
+        colors.sort_by_key(|color| color[3] == 0xFF);
+
+        let index_of: HashMap<[u8; 4], u8> = colors.iter().enumerate().map(|(index, color)| (*color, index as u8)).collect();
+
+        let data: Vec<u8> = rgba.data.chunks_exact(4).map(|pixel| index_of[&[pixel[0], pixel[1], pixel[2], pixel[3]]]).collect();
+        let palette: Vec<u8> = colors.iter().flat_map(|color| color[.. 3].to_vec()).collect();
+        let trns: Vec<u8> = colors.iter().take_while(|color| color[3] != 0xFF).map(|color| color[3]).collect();
+
+        Ok((PixelBuffer::from_data(self.width, self.height, COLOR_TYPE_INDEXED, 8, data)?, palette, trns))
+    }
+
+    /*
+        Colors of an indexed buffer looked up in @palette, RGBA with the alpha of @trns (entries past its end opaque) or RGB when @trns is empty.
+        An index past the end of the palette is reported as SampleOutOfRange.
+     */
+    pub fn expand_palette (&self, palette: &[u8], trns: &[u8]) -> Result<PixelBuffer, PngError> {
+
+        self.check_indexed(palette)?;
+
+        let entries = palette.len() / 3;
+        let channels = if trns.is_empty() { 3 } else { 4 };
+        let mut data: Vec<u8> = Vec::with_capacity(self.data.len() * channels);
+
+        for index in self.data.iter() {
+
+            if *index as usize >= entries {
+
+                return Err(PngError::SampleOutOfRange { value: *index as u32, max: entries as u32 - 1 });
+            }
+
+            data.extend_from_slice(&palette_entry(palette, trns, *index as usize)[.. channels]);
+        }
+
+        PixelBuffer::from_data(self.width, self.height, if trns.is_empty() { COLOR_TYPE_TRUECOLOR } else { COLOR_TYPE_TRUECOLOR_ALPHA }, 8, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const RED: [u8; 3] = [255, 0, 0];
+    const GREEN: [u8; 3] = [0, 255, 0];
+    const BLUE: [u8; 3] = [0, 0, 255];
+
+    /*
+        Red, an unused green, red again and blue, the pixels use the second red and blue only
+     */
+    fn indexed () -> (PixelBuffer, Vec<u8>) {
+
+        (PixelBuffer::from_data(3, 1, COLOR_TYPE_INDEXED, 8, vec![2, 3, 2]).unwrap(), [RED, GREEN, RED, BLUE].concat())
+    }
+
+    #[test]
+    fn palette_usage_finds_unused_and_duplicate_entries () {
+
+        let (image, palette) = indexed();
+
+        assert_eq!(image.palette_usage(&palette, &[]).unwrap(), PaletteUsage { counts: vec![0, 0, 2, 1], unused: vec![0, 1], duplicates: vec![(2, 0)] });
+
+        /* Different alpha makes the second red a color of its own */
+        assert!(image.palette_usage(&palette, &[0xFF, 0xFF, 0x80]).unwrap().duplicates.is_empty());
+
+        let out_of_range = PixelBuffer::from_data(1, 1, COLOR_TYPE_INDEXED, 8, vec![4]).unwrap();
+
+        assert_eq!(out_of_range.palette_usage(&palette, &[]), Err(PngError::SampleOutOfRange { value: 4, max: 3 }));
+    }
+
+    #[test]
+    fn compact_palette_keeps_an_unused_entry_whose_later_duplicate_is_used () {
+
+        let (mut image, palette) = indexed();
+        let colors = image.expand_palette(&palette, &[]).unwrap();
+
+        let (new_palette, new_trns) = image.compact_palette(&palette, &[]).unwrap();
+
+        assert_eq!(new_palette, [RED, BLUE].concat());
+        assert!(new_trns.is_empty());
+        assert_eq!(image.data, [0, 1, 0]);
+        assert_eq!(image.expand_palette(&new_palette, &new_trns).unwrap().data, colors.data);
+
+        /* tRNS is cut after the last translucent entry that is left */
+        let (mut image, palette) = indexed();
+        let (new_palette, new_trns) = image.compact_palette(&palette, &[0xFF, 0x00, 0xFF, 0x80]).unwrap();
+
+        assert_eq!(new_palette, [RED, BLUE].concat());
+        assert_eq!(new_trns, [0xFF, 0x80]);
+    }
+
+    #[test]
+    fn to_indexed_puts_translucent_colors_first () {
+
+        let rgba = PixelBuffer::from_data(4, 1, COLOR_TYPE_TRUECOLOR_ALPHA, 8, vec![255, 0, 0, 255, 0, 0, 255, 0x80, 255, 0, 0, 255, 0, 255, 0, 255]).unwrap();
+        let (image, palette, trns) = rgba.to_indexed().unwrap();
+
+        assert_eq!(palette, [BLUE, RED, GREEN].concat());
+        assert_eq!(trns, [0x80]);
+        assert_eq!(image.data, [1, 0, 1, 2]);
+    }
+
+    #[test]
+    fn to_indexed_refuses_more_than_256_colors () {
+
+        let colors = |count: usize| PixelBuffer::from_data(count as u32, 1, COLOR_TYPE_TRUECOLOR, 8, (0 .. count).flat_map(|i| [(i % 256) as u8, (i / 256) as u8, 0]).collect()).unwrap();
+
+        assert_eq!(colors(256).to_indexed().unwrap().1.len(), 256 * 3);
+        assert!(matches!(colors(257).to_indexed(), Err(PngError::TooManyColors)));
+    }
+}
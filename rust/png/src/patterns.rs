@@ -0,0 +1,172 @@
+/*
+    lib/rust/png/src/patterns.rs
+    Q@khaa.pk
+ */
+
+use crate::constants::{COLOR_TYPE_GRAYSCALE, COLOR_TYPE_TRUECOLOR, COLOR_TYPE_INDEXED, COLOR_TYPE_GRAYSCALE_ALPHA};
+use crate::error::PngError;
+use crate::pixels::{Pixel, PixelBuffer, channels_of_color_type};
+
+/*
+    Every generator here makes 8 bit buffers, and gives the same pixels for the same arguments in every version of this crate
+ */
+const BIT_DEPTH: u8 = 8;
+
+/*
+    White, yellow, cyan, green, magenta, red, blue and black, the order of the classic color bar test signal
+ */
+const COLOR_BARS: [(u16, u16, u16); 8] = [(255, 255, 255), (255, 255, 0), (0, 255, 255), (0, 255, 0), (255, 0, 255), (255, 0, 0), (0, 0, 255), (0, 0, 0)];
+
+/*
+    Pixel of @color_type made of @samples, which hold at least as many samples as the color type has channels
+ */
+fn pixel_of (color_type: u8, samples: &[u16]) -> Pixel {
+
+    match color_type {
+
+        COLOR_TYPE_GRAYSCALE => Pixel::Gray(samples[0]),
+        COLOR_TYPE_GRAYSCALE_ALPHA => Pixel::GrayAlpha(samples[0], samples[1]),
+        COLOR_TYPE_TRUECOLOR => Pixel::Rgb(samples[0], samples[1], samples[2]),
+        COLOR_TYPE_INDEXED => Pixel::Indexed(samples[0] as u8),
+        _ => Pixel::Rgba(samples[0], samples[1], samples[2], samples[3]),
+    }
+}
+
+/*
+    Squares of @cell by @cell pixels alternating between @color_a (the top left one) and @color_b, a @cell of 0 is taken as 1.
+    Both colors have to be of the same color type, that is the buffer's.
+ */
+pub fn checkerboard (width: u32, height: u32, cell: u32, color_a: Pixel, color_b: Pixel) -> Result<PixelBuffer, PngError> {
+
+    if color_a.color_type() != color_b.color_type() {
+
+        return Err(PngError::IncompatiblePixelFormat { expected: (color_a.color_type(), BIT_DEPTH), found: (color_b.color_type(), BIT_DEPTH) });
+    }
+
+    let cell = cell.max(1);
+
+    PixelBuffer::from_fn(width, height, color_a.color_type(), BIT_DEPTH, |x, y| if (x / cell + y / cell).is_multiple_of(2) { color_a } else { color_b })
+}
+
+/*
+    Every row goes from @from in the first column to @to in the last, each sample interpolated linearly and rounded to the nearest value.
+    Both colors have to be of the same color type, that is the buffer's.
+ */
+pub fn horizontal_gradient (width: u32, height: u32, from: Pixel, to: Pixel) -> Result<PixelBuffer, PngError> {
+
+    let color_type = from.color_type();
+
+    if color_type != to.color_type() {
+
+        return Err(PngError::IncompatiblePixelFormat { expected: (color_type, BIT_DEPTH), found: (to.color_type(), BIT_DEPTH) });
+    }
+
+    let (from, to) = (from.samples(), to.samples());
+    let span = width.saturating_sub(1).max(1) as i64;
+
+    PixelBuffer::from_fn(width, height, color_type, BIT_DEPTH, |x, _| {
+
+        let mut samples = [0u16; 4];
+
+        for (sample, (a, b)) in samples.iter_mut().zip(from.iter().zip(to.iter())) {
+
+            let (a, b) = (*a as i64, *b as i64);
+
+            *sample = (a + ((b - a) * x as i64 * 2 + span).div_euclid(2 * span)) as u16;
+        }
+
+        pixel_of(color_type, &samples)
+    })
+}
+
+/*
+    RGB buffer split into eight vertical bars of equal width, white, yellow, cyan, green, magenta, red, blue and black from left to right.
+    Bar n covers the columns x for which x * 8 / width is n.
+ */
+pub fn color_bars (width: u32, height: u32) -> Result<PixelBuffer, PngError> {
+
+    PixelBuffer::from_fn(width, height, COLOR_TYPE_TRUECOLOR, BIT_DEPTH, |x, _| {
+
+        let (r, g, b) = COLOR_BARS[(x as u64 * COLOR_BARS.len() as u64 / width as u64) as usize];
+
+        Pixel::Rgb(r, g, b)
+    })
+}
+
+/*
+    Uniform random samples (alpha included) from splitmix64 seeded with @seed, in row-major order.
+    The generator is part of this crate rather than rand, so a @seed gives the same image whatever rand version is in use.
+    Indexed buffers get random indices over the full 0 - 255 range.
+ */
+pub fn noise (seed: u64, width: u32, height: u32, color_type: u8) -> Result<PixelBuffer, PngError> {
+
+    let channels = channels_of_color_type(color_type)?;
+    let mut state = seed;
+
+    let mut next = move || {
+
+        state = state.wrapping_add(0x9E3779B97F4A7C15);
+
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+
+        (z ^ (z >> 31)) as u8 as u16
+    };
+
+    PixelBuffer::from_fn(width, height, color_type, BIT_DEPTH, |_, _| {
+
+        let mut samples = [0u16; 4];
+
+        samples[.. channels].iter_mut().for_each(|sample| *sample = next());
+
+        pixel_of(color_type, &samples)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::constants::COLOR_TYPE_TRUECOLOR_ALPHA;
+
+    #[test]
+    fn checkerboard_pixels () {
+
+        let board = checkerboard(4, 4, 2, Pixel::Gray(0), Pixel::Gray(255)).unwrap();
+
+        assert_eq!(board.data, [0, 0, 255, 255, 0, 0, 255, 255, 255, 255, 0, 0, 255, 255, 0, 0]);
+        assert_eq!(checkerboard(3, 2, 0, Pixel::Gray(1), Pixel::Gray(2)).unwrap().data, [1, 2, 1, 2, 1, 2]);
+        assert!(checkerboard(2, 2, 1, Pixel::Gray(0), Pixel::Rgb(0, 0, 0)).is_err());
+    }
+
+    #[test]
+    fn horizontal_gradient_pixels () {
+
+        let gradient = horizontal_gradient(5, 2, Pixel::Gray(0), Pixel::Gray(255)).unwrap();
+
+        assert_eq!(gradient.data, [0, 64, 128, 191, 255, 0, 64, 128, 191, 255]);
+        assert_eq!(horizontal_gradient(3, 1, Pixel::Rgb(255, 0, 10), Pixel::Rgb(0, 255, 10)).unwrap().data, [255, 0, 10, 128, 128, 10, 0, 255, 10]);
+        assert_eq!(horizontal_gradient(1, 1, Pixel::Gray(7), Pixel::Gray(200)).unwrap().data, [7]);
+    }
+
+    #[test]
+    fn color_bars_pixels () {
+
+        let bars = color_bars(16, 1).unwrap();
+        let expected: Vec<u8> = COLOR_BARS.iter().flat_map(|(r, g, b)| [*r as u8, *g as u8, *b as u8].repeat(2)).collect();
+
+        assert_eq!(bars.data, expected);
+        assert_eq!(&color_bars(8, 1).unwrap().data[.. 6], [255, 255, 255, 255, 255, 0]);
+    }
+
+    #[test]
+    fn noise_pixels () {
+
+        /* First outputs of splitmix64 seeded with 0 are 0xE220A8397B1DCDAF, 0x6E789E6AA1B965F4, 0x06C45D188009454F and 0xF88BB8A8724C81EC */
+        assert_eq!(noise(0, 2, 2, COLOR_TYPE_GRAYSCALE).unwrap().data, [0xAF, 0xF4, 0x4F, 0xEC]);
+        assert_eq!(noise(0, 1, 1, COLOR_TYPE_TRUECOLOR_ALPHA).unwrap().data, [0xAF, 0xF4, 0x4F, 0xEC]);
+        assert_eq!(noise(7, 8, 8, COLOR_TYPE_TRUECOLOR).unwrap().data, noise(7, 8, 8, COLOR_TYPE_TRUECOLOR).unwrap().data);
+        assert_ne!(noise(7, 8, 8, COLOR_TYPE_TRUECOLOR).unwrap().data, noise(8, 8, 8, COLOR_TYPE_TRUECOLOR).unwrap().data);
+    }
+}
@@ -0,0 +1,2142 @@
+/*
+    lib/rust/png/src/pixels.rs
+    Q@khaa.pk
+ */
+
+use crate::constants::{COLOR_TYPE_GRAYSCALE, COLOR_TYPE_TRUECOLOR, COLOR_TYPE_INDEXED, COLOR_TYPE_GRAYSCALE_ALPHA, COLOR_TYPE_TRUECOLOR_ALPHA};
+use crate::error::PngError;
+use crate::images::{extract_block, ImageBlock, ImageDataTensorShapeFormat};
+
+/*
+    Decoded pixels of an image. Scanlines are stored top to bottom and carry no filter byte.
+    Only bit depths of 8 and 16 are handled, 16 bit samples stay big-endian as they are in the PNG datastream.
+ */
+#[derive(Clone)]
+pub struct PixelBuffer {
+
+    pub width: u32,
+    pub height: u32,
+    pub color_type: u8,
+    pub bit_depth: u8,
+    pub data: Vec<u8>,
+    /* Color samples have been multiplied by alpha, see premultiply_alpha() */
+    pub premultiplied: bool,
+}
+
+/*
+    A single pixel, samples are at the bit depth of the buffer the pixel is read from or written to
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Pixel {
+
+    Gray(u16),
+    GrayAlpha(u16, u16),
+    Rgb(u16, u16, u16),
+    Rgba(u16, u16, u16, u16),
+    Indexed(u8),
+}
+
+impl Pixel {
+
+    pub fn color_type (&self) -> u8 {
+
+        match self {
+
+            Pixel::Gray(..) => COLOR_TYPE_GRAYSCALE,
+            Pixel::GrayAlpha(..) => COLOR_TYPE_GRAYSCALE_ALPHA,
+            Pixel::Rgb(..) => COLOR_TYPE_TRUECOLOR,
+            Pixel::Rgba(..) => COLOR_TYPE_TRUECOLOR_ALPHA,
+            Pixel::Indexed(..) => COLOR_TYPE_INDEXED,
+        }
+    }
+
+    pub fn samples (&self) -> Vec<u16> {
+
+        match *self {
+
+            Pixel::Gray(v) => vec![v],
+            Pixel::GrayAlpha(v, a) => vec![v, a],
+            Pixel::Rgb(r, g, b) => vec![r, g, b],
+            Pixel::Rgba(r, g, b, a) => vec![r, g, b, a],
+            Pixel::Indexed(i) => vec![i as u16],
+        }
+    }
+}
+
+/*
+    Clockwise rotations handled by PixelBuffer::rotate()
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Rotation {
+
+    Cw90,
+    Cw180,
+    Cw270,
+}
+
+/*
+    Resampling filters handled by PixelBuffer::resize()
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Filter {
+
+    Nearest,
+    Bilinear,
+}
+
+/*
+    How PixelBuffer::apply_mask() combines a mask with the alpha already there
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MaskMode {
+
+    /* Alpha becomes the mask value */
+    Replace,
+    /* Alpha is scaled by the mask value over its maximum, rounded to the nearest */
+    Multiply,
+}
+
+/*
+    Rotation works on square tiles of this many pixels so that reads and writes stay within cache for large images
+ */
+const ROTATION_TILE_SIZE: usize = 64;
+
+/*
+    Number of samples in one pixel of @color_type, an indexed pixel is a single palette index
+ */
+pub fn channels_of_color_type (color_type: u8) -> Result<usize, PngError> {
+
+    match color_type {
+
+        COLOR_TYPE_GRAYSCALE | COLOR_TYPE_INDEXED => Ok(1),
+        COLOR_TYPE_GRAYSCALE_ALPHA => Ok(2),
+        COLOR_TYPE_TRUECOLOR => Ok(3),
+        COLOR_TYPE_TRUECOLOR_ALPHA => Ok(4),
+        _ => Err(PngError::UnsupportedColorType(color_type)),
+    }
+}
+
+/*
+    Bit depths the PNG specification allows for each color type (Table 11.1)
+ */
+pub fn is_valid_bit_depth (color_type: u8, bit_depth: u8) -> bool {
+
+    match color_type {
+
+        COLOR_TYPE_GRAYSCALE => matches!(bit_depth, 1 | 2 | 4 | 8 | 16),
+        COLOR_TYPE_INDEXED => matches!(bit_depth, 1 | 2 | 4 | 8),
+        COLOR_TYPE_TRUECOLOR | COLOR_TYPE_GRAYSCALE_ALPHA | COLOR_TYPE_TRUECOLOR_ALPHA => matches!(bit_depth, 8 | 16),
+        _ => false,
+    }
+}
+
+impl PixelBuffer {
+
+    /*
+        Zero filled buffer, that is black and (where there is an alpha channel) fully transparent
+     */
+    pub fn new (width: u32, height: u32, color_type: u8, bit_depth: u8) -> Result<Self, PngError> {
+
+        let length = Self::expected_length(width, height, color_type, bit_depth)?;
+
+        Ok(Self {
+
+            width,
+            height,
+            color_type,
+            bit_depth,
+            data: vec![0; length],
+            premultiplied: false,
+        })
+    }
+
+    /*
+        @data has the unfiltered scanlines of an image and must hold exactly @width * @height pixels
+     */
+    pub fn from_data (width: u32, height: u32, color_type: u8, bit_depth: u8, data: Vec<u8>) -> Result<Self, PngError> {
+
+        let length = Self::expected_length(width, height, color_type, bit_depth)?;
+
+        if data.len() != length {
+
+            return Err(PngError::BufferSizeMismatch { expected: length, found: data.len() });
+        }
+
+        Ok(Self {
+
+            width,
+            height,
+            color_type,
+            bit_depth,
+            data,
+            premultiplied: false,
+        })
+    }
+
+    /**
+        Buffer whose pixel at (x, y) is @pixel(x, y), called for every pixel in row-major order. Samples are written straight into the buffer as they come.
+        Every Pixel has to be of @color_type with samples that fit in @bit_depth, the first that isn't stops it with the error paste() or fill_rect() would give.
+
+        ```
+        use png::{Pixel, PixelBuffer, COLOR_TYPE_GRAYSCALE, COLOR_TYPE_TRUECOLOR};
+
+        /* A 4 by 2 horizontal ramp */
+        let ramp = PixelBuffer::from_fn(4, 2, COLOR_TYPE_GRAYSCALE, 8, |x, _| Pixel::Gray(x as u16 * 85)).unwrap();
+
+        assert_eq!(ramp.data, [0, 85, 170, 255, 0, 85, 170, 255]);
+
+        /* A pixel of another color type is an error */
+        assert!(PixelBuffer::from_fn(1, 1, COLOR_TYPE_TRUECOLOR, 8, |_, _| Pixel::Gray(0)).is_err());
+        ```
+     */
+    pub fn from_fn (width: u32, height: u32, color_type: u8, bit_depth: u8, mut pixel: impl FnMut(u32, u32) -> Pixel) -> Result<Self, PngError> {
+
+        let mut buffer = Self::new(width, height, color_type, bit_depth)?;
+
+        let sample_size = buffer.bytes_per_sample();
+        let max = buffer.max_sample_value();
+        let mut offset: usize = 0;
+
+        for y in 0 .. height {
+
+            for x in 0 .. width {
+
+                let value = pixel(x, y);
+
+                if value.color_type() != color_type {
+
+                    return Err(PngError::IncompatiblePixelFormat { expected: (color_type, bit_depth), found: (value.color_type(), bit_depth) });
+                }
+
+                let (samples, count) = match value {
+
+                    Pixel::Gray(v) => ([v, 0, 0, 0], 1),
+                    Pixel::GrayAlpha(v, a) => ([v, a, 0, 0], 2),
+                    Pixel::Rgb(r, g, b) => ([r, g, b, 0], 3),
+                    Pixel::Rgba(r, g, b, a) => ([r, g, b, a], 4),
+                    Pixel::Indexed(i) => ([i as u16, 0, 0, 0], 1),
+                };
+
+                for sample in samples[.. count].iter() {
+
+                    if *sample as u32 > max {
+
+                        return Err(PngError::SampleOutOfRange { value: *sample as u32, max });
+                    }
+
+                    buffer.set_sample(offset, *sample as u32);
+                    offset += sample_size;
+                }
+            }
+        }
+
+        Ok(buffer)
+    }
+
+    fn expected_length (width: u32, height: u32, color_type: u8, bit_depth: u8) -> Result<usize, PngError> {
+
+        let channels = channels_of_color_type(color_type)?;
+
+        if !(bit_depth == 8 || (bit_depth == 16 && color_type != COLOR_TYPE_INDEXED)) {
+
+            return Err(PngError::UnsupportedBitDepth(bit_depth));
+        }
+
+        Ok(width as usize * height as usize * channels * (bit_depth as usize / 8))
+    }
+
+    pub fn channels (&self) -> usize {
+
+        channels_of_color_type(self.color_type).unwrap_or(0)
+    }
+
+    pub fn has_alpha (&self) -> bool {
+
+        self.color_type == COLOR_TYPE_GRAYSCALE_ALPHA || self.color_type == COLOR_TYPE_TRUECOLOR_ALPHA
+    }
+
+    pub fn bytes_per_sample (&self) -> usize {
+
+        self.bit_depth as usize / 8
+    }
+
+    pub fn bytes_per_pixel (&self) -> usize {
+
+        self.channels() * self.bytes_per_sample()
+    }
+
+    /*
+        Length in bytes of one row of pixels
+     */
+    pub fn stride (&self) -> usize {
+
+        self.width as usize * self.bytes_per_pixel()
+    }
+
+    /*
+        Largest value a sample can take at this bit depth
+     */
+    pub fn max_sample_value (&self) -> u32 {
+
+        (1u32 << self.bit_depth) - 1
+    }
+
+    /*
+        @offset is a byte offset into data where a (8 or 16 bit) sample begins
+     */
+    pub(crate) fn sample (&self, offset: usize) -> u32 {
+
+        if self.bit_depth == 16 {
+
+            u16::from_be_bytes([self.data[offset], self.data[offset + 1]]) as u32
+        }
+        else {
+
+            self.data[offset] as u32
+        }
+    }
+
+    pub(crate) fn set_sample (&mut self, offset: usize, value: u32) {
+
+        if self.bit_depth == 16 {
+
+            self.data[offset .. offset + 2].copy_from_slice(&(value as u16).to_be_bytes());
+        }
+        else {
+
+            self.data[offset] = value as u8;
+        }
+    }
+
+    fn check_same_format (&self, other: &PixelBuffer) -> Result<(), PngError> {
+
+        if self.color_type != other.color_type || self.bit_depth != other.bit_depth {
+
+            return Err(PngError::IncompatiblePixelFormat { expected: (self.color_type, self.bit_depth), found: (other.color_type, other.bit_depth) });
+        }
+
+        Ok(())
+    }
+
+    /*
+        Bytes @pixel is stored as in this buffer, @pixel has to be of the same color type and its samples have to fit in the bit depth
+     */
+    pub(crate) fn pixel_bytes (&self, pixel: &Pixel) -> Result<Vec<u8>, PngError> {
+
+        if pixel.color_type() != self.color_type {
+
+            return Err(PngError::IncompatiblePixelFormat { expected: (self.color_type, self.bit_depth), found: (pixel.color_type(), self.bit_depth) });
+        }
+
+        let mut bytes: Vec<u8> = Vec::with_capacity(self.bytes_per_pixel());
+
+        for sample in pixel.samples() {
+
+            if sample as u32 > self.max_sample_value() {
+
+                return Err(PngError::SampleOutOfRange { value: sample as u32, max: self.max_sample_value() });
+            }
+
+            if self.bit_depth == 16 {
+
+                bytes.extend_from_slice(&sample.to_be_bytes());
+            }
+            else {
+
+                bytes.push(sample as u8);
+            }
+        }
+
+        Ok(bytes)
+    }
+
+    /*
+        Pixel stored at byte @offset of data
+     */
+    pub(crate) fn pixel_at_offset (&self, offset: usize) -> Pixel {
+
+        let sample_size = self.bytes_per_sample();
+        let sample = |channel: usize| self.sample(offset + channel * sample_size) as u16;
+
+        match self.color_type {
+
+            COLOR_TYPE_GRAYSCALE_ALPHA => Pixel::GrayAlpha(sample(0), sample(1)),
+            COLOR_TYPE_TRUECOLOR => Pixel::Rgb(sample(0), sample(1), sample(2)),
+            COLOR_TYPE_TRUECOLOR_ALPHA => Pixel::Rgba(sample(0), sample(1), sample(2), sample(3)),
+            COLOR_TYPE_INDEXED => Pixel::Indexed(self.data[offset]),
+            _ => Pixel::Gray(sample(0)),
+        }
+    }
+
+    pub fn get_pixel (&self, x: u32, y: u32) -> Option<Pixel> {
+
+        if x >= self.width || y >= self.height {
+
+            return None;
+        }
+
+        Some(self.pixel_at_offset(y as usize * self.stride() + x as usize * self.bytes_per_pixel()))
+    }
+
+    /*
+        Every pixel of the image in row-major order
+     */
+    pub fn pixels (&self) -> impl Iterator<Item = Pixel> + '_ {
+
+        let bytes_per_pixel = self.bytes_per_pixel().max(1);
+
+        (0 .. self.data.len() / bytes_per_pixel).map(move |index| self.pixel_at_offset(index * bytes_per_pixel))
+    }
+
+    /*
+        Paints the rectangle of @w by @h pixels whose top left corner is at (@x, @y) with @color, the part outside of the image is clipped
+     */
+    pub fn fill_rect (&mut self, x: u32, y: u32, w: u32, h: u32, color: Pixel) -> Result<(), PngError> {
+
+        let bytes = self.pixel_bytes(&color)?;
+
+        if x >= self.width || y >= self.height {
+
+            return Ok(());
+        }
+
+        let bytes_per_pixel = bytes.len();
+        let stride = self.stride();
+        let columns = w.min(self.width - x) as usize;
+        let rows = h.min(self.height - y) as usize;
+
+        for row in 0 .. rows {
+
+            let start = (y as usize + row) * stride + x as usize * bytes_per_pixel;
+
+            for pixel in self.data[start .. start + columns * bytes_per_pixel].chunks_exact_mut(bytes_per_pixel) {
+
+                pixel.copy_from_slice(&bytes);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn plot (&mut self, x: i64, y: i64, bytes: &[u8]) {
+
+        if x >= 0 && y >= 0 && x < self.width as i64 && y < self.height as i64 {
+
+            let offset = y as usize * self.stride() + x as usize * bytes.len();
+
+            self.data[offset .. offset + bytes.len()].copy_from_slice(bytes);
+        }
+    }
+
+    /*
+        Draws a straight line from (@x0, @y0) to (@x1, @y1) with Bresenham's algorithm, both ends included.
+        A @thickness above 1 draws parallel copies of the line offset across it (vertically for mostly horizontal lines, horizontally otherwise).
+        Points can lie outside of the image, whatever falls outside is clipped.
+     */
+    pub fn draw_line (&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: Pixel, thickness: u32) -> Result<(), PngError> {
+
+        let bytes = self.pixel_bytes(&color)?;
+
+        let (x0, y0, x1, y1) = (x0 as i64, y0 as i64, x1 as i64, y1 as i64);
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let step_x = if x0 < x1 { 1 } else { -1 };
+        let step_y = if y0 < y1 { 1 } else { -1 };
+        let mostly_horizontal = dx >= -dy;
+
+        /* Thickness is spread around the line, the extra pixel of an even thickness goes below or right of it */
+        let first_offset = -((thickness as i64 - 1) / 2);
+        let last_offset = first_offset + thickness as i64 - 1;
+
+        let (mut x, mut y) = (x0, y0);
+        let mut error = dx + dy;
+
+        loop {
+
+            for offset in first_offset ..= last_offset {
+
+                if mostly_horizontal {
+
+                    self.plot(x, y + offset, &bytes);
+                }
+                else {
+
+                    self.plot(x + offset, y, &bytes);
+                }
+            }
+
+            if x == x1 && y == y1 {
+
+                break;
+            }
+
+            let doubled_error = 2 * error;
+
+            if doubled_error >= dy {
+
+                error += dy;
+                x += step_x;
+            }
+
+            if doubled_error <= dx {
+
+                error += dx;
+                y += step_y;
+            }
+        }
+
+        Ok(())
+    }
+
+    /*
+        Draws the border of the @w by @h rectangle whose top left corner is at (@x, @y), @thickness pixels of it grow inwards from the edges
+     */
+    pub fn draw_rect_outline (&mut self, x: u32, y: u32, w: u32, h: u32, color: Pixel, thickness: u32) -> Result<(), PngError> {
+
+        self.pixel_bytes(&color)?;
+
+        if w == 0 || h == 0 || thickness == 0 {
+
+            return Ok(());
+        }
+
+        let band_width = thickness.min(w);
+        let band_height = thickness.min(h);
+
+        self.fill_rect(x, y, w, band_height, color)?;
+        self.fill_rect(x, y.saturating_add(h - band_height), w, band_height, color)?;
+        self.fill_rect(x, y, band_width, h, color)?;
+        self.fill_rect(x.saturating_add(w - band_width), y, band_width, h, color)?;
+
+        Ok(())
+    }
+
+    /*
+        Copies @src into this buffer with its top left corner at (@x, @y).
+        Whatever part of @src falls outside of this buffer is clipped, both buffers must have the same color type and bit depth.
+     */
+    pub fn paste (&mut self, src: &PixelBuffer, x: u32, y: u32) -> Result<(), PngError> {
+
+        self.check_same_format(src)?;
+
+        if x >= self.width || y >= self.height {
+
+            return Ok(());
+        }
+
+        let bytes_per_pixel = self.bytes_per_pixel();
+        let columns = src.width.min(self.width - x) as usize;
+        let rows = src.height.min(self.height - y) as usize;
+        let dst_stride = self.stride();
+        let src_stride = src.stride();
+
+        for row in 0 .. rows {
+
+            let dst_start = (y as usize + row) * dst_stride + x as usize * bytes_per_pixel;
+            let src_start = row * src_stride;
+
+            self.data[dst_start .. dst_start + columns * bytes_per_pixel].copy_from_slice(&src.data[src_start .. src_start + columns * bytes_per_pixel]);
+        }
+
+        Ok(())
+    }
+
+    /*
+        Ok when @src can be blended over this buffer, UnsupportedColorType when @src has no alpha channel and IncompatiblePixelFormat when
+        this buffer is neither @src's color type nor its counterpart without alpha, or the bit depths differ
+     */
+    fn check_blend_format (&self, src: &PixelBuffer) -> Result<(), PngError> {
+
+        let without_alpha = match src.color_type {
+
+            COLOR_TYPE_TRUECOLOR_ALPHA => COLOR_TYPE_TRUECOLOR,
+            COLOR_TYPE_GRAYSCALE_ALPHA => COLOR_TYPE_GRAYSCALE,
+            _ => return Err(PngError::UnsupportedColorType(src.color_type)),
+        };
+
+        if (self.color_type != src.color_type && self.color_type != without_alpha) || self.bit_depth != src.bit_depth {
+
+            return Err(PngError::IncompatiblePixelFormat { expected: (src.color_type, src.bit_depth), found: (self.color_type, self.bit_depth) });
+        }
+
+        Ok(())
+    }
+
+    /*
+        Like paste() but @src, which must have an alpha channel, is blended over the existing pixels (non-premultiplied "source over").
+        This buffer can be the same color type as @src or its counterpart without alpha (RGB for an RGBA @src, gray for a gray+alpha @src).
+        Premultiplied buffers give AlphaAlreadyPremultiplied, the blend works on straight alpha.
+     */
+    pub fn alpha_over (&mut self, src: &PixelBuffer, x: u32, y: u32) -> Result<(), PngError> {
+
+        if self.premultiplied || src.premultiplied {
+
+            return Err(PngError::AlphaAlreadyPremultiplied);
+        }
+
+        self.check_blend_format(src)?;
+
+        if x >= self.width || y >= self.height {
+
+            return Ok(());
+        }
+
+        let max = self.max_sample_value() as u64;
+        let sample_size = self.bytes_per_sample();
+        let color_channels = src.channels() - 1;
+        let dst_has_alpha = self.has_alpha();
+        let dst_bytes_per_pixel = self.bytes_per_pixel();
+        let src_bytes_per_pixel = src.bytes_per_pixel();
+        let columns = src.width.min(self.width - x) as usize;
+        let rows = src.height.min(self.height - y) as usize;
+
+        for row in 0 .. rows {
+
+            for column in 0 .. columns {
+
+                let src_offset = row * src.stride() + column * src_bytes_per_pixel;
+                let dst_offset = (y as usize + row) * self.stride() + (x as usize + column) * dst_bytes_per_pixel;
+
+                let src_alpha = src.sample(src_offset + color_channels * sample_size) as u64;
+                let dst_alpha = if dst_has_alpha { self.sample(dst_offset + color_channels * sample_size) as u64 } else { max };
+
+                /* Resulting alpha scaled up by max, a_out * max = a_src * max + a_dst * (max - a_src) */
+                let weight = src_alpha * max + dst_alpha * (max - src_alpha);
+
+                for channel in 0 .. color_channels {
+
+                    let s = src.sample(src_offset + channel * sample_size) as u64;
+                    let d = self.sample(dst_offset + channel * sample_size) as u64;
+
+                    /* Fully transparent over fully transparent leaves no color to weigh, it comes out 0 */
+                    let value = (s * src_alpha * max + d * dst_alpha * (max - src_alpha) + weight / 2).checked_div(weight).unwrap_or(0);
+
+                    self.set_sample(dst_offset + channel * sample_size, value as u32);
+                }
+
+                if dst_has_alpha {
+
+                    self.set_sample(dst_offset + color_channels * sample_size, ((weight + max / 2) / max) as u32);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /*
+        Copy of @background with this buffer blended over it by alpha_over(), its top left corner at (@x, @y).
+        Offsets can be negative, whatever hangs off any edge of @background is clipped. Where @background is transparent too the result is
+        a_out = a_fg + a_bg * (1 - a_fg), with colors weighted by how much each layer contributes to it.
+        Premultiplied buffers give AlphaAlreadyPremultiplied, the blend works on straight alpha.
+     */
+    pub fn composite_over (&self, background: &PixelBuffer, x: i32, y: i32) -> Result<PixelBuffer, PngError> {
+
+        if self.premultiplied || background.premultiplied {
+
+            return Err(PngError::AlphaAlreadyPremultiplied);
+        }
+
+        background.check_blend_format(self)?;
+
+        let mut output = background.clone();
+
+        let left = if x < 0 { x.unsigned_abs().min(self.width) } else { 0 };
+        let top = if y < 0 { y.unsigned_abs().min(self.height) } else { 0 };
+        let (x, y) = (x.max(0) as u32, y.max(0) as u32);
+
+        if left == self.width || top == self.height {
+
+            return Ok(output);
+        }
+
+        if left == 0 && top == 0 {
+
+            output.alpha_over(self, x, y)?;
+        }
+        else {
+
+            let block = ImageBlock::new((self.height - top) as usize, (self.width - left) as usize);
+            let visible = PixelBuffer::from_data(self.width - left, self.height - top, self.color_type, self.bit_depth, extract_block(self, &block, left, top)?)?;
+
+            output.alpha_over(&visible, x, y)?;
+        }
+
+        Ok(output)
+    }
+
+    /*
+        Mirrors the image left to right, whole pixels are swapped so every channel count and bit depth is handled alike
+     */
+    pub fn flip_horizontal (&mut self) {
+
+        let bytes_per_pixel = self.bytes_per_pixel();
+        let stride = self.stride();
+        let width = self.width as usize;
+
+        if bytes_per_pixel == 0 || stride == 0 {
+
+            return;
+        }
+
+        for row in self.data.chunks_exact_mut(stride) {
+
+            let mut left: usize = 0;
+            let mut right: usize = width - 1;
+
+            while left < right {
+
+                let (head, tail) = row.split_at_mut(right * bytes_per_pixel);
+
+                head[left * bytes_per_pixel .. (left + 1) * bytes_per_pixel].swap_with_slice(&mut tail[.. bytes_per_pixel]);
+
+                left += 1;
+                right -= 1;
+            }
+        }
+    }
+
+    /*
+        Mirrors the image top to bottom, the first scanline becomes the last one (bottom-up row order)
+     */
+    pub fn flip_vertical (&mut self) {
+
+        let stride = self.stride();
+
+        if stride == 0 {
+
+            return;
+        }
+
+        let mut top: usize = 0;
+        let mut bottom: usize = self.height as usize;
+
+        while top + 1 < bottom {
+
+            bottom -= 1;
+
+            let (head, tail) = self.data.split_at_mut(bottom * stride);
+
+            head[top * stride .. (top + 1) * stride].swap_with_slice(&mut tail[.. stride]);
+
+            top += 1;
+        }
+    }
+
+    pub fn flipped_horizontal (mut self) -> Self {
+
+        self.flip_horizontal();
+
+        self
+    }
+
+    pub fn flipped_vertical (mut self) -> Self {
+
+        self.flip_vertical();
+
+        self
+    }
+
+    /*
+        Returns a new buffer rotated clockwise by @rotation, width and height are swapped for the 90 and 270 degree cases.
+        Pixels are moved tile by tile (ROTATION_TILE_SIZE) rather than row by row, writing a column of the destination at a time thrashes the cache on large images.
+     */
+    pub fn rotate (&self, rotation: Rotation) -> PixelBuffer {
+
+        let width = self.width as usize;
+        let height = self.height as usize;
+        let bytes_per_pixel = self.bytes_per_pixel();
+
+        let (new_width, new_height) = match rotation {
+
+            Rotation::Cw180 => (width, height),
+            Rotation::Cw90 | Rotation::Cw270 => (height, width),
+        };
+
+        let mut data = vec![0; self.data.len()];
+        let new_stride = new_width * bytes_per_pixel;
+
+        let mut tile_y: usize = 0;
+
+        while tile_y < height {
+
+            let mut tile_x: usize = 0;
+
+            while tile_x < width {
+
+                for y in tile_y .. (tile_y + ROTATION_TILE_SIZE).min(height) {
+
+                    for x in tile_x .. (tile_x + ROTATION_TILE_SIZE).min(width) {
+
+                        let (dst_x, dst_y) = match rotation {
+
+                            Rotation::Cw90 => (height - 1 - y, x),
+                            Rotation::Cw180 => (width - 1 - x, height - 1 - y),
+                            Rotation::Cw270 => (y, width - 1 - x),
+                        };
+
+                        let src_offset = (y * width + x) * bytes_per_pixel;
+                        let dst_offset = dst_y * new_stride + dst_x * bytes_per_pixel;
+
+                        data[dst_offset .. dst_offset + bytes_per_pixel].copy_from_slice(&self.data[src_offset .. src_offset + bytes_per_pixel]);
+                    }
+                }
+
+                tile_x += ROTATION_TILE_SIZE;
+            }
+
+            tile_y += ROTATION_TILE_SIZE;
+        }
+
+        PixelBuffer {
+
+            width: new_width as u32,
+            height: new_height as u32,
+            color_type: self.color_type,
+            bit_depth: self.bit_depth,
+            data,
+            premultiplied: self.premultiplied,
+        }
+    }
+
+    /*
+        Returns a new buffer of @new_width by @new_height pixels resampled from this one with @filter
+     */
+    pub fn resize (&self, new_width: u32, new_height: u32, filter: Filter) -> PixelBuffer {
+
+        match filter {
+
+            Filter::Nearest => self.resize_nearest(new_width, new_height),
+            Filter::Bilinear => self.resize_bilinear(new_width, new_height, false),
+        }
+    }
+
+    /*
+        Each destination pixel takes the source pixel its center falls in.
+        Source columns and rows are worked out once per axis, so the inner loop does nothing but copy pixels.
+     */
+    pub fn resize_nearest (&self, new_width: u32, new_height: u32) -> PixelBuffer {
+
+        let bytes_per_pixel = self.bytes_per_pixel();
+        let mut data = vec![0; new_width as usize * new_height as usize * bytes_per_pixel];
+
+        if self.width > 0 && self.height > 0 {
+
+            let source_columns: Vec<usize> = (0 .. new_width as u64).map(|x| ((2 * x + 1) * self.width as u64 / (2 * new_width as u64)) as usize * bytes_per_pixel).collect();
+            let source_rows: Vec<usize> = (0 .. new_height as u64).map(|y| ((2 * y + 1) * self.height as u64 / (2 * new_height as u64)) as usize * self.stride()).collect();
+
+            let mut offset: usize = 0;
+
+            for row in source_rows.iter() {
+
+                for column in source_columns.iter() {
+
+                    data[offset .. offset + bytes_per_pixel].copy_from_slice(&self.data[row + column .. row + column + bytes_per_pixel]);
+
+                    offset += bytes_per_pixel;
+                }
+            }
+        }
+
+        PixelBuffer {
+
+            width: new_width,
+            height: new_height,
+            color_type: self.color_type,
+            bit_depth: self.bit_depth,
+            data,
+            premultiplied: self.premultiplied,
+        }
+    }
+
+    /*
+        Each destination sample is interpolated from the four nearest source pixels, coordinates beyond the edges are clamped to the border pixels.
+        Interpolation is done in f32 and rounded once at the end so neither 8 nor 16 bit samples pick up banding.
+        With @premultiply_alpha false the alpha channel is interpolated like any other channel (straight, non-premultiplied alpha),
+        which lets the color of fully transparent pixels bleed into their visible neighbours. Passing true weights every color sample
+        by its alpha before interpolating and divides it back out afterwards, which is the correct behavior around transparent edges.
+        Palette indices can't be blended, indexed buffers are resized with resize_nearest() instead.
+     */
+    pub fn resize_bilinear (&self, new_width: u32, new_height: u32, premultiply_alpha: bool) -> PixelBuffer {
+
+        if self.color_type == COLOR_TYPE_INDEXED || self.width == 0 || self.height == 0 {
+
+            return self.resize_nearest(new_width, new_height);
+        }
+
+        let channels = self.channels();
+        let sample_size = self.bytes_per_sample();
+        let bytes_per_pixel = self.bytes_per_pixel();
+        let stride = self.stride();
+        let max = self.max_sample_value() as f32;
+        let premultiply = premultiply_alpha && self.has_alpha();
+        let alpha_channel = channels - 1;
+
+        let columns = Self::bilinear_weights(self.width, new_width);
+        let rows = Self::bilinear_weights(self.height, new_height);
+
+        let mut output = PixelBuffer {
+
+            width: new_width,
+            height: new_height,
+            color_type: self.color_type,
+            bit_depth: self.bit_depth,
+            data: vec![0; new_width as usize * new_height as usize * bytes_per_pixel],
+            premultiplied: self.premultiplied,
+        };
+
+        let mut pixel = vec![0f32; channels];
+        let mut offset: usize = 0;
+
+        for &(top, bottom, fy) in rows.iter() {
+
+            for &(left, right, fx) in columns.iter() {
+
+                let corners = [
+
+                    (top * stride + left * bytes_per_pixel, (1.0 - fx) * (1.0 - fy)),
+                    (top * stride + right * bytes_per_pixel, fx * (1.0 - fy)),
+                    (bottom * stride + left * bytes_per_pixel, (1.0 - fx) * fy),
+                    (bottom * stride + right * bytes_per_pixel, fx * fy),
+                ];
+
+                pixel.iter_mut().for_each(|value| *value = 0.0);
+
+                for &(corner, weight) in corners.iter() {
+
+                    let alpha = if premultiply { self.sample(corner + alpha_channel * sample_size) as f32 / max } else { 1.0 };
+
+                    for (channel, total) in pixel.iter_mut().enumerate() {
+
+                        let value = self.sample(corner + channel * sample_size) as f32;
+
+                        if premultiply && channel != alpha_channel {
+
+                            *total += value * alpha * weight;
+                        }
+                        else {
+
+                            *total += value * weight;
+                        }
+                    }
+                }
+
+                if premultiply {
+
+                    let alpha = pixel[alpha_channel] / max;
+
+                    for value in pixel[.. alpha_channel].iter_mut() {
+
+                        *value = if alpha > 0.0 { *value / alpha } else { 0.0 };
+                    }
+                }
+
+                for (channel, value) in pixel.iter().enumerate() {
+
+                    output.set_sample(offset + channel * sample_size, value.round().clamp(0.0, max) as u32);
+                }
+
+                offset += bytes_per_pixel;
+            }
+        }
+
+        output
+    }
+
+    /*
+        For every destination index along one axis, the two source indices to blend and the weight of the second one
+     */
+    fn bilinear_weights (source_length: u32, target_length: u32) -> Vec<(usize, usize, f32)> {
+
+        let scale = source_length as f32 / target_length as f32;
+        let last = source_length as usize - 1;
+
+        (0 .. target_length).map(|index| {
+
+            let position = ((index as f32 + 0.5) * scale - 0.5).clamp(0.0, last as f32);
+            let first = position.floor() as usize;
+
+            (first, (first + 1).min(last), position - first as f32)
+
+        }).collect()
+    }
+
+    /*
+        Offsets of the pixels whose leading samples are each within @tolerance of @samples.
+        @tolerance is on the 8 bit scale, for 16 bit buffers it is scaled up by 257 so it means the same.
+     */
+    fn matching_pixels (&self, samples: &[u16], tolerance: u8) -> Vec<usize> {
+
+        let sample_size = self.bytes_per_sample();
+        let bytes_per_pixel = self.bytes_per_pixel();
+        let tolerance = if self.bit_depth == 16 { tolerance as u32 * 257 } else { tolerance as u32 };
+
+        (0 .. self.width as usize * self.height as usize).map(|index| index * bytes_per_pixel).filter(|&offset| {
+
+            samples.iter().enumerate().all(|(channel, &value)| self.sample(offset + channel * sample_size).abs_diff(value as u32) <= tolerance)
+
+        }).collect()
+    }
+
+    /*
+        Replaces every pixel within @tolerance (per channel) of @from with @to and returns how many pixels were changed.
+        Both pixels must be of this buffer's color type.
+     */
+    pub fn replace_color (&mut self, from: Pixel, to: Pixel, tolerance: u8) -> Result<usize, PngError> {
+
+        self.pixel_bytes(&from)?;
+        let bytes = self.pixel_bytes(&to)?;
+
+        let matches = self.matching_pixels(&from.samples(), tolerance);
+
+        for &offset in matches.iter() {
+
+            self.data[offset .. offset + bytes.len()].copy_from_slice(&bytes);
+        }
+
+        Ok(matches.len())
+    }
+
+    /*
+        Returns the buffer with an alpha channel, gray becomes gray+alpha and RGB becomes RGBA with every pixel opaque.
+        Buffers that already have alpha come back unchanged.
+     */
+    pub fn with_alpha (self) -> Result<PixelBuffer, PngError> {
+
+        let color_type = match self.color_type {
+
+            COLOR_TYPE_GRAYSCALE => COLOR_TYPE_GRAYSCALE_ALPHA,
+            COLOR_TYPE_TRUECOLOR => COLOR_TYPE_TRUECOLOR_ALPHA,
+            COLOR_TYPE_GRAYSCALE_ALPHA | COLOR_TYPE_TRUECOLOR_ALPHA => return Ok(self),
+            _ => return Err(PngError::UnsupportedColorType(self.color_type)),
+        };
+
+        let bytes_per_pixel = self.bytes_per_pixel();
+        let opaque = vec![0xFF; self.bytes_per_sample()];
+        let mut data: Vec<u8> = Vec::with_capacity(self.data.len() / bytes_per_pixel * (bytes_per_pixel + opaque.len()));
+
+        for pixel in self.data.chunks_exact(bytes_per_pixel) {
+
+            data.extend_from_slice(pixel);
+            data.extend_from_slice(&opaque);
+        }
+
+        Ok(PixelBuffer {
+
+            width: self.width,
+            height: self.height,
+            color_type,
+            bit_depth: self.bit_depth,
+            data,
+            premultiplied: false,
+        })
+    }
+
+    /*
+        Alpha of every pixel as a gray buffer of the same size, the alpha channel itself for gray+alpha and RGBA.
+        Without one, alpha is made from @trns, the data field of tRNS (empty when there is none): per palette entry for indexed buffers, 0 for the
+        transparent color and opaque for any other for gray and RGB. None when there is neither, the image is fully opaque. Bit depth stays that of the buffer.
+     */
+    pub fn extract_alpha (&self, trns: &[u8]) -> Result<Option<PixelBuffer>, PngError> {
+
+        if self.has_alpha() {
+
+            return Ok(Some(PixelBuffer::from_data(self.width, self.height, COLOR_TYPE_GRAYSCALE, self.bit_depth, self.extract_channel(self.channels() - 1)?)?));
+        }
+
+        if trns.is_empty() {
+
+            return Ok(None);
+        }
+
+        let mut alpha = PixelBuffer::new(self.width, self.height, COLOR_TYPE_GRAYSCALE, self.bit_depth)?;
+
+        if self.color_type == COLOR_TYPE_INDEXED {
+
+            alpha.data.iter_mut().zip(self.data.iter()).for_each(|(value, index)| *value = *trns.get(*index as usize).unwrap_or(&0xFF));
+
+            return Ok(Some(alpha));
+        }
+
+        let channels = self.channels();
+
+        if trns.len() != channels * 2 {
+
+            return Err(PngError::InvalidChunkLength { type_name: "tRNS", expected: channels * 2, found: trns.len() });
+        }
+
+        let key: Vec<u32> = trns.chunks_exact(2).map(|sample| u16::from_be_bytes([sample[0], sample[1]]) as u32).collect();
+        let sample_size = self.bytes_per_sample();
+
+        for pixel in 0 .. self.width as usize * self.height as usize {
+
+            let transparent = (0 .. channels).all(|channel| self.sample((pixel * channels + channel) * sample_size) == key[channel]);
+
+            alpha.set_sample(pixel * sample_size, if transparent { 0 } else { alpha.max_sample_value() });
+        }
+
+        Ok(Some(alpha))
+    }
+
+    /*
+        Gray or RGB buffer with @mask, a gray buffer of the same size, as its alpha channel, the inverse of extract_alpha() for gray+alpha and RGBA.
+        @mask is rescaled to the bit depth of this buffer if it differs.
+     */
+    pub fn with_alpha_from (&self, mask: &PixelBuffer) -> Result<PixelBuffer, PngError> {
+
+        if self.color_type != COLOR_TYPE_GRAYSCALE && self.color_type != COLOR_TYPE_TRUECOLOR {
+
+            return Err(PngError::UnsupportedColorType(self.color_type));
+        }
+
+        if mask.color_type != COLOR_TYPE_GRAYSCALE {
+
+            return Err(PngError::IncompatiblePixelFormat { expected: (COLOR_TYPE_GRAYSCALE, self.bit_depth), found: (mask.color_type, mask.bit_depth) });
+        }
+
+        if (mask.width, mask.height) != (self.width, self.height) {
+
+            return Err(PngError::DimensionMismatch { expected: (self.width, self.height), found: (mask.width, mask.height) });
+        }
+
+        let mask = mask.convert_bit_depth(self.bit_depth)?;
+        let mut output = self.clone().with_alpha()?;
+
+        let sample_size = output.bytes_per_sample();
+        let alpha_offset = output.bytes_per_pixel() - sample_size;
+
+        for (pixel, alpha) in output.data.chunks_exact_mut(self.bytes_per_pixel() + sample_size).zip(mask.data.chunks_exact(sample_size)) {
+
+            pixel[alpha_offset ..].copy_from_slice(alpha);
+        }
+
+        Ok(output)
+    }
+
+    /*
+        Sets the alpha of every pixel from @mask, a gray buffer of the same size rescaled to this buffer's bit depth if it differs, as @mode says.
+        A gray or RGB buffer is turned into RGBA first, gray+alpha stays as it is. Premultiplied buffers give AlphaAlreadyPremultiplied, their colors would need rescaling too.
+     */
+    pub fn apply_mask (&mut self, mask: &PixelBuffer, mode: MaskMode) -> Result<(), PngError> {
+
+        if self.premultiplied {
+
+            return Err(PngError::AlphaAlreadyPremultiplied);
+        }
+
+        if mask.color_type != COLOR_TYPE_GRAYSCALE {
+
+            return Err(PngError::IncompatiblePixelFormat { expected: (COLOR_TYPE_GRAYSCALE, self.bit_depth), found: (mask.color_type, mask.bit_depth) });
+        }
+
+        if (mask.width, mask.height) != (self.width, self.height) {
+
+            return Err(PngError::DimensionMismatch { expected: (self.width, self.height), found: (mask.width, mask.height) });
+        }
+
+        if !self.has_alpha() {
+
+            *self = self.to_rgba()?;
+        }
+
+        let mask = mask.convert_bit_depth(self.bit_depth)?;
+
+        let sample_size = self.bytes_per_sample();
+        let bytes_per_pixel = self.bytes_per_pixel();
+        let max = self.max_sample_value();
+
+        for pixel in 0 .. self.width as usize * self.height as usize {
+
+            let offset = (pixel + 1) * bytes_per_pixel - sample_size;
+            let value = mask.sample(pixel * sample_size);
+
+            let alpha = match mode {
+
+                MaskMode::Replace => value,
+                MaskMode::Multiply => (self.sample(offset) * value + max / 2) / max,
+            };
+
+            self.set_sample(offset, alpha);
+        }
+
+        Ok(())
+    }
+
+    /*
+        Chroma keying, every pixel within @tolerance of @key becomes fully transparent and the number of such pixels is returned.
+        @key is a Pixel::Gray or Pixel::Rgb for the color channels of this buffer, a buffer without alpha is given an alpha channel first.
+     */
+    pub fn make_color_transparent (&mut self, key: Pixel, tolerance: u8) -> Result<usize, PngError> {
+
+        let key_fits = match key {
+
+            Pixel::Gray(..) => self.color_type == COLOR_TYPE_GRAYSCALE || self.color_type == COLOR_TYPE_GRAYSCALE_ALPHA,
+            Pixel::Rgb(..) => self.color_type == COLOR_TYPE_TRUECOLOR || self.color_type == COLOR_TYPE_TRUECOLOR_ALPHA,
+            _ => false,
+        };
+
+        if !key_fits {
+
+            return Err(PngError::IncompatiblePixelFormat { expected: (self.color_type, self.bit_depth), found: (key.color_type(), self.bit_depth) });
+        }
+
+        if !self.has_alpha() {
+
+            let buffer = PixelBuffer { data: std::mem::take(&mut self.data), ..*self };
+
+            *self = buffer.with_alpha()?;
+        }
+
+        let alpha_offset = (self.channels() - 1) * self.bytes_per_sample();
+        let matches = self.matching_pixels(&key.samples(), tolerance);
+
+        for &offset in matches.iter() {
+
+            self.set_sample(offset + alpha_offset, 0);
+        }
+
+        Ok(matches.len())
+    }
+
+    /*
+        One 256 bin histogram per channel, filled in a single pass over the data.
+        Bins are indexed with the sample byte itself so the hot loop has no bounds checks, 16 bit samples are binned by their high byte.
+     */
+    pub fn histogram (&self) -> Vec<[u32; 256]> {
+
+        let channels = self.channels();
+        let sample_size = self.bytes_per_sample();
+        let mut histograms = vec![[0u32; 256]; channels];
+
+        if channels == 0 {
+
+            return histograms;
+        }
+
+        for pixel in self.data.chunks_exact(channels * sample_size) {
+
+            for (histogram, sample) in histograms.iter_mut().zip(pixel.chunks_exact(sample_size)) {
+
+                histogram[sample[0] as usize] += 1;
+            }
+        }
+
+        histograms
+    }
+
+    /*
+        Full resolution histograms of a 16 bit buffer, one 65536 bin histogram per channel. 8 bit samples only land in the first 256 bins.
+     */
+    pub fn histogram_16 (&self) -> Vec<Box<[u32; 65536]>> {
+
+        let channels = self.channels();
+        let sample_size = self.bytes_per_sample();
+        let mut histograms: Vec<Box<[u32; 65536]>> = (0 .. channels).map(|_| Box::new([0u32; 65536])).collect();
+
+        if channels == 0 {
+
+            return histograms;
+        }
+
+        for pixel in self.data.chunks_exact(channels * sample_size) {
+
+            for (histogram, sample) in histograms.iter_mut().zip(pixel.chunks_exact(sample_size)) {
+
+                let value = if sample_size == 2 { u16::from_be_bytes([sample[0], sample[1]]) } else { sample[0] as u16 };
+
+                histogram[value as usize] += 1;
+            }
+        }
+
+        histograms
+    }
+
+    /*
+        Brightness and contrast, out = clamp((in - 128) * @contrast + 128 + @brightness) for every color sample, alpha is left untouched.
+        For 16 bit buffers the midpoint is 32768 and @brightness is scaled by 257. A @contrast of 0 gives uniform gray and results clamp instead of wrapping.
+        The curve is worked out once into a lookup table so each sample costs one table lookup. Palette indices are not adjusted.
+     */
+    pub fn adjust (&self, brightness: i16, contrast: f32) -> PixelBuffer {
+
+        let mut output = self.clone();
+
+        if self.color_type == COLOR_TYPE_INDEXED {
+
+            return output;
+        }
+
+        let max = self.max_sample_value() as f32;
+        let middle = (max + 1.0) / 2.0;
+        let shift = brightness as f32 * if self.bit_depth == 16 { 257.0 } else { 1.0 };
+
+        let table: Vec<u32> = (0 ..= self.max_sample_value()).map(|value| ((value as f32 - middle) * contrast + middle + shift).round().clamp(0.0, max) as u32).collect();
+
+        let channels = self.channels();
+        let sample_size = self.bytes_per_sample();
+        let color_channels = if self.has_alpha() { channels - 1 } else { channels };
+
+        for pixel in 0 .. self.width as usize * self.height as usize {
+
+            let offset = pixel * channels * sample_size;
+
+            for channel in 0 .. color_channels {
+
+                let value = table[self.sample(offset + channel * sample_size) as usize];
+
+                output.set_sample(offset + channel * sample_size, value);
+            }
+        }
+
+        output
+    }
+
+    /*
+        Maps every color sample to max - value, the alpha channel is left alone so inverting twice gives back the exact original.
+        Palette indices can't be inverted, an indexed image has to be inverted through its palette instead.
+     */
+    pub fn invert (&mut self) -> Result<(), PngError> {
+
+        if self.color_type == COLOR_TYPE_INDEXED {
+
+            return Err(PngError::UnsupportedColorType(self.color_type));
+        }
+
+        let max = self.max_sample_value();
+        let channels = self.channels();
+        let sample_size = self.bytes_per_sample();
+        let color_channels = if self.has_alpha() { channels - 1 } else { channels };
+
+        for pixel in 0 .. self.width as usize * self.height as usize {
+
+            let offset = pixel * channels * sample_size;
+
+            for channel in 0 .. color_channels {
+
+                let value = self.sample(offset + channel * sample_size);
+
+                self.set_sample(offset + channel * sample_size, max - value);
+            }
+        }
+
+        Ok(())
+    }
+
+    /*
+        Multiplies every color sample by its alpha, out = (c * a + max / 2) / max, which is what GPU compositors expect.
+        PNG stores straight alpha so a buffer that is already premultiplied is refused rather than darkened a second time.
+     */
+    pub fn premultiply_alpha (&mut self) -> Result<(), PngError> {
+
+        if !self.has_alpha() {
+
+            return Err(PngError::UnsupportedColorType(self.color_type));
+        }
+
+        if self.premultiplied {
+
+            return Err(PngError::AlphaAlreadyPremultiplied);
+        }
+
+        let max = self.max_sample_value();
+        let channels = self.channels();
+        let sample_size = self.bytes_per_sample();
+
+        for pixel in 0 .. self.width as usize * self.height as usize {
+
+            let offset = pixel * channels * sample_size;
+            let alpha = self.sample(offset + (channels - 1) * sample_size);
+
+            for channel in 0 .. channels - 1 {
+
+                let value = self.sample(offset + channel * sample_size);
+
+                self.set_sample(offset + channel * sample_size, (value * alpha + max / 2) / max);
+            }
+        }
+
+        self.premultiplied = true;
+
+        Ok(())
+    }
+
+    /*
+        Inverse of premultiply_alpha(), color samples of fully transparent pixels become 0.
+        Premultiplying leaves only alpha + 1 levels per color sample, so a round trip restores color to within about max / alpha (±1 for opaque pixels).
+     */
+    pub fn unpremultiply_alpha (&mut self) -> Result<(), PngError> {
+
+        if !self.has_alpha() {
+
+            return Err(PngError::UnsupportedColorType(self.color_type));
+        }
+
+        if !self.premultiplied {
+
+            return Err(PngError::AlphaNotPremultiplied);
+        }
+
+        let max = self.max_sample_value();
+        let channels = self.channels();
+        let sample_size = self.bytes_per_sample();
+
+        for pixel in 0 .. self.width as usize * self.height as usize {
+
+            let offset = pixel * channels * sample_size;
+            let alpha = self.sample(offset + (channels - 1) * sample_size);
+
+            for channel in 0 .. channels - 1 {
+
+                let value = (self.sample(offset + channel * sample_size) * max + alpha / 2).checked_div(alpha).map_or(0, |value| value.min(max));
+
+                self.set_sample(offset + channel * sample_size, value);
+            }
+        }
+
+        self.premultiplied = false;
+
+        Ok(())
+    }
+
+    /*
+        One plane per channel (R, G, B, A order, or gray then alpha), 16 bit samples keep their two bytes together
+     */
+    pub fn split_channels (&self) -> Vec<Vec<u8>> {
+
+        let channels = self.channels();
+        let sample_size = self.bytes_per_sample();
+        let plane_length = self.width as usize * self.height as usize * sample_size;
+        let mut planes: Vec<Vec<u8>> = (0 .. channels).map(|_| Vec::with_capacity(plane_length)).collect();
+
+        if channels == 0 {
+
+            return planes;
+        }
+
+        for pixel in self.data.chunks_exact(channels * sample_size) {
+
+            for (plane, sample) in planes.iter_mut().zip(pixel.chunks_exact(sample_size)) {
+
+                plane.extend_from_slice(sample);
+            }
+        }
+
+        planes
+    }
+
+    /*
+        Plane of the single channel @index
+     */
+    pub fn extract_channel (&self, index: usize) -> Result<Vec<u8>, PngError> {
+
+        let channels = self.channels();
+
+        if index >= channels {
+
+            return Err(PngError::ChannelOutOfRange { index, channels });
+        }
+
+        let sample_size = self.bytes_per_sample();
+
+        Ok(self.data.chunks_exact(channels * sample_size).flat_map(|pixel| pixel[index * sample_size .. (index + 1) * sample_size].iter().copied()).collect())
+    }
+
+    /*
+        Samples of channel @channel scaled to 0.0 - 1.0 (by 255 or 65535), one value per pixel in row-major order
+     */
+    pub fn channel_as_f64 (&self, channel: usize) -> Result<Vec<f64>, PngError> {
+
+        let channels = self.channels();
+
+        if channel >= channels {
+
+            return Err(PngError::ChannelOutOfRange { index: channel, channels });
+        }
+
+        let sample_size = self.bytes_per_sample();
+        let max = self.max_sample_value() as f64;
+
+        Ok((0 .. self.width as usize * self.height as usize).map(|pixel| self.sample((pixel * channels + channel) * sample_size) as f64 / max).collect())
+    }
+
+    /*
+        Every sample scaled to 0.0 - 1.0, channels stay interleaved (HWC) as they are in the buffer
+     */
+    pub fn all_channels_as_f32 (&self) -> Vec<f32> {
+
+        self.to_tensor(ImageDataTensorShapeFormat::HWC).0
+    }
+
+    /*
+        Interleaves 1 to 4 planes into a gray, gray+alpha, RGB or RGBA buffer, the inverse of split_channels().
+        Every plane must hold exactly @width * @height samples of @bit_depth bits.
+     */
+    pub fn from_planes (planes: &[Vec<u8>], width: u32, height: u32, bit_depth: u8) -> Result<PixelBuffer, PngError> {
+
+        let color_type = match planes.len() {
+
+            1 => COLOR_TYPE_GRAYSCALE,
+            2 => COLOR_TYPE_GRAYSCALE_ALPHA,
+            3 => COLOR_TYPE_TRUECOLOR,
+            4 => COLOR_TYPE_TRUECOLOR_ALPHA,
+            count => return Err(PngError::UnsupportedPlaneCount(count)),
+        };
+
+        let length = Self::expected_length(width, height, color_type, bit_depth)?;
+        let plane_length = length / planes.len();
+
+        for (plane, data) in planes.iter().enumerate() {
+
+            if data.len() != plane_length {
+
+                return Err(PngError::PlaneSizeMismatch { plane, expected: plane_length, found: data.len() });
+            }
+        }
+
+        let sample_size = bit_depth as usize / 8;
+        let mut data: Vec<u8> = Vec::with_capacity(length);
+
+        for sample in 0 .. width as usize * height as usize {
+
+            for plane in planes.iter() {
+
+                data.extend_from_slice(&plane[sample * sample_size .. (sample + 1) * sample_size]);
+            }
+        }
+
+        Self::from_data(width, height, color_type, bit_depth, data)
+    }
+
+    /*
+        Same image with every sample rescaled to @bit_depth (8 or 16), 8 to 16 bits multiplies by 257 and 16 to 8 bits rounds to the nearest value
+     */
+    pub fn convert_bit_depth (&self, bit_depth: u8) -> Result<PixelBuffer, PngError> {
+
+        if self.color_type == COLOR_TYPE_INDEXED && bit_depth != self.bit_depth {
+
+            return Err(PngError::UnsupportedColorType(self.color_type));
+        }
+
+        let mut output = PixelBuffer::new(self.width, self.height, self.color_type, bit_depth)?;
+        output.premultiplied = self.premultiplied;
+
+        let from_size = self.bytes_per_sample();
+        let to_size = output.bytes_per_sample();
+
+        for index in 0 .. self.data.len() / from_size {
+
+            let value = self.sample(index * from_size);
+
+            let value = match (self.bit_depth, bit_depth) {
+
+                (8, 16) => value * 257,
+                (16, 8) => (value * 255 + 32767) / 65535,
+                _ => value,
+            };
+
+            output.set_sample(index * to_size, value);
+        }
+
+        Ok(output)
+    }
+
+    /*
+        Same image as RGBA at the same bit depth, gray is copied into all three color channels and missing alpha is opaque
+     */
+    pub fn to_rgba (&self) -> Result<PixelBuffer, PngError> {
+
+        let mut output = PixelBuffer::new(self.width, self.height, COLOR_TYPE_TRUECOLOR_ALPHA, self.bit_depth)?;
+        output.premultiplied = self.premultiplied;
+
+        let max = self.max_sample_value();
+        let sample_size = self.bytes_per_sample();
+        let bytes_per_pixel = self.bytes_per_pixel();
+
+        for pixel in 0 .. self.width as usize * self.height as usize {
+
+            let offset = pixel * bytes_per_pixel;
+            let sample = |channel: usize| self.sample(offset + channel * sample_size);
+
+            let samples = match self.color_type {
+
+                COLOR_TYPE_GRAYSCALE => [sample(0), sample(0), sample(0), max],
+                COLOR_TYPE_GRAYSCALE_ALPHA => [sample(0), sample(0), sample(0), sample(1)],
+                COLOR_TYPE_TRUECOLOR => [sample(0), sample(1), sample(2), max],
+                COLOR_TYPE_TRUECOLOR_ALPHA => [sample(0), sample(1), sample(2), sample(3)],
+                _ => return Err(PngError::UnsupportedColorType(self.color_type)),
+            };
+
+            for (channel, value) in samples.iter().enumerate() {
+
+                output.set_sample((pixel * 4 + channel) * sample_size, *value);
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    /*
+        Buffer whose every sample is distinct (modulo 256 per byte), so any pixel landing in the wrong place shows up
+     */
+    fn numbered (width: u32, height: u32, color_type: u8, bit_depth: u8) -> PixelBuffer {
+
+        let length = PixelBuffer::new(width, height, color_type, bit_depth).unwrap().data.len();
+
+        PixelBuffer::from_data(width, height, color_type, bit_depth, (0 .. length).map(|index| (index * 7 + 1) as u8).collect()).unwrap()
+    }
+
+    #[test]
+    fn flipping_twice_gives_back_the_original () {
+
+        for (color_type, bit_depth) in [(COLOR_TYPE_GRAYSCALE, 8), (COLOR_TYPE_TRUECOLOR, 8), (COLOR_TYPE_TRUECOLOR_ALPHA, 16)] {
+
+            let original = numbered(5, 3, color_type, bit_depth);
+
+            assert_eq!(original.clone().flipped_horizontal().flipped_horizontal().data, original.data);
+            assert_eq!(original.clone().flipped_vertical().flipped_vertical().data, original.data);
+        }
+    }
+
+    #[test]
+    fn flip_horizontal_reverses_pixels_not_bytes () {
+
+        let buffer = PixelBuffer::from_data(3, 1, COLOR_TYPE_GRAYSCALE_ALPHA, 8, vec![1, 2, 3, 4, 5, 6]).unwrap();
+
+        assert_eq!(buffer.flipped_horizontal().data, vec![5, 6, 3, 4, 1, 2]);
+    }
+
+    #[test]
+    fn flips_of_one_pixel_wide_and_tall_images () {
+
+        let column = numbered(1, 4, COLOR_TYPE_TRUECOLOR, 8);
+        let row = numbered(4, 1, COLOR_TYPE_TRUECOLOR, 8);
+
+        assert_eq!(column.clone().flipped_horizontal().data, column.data);
+        assert_eq!(row.clone().flipped_vertical().data, row.data);
+
+        let reversed: Vec<u8> = column.data.chunks(3).rev().flatten().copied().collect();
+
+        assert_eq!(column.clone().flipped_vertical().data, reversed);
+        assert_eq!(row.clone().flipped_horizontal().data, reversed);
+    }
+
+    #[test]
+    fn four_quarter_turns_give_back_the_original () {
+
+        /* Non-square, and larger than a rotation tile with partial tiles along both edges */
+        for (width, height) in [(3, 5), (ROTATION_TILE_SIZE as u32 * 2 + 3, ROTATION_TILE_SIZE as u32 + 5)] {
+
+            let original = numbered(width, height, COLOR_TYPE_TRUECOLOR, 8);
+            let mut rotated = original.clone();
+
+            for _ in 0 .. 4 {
+
+                rotated = rotated.rotate(Rotation::Cw90);
+            }
+
+            assert_eq!((rotated.width, rotated.height), (width, height));
+            assert_eq!(rotated.data, original.data);
+            assert_eq!(original.rotate(Rotation::Cw90).rotate(Rotation::Cw270).data, original.data);
+            assert_eq!(original.rotate(Rotation::Cw180).rotate(Rotation::Cw180).data, original.data);
+        }
+    }
+
+    #[test]
+    fn quarter_turn_moves_pixels_clockwise () {
+
+        /* 1 2 3      4 1
+           4 5 6  ->  5 2
+                      6 3 */
+        let buffer = PixelBuffer::from_data(3, 2, COLOR_TYPE_GRAYSCALE, 8, vec![1, 2, 3, 4, 5, 6]).unwrap();
+        let rotated = buffer.rotate(Rotation::Cw90);
+
+        assert_eq!((rotated.width, rotated.height), (2, 3));
+        assert_eq!(rotated.data, vec![4, 1, 5, 2, 6, 3]);
+    }
+
+    /*
+        Premultiplies then unpremultiplies one RGBA pixel, giving back the color samples
+     */
+    fn premultiply_round_trip (bit_depth: u8, color: u16, alpha: u16) -> [u32; 3] {
+
+        let pixel = Pixel::Rgba(color, color / 2, color / 3, alpha);
+        let mut buffer = PixelBuffer::from_fn(1, 1, COLOR_TYPE_TRUECOLOR_ALPHA, bit_depth, |_, _| pixel).unwrap();
+
+        buffer.premultiply_alpha().unwrap();
+        buffer.unpremultiply_alpha().unwrap();
+
+        let samples = buffer.get_pixel(0, 0).unwrap().samples();
+
+        [samples[0] as u32, samples[1] as u32, samples[2] as u32]
+    }
+
+    #[test]
+    fn premultiply_round_trip_is_within_one () {
+
+        /* Premultiplying keeps alpha + 1 levels per color, ±1 holds once alpha is at least half way to opaque, below that the error grows as max / (2 * alpha) */
+        for (bit_depth, max, step) in [(8, 255u32, 1usize), (16, 65535, 257)] {
+
+            for alpha in (1 ..= max).step_by(step) {
+
+                let bound = if alpha * 2 >= max { 1 } else { max.div_ceil(2 * alpha) };
+
+                for color in (0 ..= max).step_by(step) {
+
+                    let expected = [color, color / 2, color / 3];
+                    let result = premultiply_round_trip(bit_depth, color as u16, alpha as u16);
+
+                    for (found, expected) in result.iter().zip(expected.iter()) {
+
+                        assert!(found.abs_diff(*expected) <= bound, "{} bit, color {} alpha {}: {} came back as {}", bit_depth, color, alpha, expected, found);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn premultiply_state_is_tracked () {
+
+        let mut buffer = PixelBuffer::new(2, 2, COLOR_TYPE_TRUECOLOR_ALPHA, 8).unwrap();
+
+        assert!(matches!(buffer.unpremultiply_alpha(), Err(PngError::AlphaNotPremultiplied)));
+
+        buffer.premultiply_alpha().unwrap();
+
+        assert!(matches!(buffer.premultiply_alpha(), Err(PngError::AlphaAlreadyPremultiplied)));
+        assert_eq!(premultiply_round_trip(8, 200, 0), [0, 0, 0]);
+    }
+
+    #[test]
+    fn composite_over_at_alpha_0_127_and_255 () {
+
+        let blue = PixelBuffer::from_data(1, 1, COLOR_TYPE_TRUECOLOR, 8, vec![0, 0, 255]).unwrap();
+        let red = |alpha: u8| PixelBuffer::from_data(1, 1, COLOR_TYPE_TRUECOLOR_ALPHA, 8, vec![255, 0, 0, alpha]).unwrap();
+
+        assert_eq!(red(0).composite_over(&blue, 0, 0).unwrap().data, [0, 0, 255]);
+        assert_eq!(red(127).composite_over(&blue, 0, 0).unwrap().data, [127, 0, 128]);
+        assert_eq!(red(255).composite_over(&blue, 0, 0).unwrap().data, [255, 0, 0]);
+
+        let translucent_blue = PixelBuffer::from_data(1, 1, COLOR_TYPE_TRUECOLOR_ALPHA, 8, vec![0, 0, 255, 127]).unwrap();
+
+        /* a_out = 127 + 127 * (255 - 127) / 255, red weighs 127 of it and blue the 64 left */
+        assert_eq!(red(127).composite_over(&translucent_blue, 0, 0).unwrap().data, [170, 0, 85, 191]);
+        assert_eq!(red(0).composite_over(&translucent_blue, 0, 0).unwrap().data, [0, 0, 255, 127]);
+    }
+
+    #[test]
+    fn composite_over_clips_negative_offsets () {
+
+        let background = PixelBuffer::from_data(3, 3, COLOR_TYPE_GRAYSCALE, 8, vec![0; 9]).unwrap();
+        let foreground = PixelBuffer::from_data(2, 2, COLOR_TYPE_GRAYSCALE_ALPHA, 8, vec![10, 255, 20, 255, 30, 255, 40, 255]).unwrap();
+
+        assert_eq!(foreground.composite_over(&background, -1, -1).unwrap().data, [40, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(foreground.composite_over(&background, -1, 2).unwrap().data, [0, 0, 0, 0, 0, 0, 20, 0, 0]);
+        assert_eq!(foreground.composite_over(&background, 2, -1).unwrap().data, [0, 0, 30, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(foreground.composite_over(&background, -2, 0).unwrap().data, background.data);
+        assert_eq!(foreground.composite_over(&background, 0, i32::MIN).unwrap().data, background.data);
+    }
+
+    #[test]
+    fn composite_over_checks_formats_even_when_nothing_is_drawn () {
+
+        let background = PixelBuffer::from_data(2, 2, COLOR_TYPE_TRUECOLOR, 8, vec![0; 12]).unwrap();
+        let opaque = PixelBuffer::from_data(1, 1, COLOR_TYPE_TRUECOLOR, 8, vec![1, 2, 3]).unwrap();
+        let gray = PixelBuffer::from_data(1, 1, COLOR_TYPE_GRAYSCALE_ALPHA, 8, vec![1, 255]).unwrap();
+
+        assert!(matches!(opaque.composite_over(&background, -5, -5), Err(PngError::UnsupportedColorType(COLOR_TYPE_TRUECOLOR))));
+        assert!(matches!(gray.composite_over(&background, -5, -5), Err(PngError::IncompatiblePixelFormat { .. })));
+        assert!(matches!(gray.composite_over(&background, 10, 10), Err(PngError::IncompatiblePixelFormat { .. })));
+    }
+
+    #[test]
+    fn paste_into_each_corner () {
+
+        let patch = PixelBuffer::from_data(2, 2, COLOR_TYPE_GRAYSCALE, 8, vec![1, 2, 3, 4]).unwrap();
+
+        for (x, y, expected) in [
+
+            (0, 0, [1, 2, 0, 0, 3, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+            (2, 0, [0, 0, 1, 2, 0, 0, 3, 4, 0, 0, 0, 0, 0, 0, 0, 0]),
+            (0, 2, [0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 0, 0, 3, 4, 0, 0]),
+            (2, 2, [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 0, 0, 3, 4]),
+            /* Hanging off the bottom right corner, only the top left pixel of the patch lands */
+            (3, 3, [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]),
+        ] {
+
+            let mut canvas = PixelBuffer::new(4, 4, COLOR_TYPE_GRAYSCALE, 8).unwrap();
+
+            canvas.paste(&patch, x, y).unwrap();
+
+            assert_eq!(canvas.data, expected, "at ({}, {})", x, y);
+        }
+
+        let mut canvas = PixelBuffer::new(4, 4, COLOR_TYPE_TRUECOLOR, 8).unwrap();
+
+        assert!(matches!(canvas.paste(&patch, 0, 0), Err(PngError::IncompatiblePixelFormat { .. })));
+    }
+
+    #[test]
+    fn alpha_over_refuses_premultiplied_buffers () {
+
+        let mut background = PixelBuffer::from_data(1, 1, COLOR_TYPE_TRUECOLOR_ALPHA, 8, vec![0, 0, 255, 255]).unwrap();
+        let mut foreground = PixelBuffer::from_data(1, 1, COLOR_TYPE_TRUECOLOR_ALPHA, 8, vec![255, 0, 0, 127]).unwrap();
+
+        foreground.premultiply_alpha().unwrap();
+
+        assert!(matches!(background.alpha_over(&foreground, 0, 0), Err(PngError::AlphaAlreadyPremultiplied)));
+
+        foreground.unpremultiply_alpha().unwrap();
+        background.premultiply_alpha().unwrap();
+
+        assert!(matches!(background.alpha_over(&foreground, 0, 0), Err(PngError::AlphaAlreadyPremultiplied)));
+        assert_eq!(background.data, [0, 0, 255, 255]);
+    }
+
+    /*
+        One string per row, '#' for a black pixel and '.' for any other, of an 8 bit gray buffer
+     */
+    fn snapshot (buffer: &PixelBuffer) -> Vec<String> {
+
+        buffer.data.chunks(buffer.stride()).map(|row| row.iter().map(|value| if *value == 0 { '#' } else { '.' }).collect()).collect()
+    }
+
+    #[test]
+    fn resize_nearest_of_a_checkerboard () {
+
+        let board = crate::patterns::checkerboard(4, 4, 1, Pixel::Gray(0), Pixel::Gray(255)).unwrap();
+
+        assert_eq!(snapshot(&board.resize_nearest(8, 8)), snapshot(&crate::patterns::checkerboard(8, 8, 2, Pixel::Gray(0), Pixel::Gray(255)).unwrap()));
+        assert_eq!(snapshot(&board.resize_nearest(8, 8).resize_nearest(4, 4)), snapshot(&board));
+        assert_eq!(snapshot(&board.resize_nearest(6, 6)), ["#..#..", ".##.##", ".##.##", "#..#..", ".##.##", ".##.##"]);
+
+        /* Halving samples pixels (1, 1), (3, 1), (1, 3) and (3, 3), all of them black */
+        assert_eq!(snapshot(&board.resize_nearest(2, 2)), ["##", "##"]);
+    }
+
+    #[test]
+    fn resize_bilinear_of_black_and_white_to_mid_gray () {
+
+        let board = PixelBuffer::from_data(2, 2, COLOR_TYPE_GRAYSCALE, 8, vec![0, 255, 255, 0]).unwrap();
+
+        assert_eq!(board.resize_bilinear(1, 1, false).data, [128]);
+        assert_eq!(board.resize_bilinear(1, 1, true).data, [128]);
+
+        let wide = PixelBuffer::from_data(2, 2, COLOR_TYPE_GRAYSCALE, 16, vec![0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0]).unwrap();
+
+        assert_eq!(wide.resize_bilinear(1, 1, false).data, [0x80, 0x00]);
+    }
+
+    #[test]
+    fn resize_bilinear_with_premultiplied_alpha () {
+
+        /* Opaque white and fully transparent black on the diagonals */
+        let mut board = PixelBuffer::from_data(2, 2, COLOR_TYPE_GRAYSCALE_ALPHA, 8, vec![255, 255, 0, 0, 0, 0, 255, 255]).unwrap();
+
+        /* Straight interpolation lets the black of the transparent pixels darken the result, premultiplying keeps it white */
+        assert_eq!(board.resize_bilinear(1, 1, false).data, [128, 128]);
+        assert_eq!(board.resize_bilinear(1, 1, true).data, [255, 128]);
+
+        board.premultiply_alpha().unwrap();
+
+        let resized = board.resize_bilinear(1, 1, false);
+
+        assert!(resized.premultiplied);
+        assert_eq!(resized.data, [128, 128]);
+    }
+
+    fn canvas (width: u32, height: u32) -> PixelBuffer {
+
+        PixelBuffer::from_data(width, height, COLOR_TYPE_GRAYSCALE, 8, vec![255; width as usize * height as usize]).unwrap()
+    }
+
+    #[test]
+    fn draw_horizontal_vertical_and_diagonal_lines () {
+
+        let mut image = canvas(5, 5);
+
+        image.draw_line(1, 1, 3, 1, Pixel::Gray(0), 1).unwrap();
+        image.draw_line(0, 4, 0, 2, Pixel::Gray(0), 1).unwrap();
+
+        assert_eq!(snapshot(&image), [".....", ".###.", "#....", "#....", "#...."]);
+
+        let mut image = canvas(5, 5);
+
+        image.draw_line(0, 0, 4, 4, Pixel::Gray(0), 1).unwrap();
+        image.draw_line(4, 0, 3, 1, Pixel::Gray(0), 1).unwrap();
+
+        assert_eq!(snapshot(&image), ["#...#", ".#.#.", "..#..", "...#.", "....#"]);
+
+        let mut image = canvas(5, 5);
+
+        image.draw_line(0, 2, 4, 2, Pixel::Gray(0), 3).unwrap();
+
+        assert_eq!(snapshot(&image), [".....", "#####", "#####", "#####", "....."]);
+    }
+
+    #[test]
+    fn draw_line_clips_endpoints_off_the_canvas () {
+
+        let mut image = canvas(4, 4);
+
+        image.draw_line(-3, 1, 10, 1, Pixel::Gray(0), 1).unwrap();
+        image.draw_line(2, -5, 2, 2, Pixel::Gray(0), 1).unwrap();
+
+        assert_eq!(snapshot(&image), ["..#.", "####", "..#.", "...."]);
+
+        let mut image = canvas(4, 4);
+
+        /* Diagonal entering at the top left and leaving at the bottom right */
+        image.draw_line(-2, -2, 6, 6, Pixel::Gray(0), 1).unwrap();
+
+        assert_eq!(snapshot(&image), ["#...", ".#..", "..#.", "...#"]);
+
+        let mut image = canvas(4, 4);
+
+        image.draw_line(-10, -10, -1, 20, Pixel::Gray(0), 1).unwrap();
+
+        assert_eq!(snapshot(&image), ["....", "....", "....", "...."]);
+        assert!(matches!(image.draw_line(0, 0, 1, 1, Pixel::Rgb(0, 0, 0), 1), Err(PngError::IncompatiblePixelFormat { .. })));
+    }
+
+    #[test]
+    fn draw_rect_outline_pixels () {
+
+        let mut image = canvas(5, 5);
+
+        image.draw_rect_outline(0, 0, 4, 3, Pixel::Gray(0), 1).unwrap();
+
+        assert_eq!(snapshot(&image), ["####.", "#..#.", "####.", ".....", "....."]);
+
+        let mut image = canvas(5, 5);
+
+        image.draw_rect_outline(2, 2, 10, 10, Pixel::Gray(0), 2).unwrap();
+
+        assert_eq!(snapshot(&image), [".....", ".....", "..###", "..###", "..##."]);
+    }
+
+    #[test]
+    fn fill_rect_solid_and_clipped () {
+
+        let mut image = canvas(4, 3);
+
+        image.fill_rect(1, 1, 2, 2, Pixel::Gray(0)).unwrap();
+
+        assert_eq!(snapshot(&image), ["....", ".##.", ".##."]);
+
+        image.fill_rect(0, 0, 4, 3, Pixel::Gray(0)).unwrap();
+
+        assert_eq!(snapshot(&image), ["####", "####", "####"]);
+
+        let mut image = canvas(4, 3);
+
+        image.fill_rect(2, 1, 100, u32::MAX, Pixel::Gray(0)).unwrap();
+        image.fill_rect(4, 0, 1, 1, Pixel::Gray(0)).unwrap();
+
+        assert_eq!(snapshot(&image), ["....", "..##", "..##"]);
+
+        let mut rgb = PixelBuffer::new(2, 1, COLOR_TYPE_TRUECOLOR, 16).unwrap();
+
+        rgb.fill_rect(1, 0, 5, 5, Pixel::Rgb(0x0102, 0x0304, 0x0506)).unwrap();
+
+        assert_eq!(rgb.data, [0, 0, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6]);
+        assert!(matches!(rgb.fill_rect(0, 0, 1, 1, Pixel::Gray(0)), Err(PngError::IncompatiblePixelFormat { .. })));
+    }
+
+    #[test]
+    fn adjust_known_values () {
+
+        let ramp = PixelBuffer::from_data(3, 1, COLOR_TYPE_GRAYSCALE, 8, vec![0, 128, 255]).unwrap();
+
+        assert_eq!(ramp.adjust(0, 1.0).data, [0, 128, 255]);
+        assert_eq!(ramp.adjust(20, 1.0).data, [20, 148, 255]);
+        assert_eq!(ramp.adjust(-20, 1.0).data, [0, 108, 235]);
+        assert_eq!(ramp.adjust(0, 0.5).data, [64, 128, 192]);
+        assert_eq!(ramp.adjust(0, 2.0).data, [0, 128, 255]);
+        assert_eq!(ramp.adjust(0, 0.0).data, [128, 128, 128]);
+
+        let translucent = PixelBuffer::from_data(1, 1, COLOR_TYPE_GRAYSCALE_ALPHA, 8, vec![128, 77]).unwrap();
+
+        assert_eq!(translucent.adjust(20, 0.5).data, [148, 77]);
+
+        /* Brightness is in 8 bit steps whatever the bit depth */
+        let wide = PixelBuffer::from_data(1, 1, COLOR_TYPE_GRAYSCALE, 16, vec![0x80, 0x00]).unwrap();
+
+        assert_eq!(wide.adjust(1, 1.0).data, [0x81, 0x01]);
+    }
+
+    #[test]
+    fn inverting_twice_gives_back_the_original () {
+
+        for (color_type, bit_depth) in [(COLOR_TYPE_GRAYSCALE, 8), (COLOR_TYPE_TRUECOLOR, 16), (COLOR_TYPE_GRAYSCALE_ALPHA, 8), (COLOR_TYPE_TRUECOLOR_ALPHA, 16)] {
+
+            let original = numbered(3, 2, color_type, bit_depth);
+            let mut image = original.clone();
+
+            image.invert().unwrap();
+
+            assert_ne!(image.data, original.data);
+
+            image.invert().unwrap();
+
+            assert_eq!(image.data, original.data);
+        }
+
+        let mut translucent = PixelBuffer::from_data(1, 1, COLOR_TYPE_GRAYSCALE_ALPHA, 8, vec![10, 77]).unwrap();
+
+        translucent.invert().unwrap();
+
+        assert_eq!(translucent.data, [245, 77]);
+    }
+
+    #[test]
+    fn invert_refuses_indexed_buffers () {
+
+        let mut indexed = PixelBuffer::from_data(2, 1, COLOR_TYPE_INDEXED, 8, vec![0, 1]).unwrap();
+
+        assert!(matches!(indexed.invert(), Err(PngError::UnsupportedColorType(COLOR_TYPE_INDEXED))));
+        assert_eq!(indexed.data, [0, 1]);
+    }
+
+    #[test]
+    fn split_and_extract_channels () {
+
+        let rgba = PixelBuffer::from_data(2, 1, COLOR_TYPE_TRUECOLOR_ALPHA, 8, vec![1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+
+        assert_eq!(rgba.split_channels(), [vec![1, 5], vec![2, 6], vec![3, 7], vec![4, 8]]);
+        assert_eq!(rgba.extract_channel(2).unwrap(), [3, 7]);
+        assert!(matches!(rgba.extract_channel(4), Err(PngError::ChannelOutOfRange { index: 4, channels: 4 })));
+
+        /* 16 bit samples keep both bytes together */
+        let wide = PixelBuffer::from_data(2, 1, COLOR_TYPE_GRAYSCALE_ALPHA, 16, vec![1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+
+        assert_eq!(wide.split_channels(), [vec![1, 2, 5, 6], vec![3, 4, 7, 8]]);
+        assert_eq!(wide.extract_channel(1).unwrap(), [3, 4, 7, 8]);
+    }
+
+    #[test]
+    fn split_then_merge_gives_back_the_original () {
+
+        for (color_type, bit_depth) in [(COLOR_TYPE_GRAYSCALE, 8), (COLOR_TYPE_GRAYSCALE_ALPHA, 16), (COLOR_TYPE_TRUECOLOR, 8), (COLOR_TYPE_TRUECOLOR_ALPHA, 16)] {
+
+            let original = numbered(3, 2, color_type, bit_depth);
+            let merged = PixelBuffer::from_planes(&original.split_channels(), 3, 2, bit_depth).unwrap();
+
+            assert_eq!(merged.color_type, color_type);
+            assert_eq!(merged.data, original.data);
+        }
+    }
+
+    #[test]
+    fn from_planes_checks_count_and_length () {
+
+        let plane = vec![0u8; 4];
+
+        assert!(matches!(PixelBuffer::from_planes(&[], 2, 2, 8), Err(PngError::UnsupportedPlaneCount(0))));
+        assert!(matches!(PixelBuffer::from_planes(&vec![plane.clone(); 5], 2, 2, 8), Err(PngError::UnsupportedPlaneCount(5))));
+        assert!(matches!(PixelBuffer::from_planes(&[plane.clone(), vec![0; 3], plane.clone()], 2, 2, 8), Err(PngError::PlaneSizeMismatch { plane: 1, expected: 4, found: 3 })));
+        assert!(matches!(PixelBuffer::from_planes(&[plane.clone(), plane.clone()], 2, 2, 16), Err(PngError::PlaneSizeMismatch { plane: 0, expected: 8, found: 4 })));
+    }
+
+    #[test]
+    fn channels_as_floats_at_8_and_16_bits () {
+
+        let rgb = PixelBuffer::from_data(2, 1, COLOR_TYPE_TRUECOLOR, 8, vec![0, 51, 255, 102, 204, 0]).unwrap();
+
+        assert_eq!(rgb.channel_as_f64(1).unwrap(), [0.2, 0.8]);
+        assert_eq!(rgb.all_channels_as_f32(), [0.0, 0.2, 1.0, 0.4, 0.8, 0.0]);
+        assert!(matches!(rgb.channel_as_f64(3), Err(PngError::ChannelOutOfRange { index: 3, channels: 3 })));
+
+        /* 16 bit samples are big endian and scaled by 65535 */
+        let wide = PixelBuffer::from_data(2, 1, COLOR_TYPE_GRAYSCALE_ALPHA, 16, vec![0xFF, 0xFF, 0x00, 0x00, 0x33, 0x33, 0xCC, 0xCC]).unwrap();
+
+        assert_eq!(wide.channel_as_f64(0).unwrap(), [1.0, 0.2]);
+        assert_eq!(wide.channel_as_f64(1).unwrap(), [0.0, 0.8]);
+        assert_eq!(wide.all_channels_as_f32(), [1.0, 0.0, 0.2, 0.8]);
+    }
+
+    #[test]
+    fn extracted_alpha_put_back_gives_the_original () {
+
+        for (color_type, bit_depth) in [(COLOR_TYPE_TRUECOLOR_ALPHA, 8), (COLOR_TYPE_TRUECOLOR_ALPHA, 16), (COLOR_TYPE_GRAYSCALE_ALPHA, 8), (COLOR_TYPE_GRAYSCALE_ALPHA, 16)] {
+
+            let original = numbered(3, 2, color_type, bit_depth);
+            let alpha = original.extract_alpha(&[]).unwrap().unwrap();
+
+            assert_eq!((alpha.width, alpha.height, alpha.color_type, alpha.bit_depth), (3, 2, COLOR_TYPE_GRAYSCALE, bit_depth));
+
+            let planes = original.split_channels();
+            let colors = PixelBuffer::from_planes(&planes[.. planes.len() - 1], 3, 2, bit_depth).unwrap();
+
+            assert_eq!(colors.with_alpha_from(&alpha).unwrap().data, original.data);
+        }
+    }
+
+    #[test]
+    fn extract_alpha_from_trns_or_none () {
+
+        let rgb = PixelBuffer::from_data(2, 1, COLOR_TYPE_TRUECOLOR, 8, vec![1, 2, 3, 4, 5, 6]).unwrap();
+
+        assert!(rgb.extract_alpha(&[]).unwrap().is_none());
+        assert_eq!(rgb.extract_alpha(&[0, 4, 0, 5, 0, 6]).unwrap().unwrap().data, [0xFF, 0]);
+        assert!(matches!(rgb.extract_alpha(&[0, 4]), Err(PngError::InvalidChunkLength { type_name: "tRNS", expected: 6, found: 2 })));
+
+        let indexed = PixelBuffer::from_data(3, 1, COLOR_TYPE_INDEXED, 8, vec![0, 1, 2]).unwrap();
+
+        assert_eq!(indexed.extract_alpha(&[0x10, 0x20]).unwrap().unwrap().data, [0x10, 0x20, 0xFF]);
+
+        /* An 8 bit mask on a 16 bit buffer is rescaled by 257 */
+        let wide = PixelBuffer::from_data(1, 1, COLOR_TYPE_GRAYSCALE, 16, vec![0x12, 0x34]).unwrap();
+        let mask = PixelBuffer::from_data(1, 1, COLOR_TYPE_GRAYSCALE, 8, vec![0x80]).unwrap();
+
+        assert_eq!(wide.with_alpha_from(&mask).unwrap().data, [0x12, 0x34, 0x80, 0x80]);
+        assert!(matches!(wide.with_alpha_from(&rgb), Err(PngError::IncompatiblePixelFormat { .. })));
+    }
+
+    #[test]
+    fn apply_mask_replace_and_multiply () {
+
+        let mask = PixelBuffer::from_data(4, 1, COLOR_TYPE_GRAYSCALE, 8, vec![0, 255, 128, 64]).unwrap();
+
+        /* RGB is given an alpha channel first, Replace makes it the mask */
+        let mut rgb = PixelBuffer::from_data(4, 1, COLOR_TYPE_TRUECOLOR, 8, vec![10; 12]).unwrap();
+
+        rgb.apply_mask(&mask, MaskMode::Replace).unwrap();
+
+        assert_eq!(rgb.color_type, COLOR_TYPE_TRUECOLOR_ALPHA);
+        assert_eq!(rgb.extract_channel(3).unwrap(), [0, 255, 128, 64]);
+        assert_eq!(rgb.extract_channel(0).unwrap(), [10; 4]);
+
+        /* Multiply: 200 * 0 / 255 = 0, 200 * 255 / 255 = 200, 200 * 128 / 255 = 100.39 and 100 * 64 / 255 = 25.1 */
+        let mut gray_alpha = PixelBuffer::from_data(4, 1, COLOR_TYPE_GRAYSCALE_ALPHA, 8, vec![1, 200, 2, 200, 3, 200, 4, 100]).unwrap();
+
+        gray_alpha.apply_mask(&mask, MaskMode::Multiply).unwrap();
+
+        assert_eq!(gray_alpha.data, [1, 0, 2, 200, 3, 100, 4, 25]);
+
+        /* 200 * 192 / 255 = 150.59 rounds up */
+        let mut rounding = PixelBuffer::from_data(1, 1, COLOR_TYPE_GRAYSCALE_ALPHA, 8, vec![0, 200]).unwrap();
+
+        rounding.apply_mask(&PixelBuffer::from_data(1, 1, COLOR_TYPE_GRAYSCALE, 8, vec![192]).unwrap(), MaskMode::Multiply).unwrap();
+
+        assert_eq!(rounding.data, [0, 151]);
+    }
+
+    #[test]
+    fn apply_mask_refuses_bad_masks () {
+
+        let mut rgba = PixelBuffer::new(2, 2, COLOR_TYPE_TRUECOLOR_ALPHA, 8).unwrap();
+
+        assert!(matches!(rgba.apply_mask(&PixelBuffer::new(2, 1, COLOR_TYPE_GRAYSCALE, 8).unwrap(), MaskMode::Replace), Err(PngError::DimensionMismatch { expected: (2, 2), found: (2, 1) })));
+        assert!(matches!(rgba.apply_mask(&PixelBuffer::new(2, 2, COLOR_TYPE_TRUECOLOR, 8).unwrap(), MaskMode::Replace), Err(PngError::IncompatiblePixelFormat { .. })));
+
+        rgba.premultiplied = true;
+
+        assert!(matches!(rgba.apply_mask(&PixelBuffer::new(2, 2, COLOR_TYPE_GRAYSCALE, 8).unwrap(), MaskMode::Replace), Err(PngError::AlphaAlreadyPremultiplied)));
+    }
+}
@@ -0,0 +1,252 @@
+/*
+    lib/rust/png/src/stream.rs
+    Q@khaa.pk
+ */
+
+use std::sync::Arc;
+
+use crate::constants;
+use crate::error::PngError;
+use crate::{Chunk, DecodeLimits, Png};
+
+/*
+    What StreamingParser::push() saw complete in the bytes it was given, in file order
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub enum ParsedEvent {
+
+    /* First 8 bytes were the PNG signature */
+    Signature,
+    /* A whole chunk, CRC included, has arrived */
+    Chunk(Chunk),
+    /* IEND has arrived, bytes pushed after it are ignored */
+    End,
+}
+
+/*
+    Parses a PNG whose bytes arrive in pieces of any size, e.g. from a network stream, and hands out each chunk as soon as its last byte is in.
+    Only the bytes of the chunk (or signature) not yet complete are buffered. IHDR is checked against the DecodeLimits as soon as it arrives,
+    and a chunk whose length field alone is over max_decoded_bytes is refused before any of it is buffered.
+ */
+#[derive(Clone, Debug)]
+pub struct StreamingParser {
+
+    limits: DecodeLimits,
+    /* Start of the signature or chunk not yet complete */
+    pending: Vec<u8>,
+    /* Byte offset in the file of pending[0] */
+    offset: usize,
+    png: Png,
+    ended: bool,
+}
+
+impl Default for StreamingParser {
+
+    fn default () -> Self {
+
+        StreamingParser::new(DecodeLimits::default())
+    }
+}
+
+impl StreamingParser {
+
+    pub fn new (limits: DecodeLimits) -> Self {
+
+        Self {
+
+            limits,
+            pending: Vec::new(),
+            offset: 0,
+            png: Png { signature: Vec::new(), chunks: Vec::new() },
+            ended: false,
+        }
+    }
+
+    /*
+        Feeds the next @bytes of the file and gives back the events they completed, none when they only extend a partial chunk.
+        InvalidSignature when the file doesn't start with the PNG signature, and whatever DecodeLimits::check() gives for an IHDR over the limits.
+     */
+    pub fn push (&mut self, bytes: &[u8]) -> Result<Vec<ParsedEvent>, PngError> {
+
+        let mut events: Vec<ParsedEvent> = Vec::new();
+
+        if self.ended {
+
+            return Ok(events);
+        }
+
+        self.pending.extend_from_slice(bytes);
+
+        let mut start: usize = 0;
+
+        if self.png.signature.is_empty() {
+
+            if self.pending.len() < constants::LENGTH_OF_SIGNATURE {
+
+                if self.pending[..] != constants::PNG_SIGNATURE[.. self.pending.len()] {
+
+                    return Err(PngError::InvalidSignature);
+                }
+
+                return Ok(events);
+            }
+
+            if self.pending[.. constants::LENGTH_OF_SIGNATURE] != constants::PNG_SIGNATURE {
+
+                return Err(PngError::InvalidSignature);
+            }
+
+            self.png.signature = constants::PNG_SIGNATURE.to_vec();
+            start = constants::LENGTH_OF_SIGNATURE;
+
+            events.push(ParsedEvent::Signature);
+        }
+
+        while !self.ended && self.pending.len() - start >= constants::LENGTH_OF_THREE_FIELDS {
+
+            let length = u32::from_be_bytes([self.pending[start], self.pending[start + 1], self.pending[start + 2], self.pending[start + 3]]) as usize;
+
+            if length as u64 > self.limits.max_decoded_bytes {
+
+                return Err(PngError::DecodeLimitExceeded { limit: self.limits.max_decoded_bytes, estimate: length as u64 });
+            }
+
+            let end = start + constants::LENGTH_OF_THREE_FIELDS + length;
+
+            if self.pending.len() < end {
+
+                break;
+            }
+
+            let chunk = Chunk {
+
+                length: self.pending[start .. start + 4].to_vec(),
+                type_name: self.pending[start + 4 .. start + 8].to_vec(),
+                data: Arc::new(self.pending[start + 8 .. end - constants::LENGTH_OF_CRC_FIELD].to_vec()),
+                crc: self.pending[end - constants::LENGTH_OF_CRC_FIELD .. end].to_vec(),
+            };
+
+            start = end;
+
+            let is_ihdr = chunk.type_name == b"IHDR";
+            self.ended = chunk.type_name == b"IEND";
+
+            self.png.chunks.push(chunk.clone());
+
+            if is_ihdr {
+
+                self.limits.check(&self.png)?;
+            }
+
+            events.push(ParsedEvent::Chunk(chunk));
+        }
+
+        self.pending.drain(.. start);
+        self.offset += start;
+
+        if self.ended {
+
+            self.pending = Vec::new();
+
+            events.push(ParsedEvent::End);
+        }
+
+        Ok(events)
+    }
+
+    /*
+        True once IEND has arrived
+     */
+    pub fn is_finished (&self) -> bool {
+
+        self.ended
+    }
+
+    /*
+        The image assembled from every chunk so far, None until IEND has arrived
+     */
+    pub fn png (&self) -> Option<&Png> {
+
+        if self.ended { Some(&self.png) } else { None }
+    }
+
+    /*
+        The assembled image, TruncatedChunk (at the offset of the partial chunk) when the stream stopped before IEND
+     */
+    pub fn into_png (self) -> Result<Png, PngError> {
+
+        if !self.ended {
+
+            return Err(PngError::TruncatedChunk { offset: self.offset });
+        }
+
+        Ok(self.png)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{fixtures, ParseOptions};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn parse_in_pieces (bytes: &[u8], mut piece_length: impl FnMut() -> usize) -> (Png, Vec<ParsedEvent>) {
+
+        let mut parser = StreamingParser::default();
+        let mut events: Vec<ParsedEvent> = Vec::new();
+        let mut position = 0;
+
+        while position < bytes.len() {
+
+            let end = (position + piece_length()).min(bytes.len());
+
+            events.extend(parser.push(&bytes[position .. end]).unwrap());
+            position = end;
+        }
+
+        (parser.into_png().unwrap(), events)
+    }
+
+    #[test]
+    fn one_byte_at_a_time_matches_parse () {
+
+        let bytes = fixtures::rgb();
+        let (png, events) = parse_in_pieces(&bytes, || 1);
+        let (parsed, _) = Png::parse(&bytes, &ParseOptions::default()).unwrap();
+
+        assert!(png == parsed);
+        assert_eq!(png.to_bytes(), bytes);
+        assert_eq!(events.first(), Some(&ParsedEvent::Signature));
+        assert_eq!(events.last(), Some(&ParsedEvent::End));
+        assert_eq!(events.len(), 1 + parsed.get_chunks().len() + 1);
+    }
+
+    #[test]
+    fn random_pieces_match_parse () {
+
+        let bytes = fixtures::rgb();
+        let (parsed, _) = Png::parse(&bytes, &ParseOptions::default()).unwrap();
+        let mut rng = StdRng::seed_from_u64(1402);
+
+        for _ in 0 .. 32 {
+
+            let (png, _) = parse_in_pieces(&bytes, || rng.gen_range(1 ..= 20));
+
+            assert!(png == parsed);
+        }
+    }
+
+    #[test]
+    fn stream_stopping_early_is_truncated () {
+
+        let bytes = fixtures::rgb();
+        let mut parser = StreamingParser::default();
+
+        parser.push(&bytes[.. bytes.len() - 1]).unwrap();
+
+        assert!(!parser.is_finished());
+        assert_eq!(parser.into_png().unwrap_err(), PngError::TruncatedChunk { offset: bytes.len() - 12 });
+    }
+}
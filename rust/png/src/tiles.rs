@@ -0,0 +1,231 @@
+/*
+    lib/rust/png/src/tiles.rs
+    Q@khaa.pk
+ */
+
+use crate::constants::COLOR_TYPE_TRUECOLOR_ALPHA;
+use crate::error::PngError;
+use crate::images::{extract_block, ImageBlock};
+use crate::pixels::PixelBuffer;
+
+/*
+    One tile of PixelBuffer::tiles(), its place in the grid, where its top left pixel was in the source and its pixels
+ */
+#[derive(Clone)]
+pub struct Tile {
+
+    pub column: u32,
+    pub row: u32,
+    pub x: u32,
+    pub y: u32,
+    pub pixels: PixelBuffer,
+}
+
+impl PixelBuffer {
+
+    /*
+        Cuts the image into a grid of @tile_width by @tile_height tiles, row by row from the top left.
+        Tiles along the right and bottom edges are as large as what is left of the image, with @pad they are the full size instead, the extra pixels zero (black, transparent where there is alpha).
+     */
+    pub fn tiles (&self, tile_width: u32, tile_height: u32, pad: bool) -> Result<Vec<Tile>, PngError> {
+
+        if tile_width == 0 || tile_height == 0 {
+
+            return Err(PngError::InvalidDimensions { width: tile_width, height: tile_height });
+        }
+
+        let columns = self.width.div_ceil(tile_width);
+        let rows = self.height.div_ceil(tile_height);
+        let mut tiles: Vec<Tile> = Vec::with_capacity(columns as usize * rows as usize);
+
+        for row in 0 .. rows {
+
+            for column in 0 .. columns {
+
+                let (x, y) = (column * tile_width, row * tile_height);
+                let (width, height) = (tile_width.min(self.width - x), tile_height.min(self.height - y));
+
+                let block = ImageBlock::new(height as usize, width as usize);
+                let mut pixels = PixelBuffer::from_data(width, height, self.color_type, self.bit_depth, extract_block(self, &block, x, y)?)?;
+
+                if pad && (width != tile_width || height != tile_height) {
+
+                    let mut padded = PixelBuffer::new(tile_width, tile_height, self.color_type, self.bit_depth)?;
+
+                    padded.paste(&pixels, 0, 0)?;
+                    pixels = padded;
+                }
+
+                pixels.premultiplied = self.premultiplied;
+
+                tiles.push(Tile { column, row, x, y, pixels });
+            }
+        }
+
+        Ok(tiles)
+    }
+
+    /*
+        Puts @tiles back together on a grid of @columns by @rows, the inverse of tiles(). Only the column and row of each tile count, not its x and y.
+        Each grid column is as wide as its widest tile and each grid row as tall as its tallest, so smaller edge tiles fit, and cells no tile covers stay zero.
+        Tiles have to share color type and bit depth, unless @convert is given, then all are turned into RGBA at the largest bit depth among them.
+     */
+    pub fn stitch (tiles: &[Tile], columns: u32, rows: u32, convert: bool) -> Result<PixelBuffer, PngError> {
+
+        let first = match tiles.first() {
+
+            Some(tile) => &tile.pixels,
+            None => return Err(PngError::EmptyImage),
+        };
+
+        let mut column_widths: Vec<u32> = vec![0; columns as usize];
+        let mut row_heights: Vec<u32> = vec![0; rows as usize];
+        let mut same_format = true;
+
+        for tile in tiles.iter() {
+
+            if tile.column >= columns || tile.row >= rows {
+
+                return Err(PngError::TileOutsideGrid { column: tile.column, row: tile.row, columns, rows });
+            }
+
+            if tile.pixels.color_type != first.color_type || tile.pixels.bit_depth != first.bit_depth {
+
+                if !convert {
+
+                    return Err(PngError::IncompatiblePixelFormat { expected: (first.color_type, first.bit_depth), found: (tile.pixels.color_type, tile.pixels.bit_depth) });
+                }
+
+                same_format = false;
+            }
+
+            column_widths[tile.column as usize] = column_widths[tile.column as usize].max(tile.pixels.width);
+            row_heights[tile.row as usize] = row_heights[tile.row as usize].max(tile.pixels.height);
+        }
+
+        let bit_depth = tiles.iter().map(|tile| tile.pixels.bit_depth).max().unwrap_or(first.bit_depth);
+        let (color_type, bit_depth) = if same_format { (first.color_type, first.bit_depth) } else { (COLOR_TYPE_TRUECOLOR_ALPHA, bit_depth) };
+
+        let total = |sizes: &[u32]| u32::try_from(sizes.iter().map(|size| *size as u64).sum::<u64>()).unwrap_or(u32::MAX);
+        let (width, height) = (total(&column_widths), total(&row_heights));
+
+        if width > i32::MAX as u32 || height > i32::MAX as u32 {
+
+            return Err(PngError::InvalidDimensions { width, height });
+        }
+
+        let mut output = PixelBuffer::new(width, height, color_type, bit_depth)?;
+
+        for tile in tiles.iter() {
+
+            let x: u32 = column_widths[.. tile.column as usize].iter().sum();
+            let y: u32 = row_heights[.. tile.row as usize].iter().sum();
+
+            if same_format {
+
+                output.paste(&tile.pixels, x, y)?;
+            }
+            else {
+
+                output.paste(&tile.pixels.to_rgba()?.convert_bit_depth(bit_depth)?, x, y)?;
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::constants::{COLOR_TYPE_GRAYSCALE, COLOR_TYPE_TRUECOLOR};
+
+    /*
+        10 by 10 gray image whose pixel at (x, y) is y * 10 + x
+     */
+    fn numbered () -> PixelBuffer {
+
+        PixelBuffer::from_data(10, 10, COLOR_TYPE_GRAYSCALE, 8, (0 .. 100).collect()).unwrap()
+    }
+
+    #[test]
+    fn ten_by_ten_in_three_by_three_tiles () {
+
+        let tiles = numbered().tiles(3, 3, false).unwrap();
+
+        assert_eq!(tiles.len(), 16);
+        assert_eq!(tiles.iter().map(|tile| (tile.column, tile.row)).collect::<Vec<(u32, u32)>>()[.. 5], [(0, 0), (1, 0), (2, 0), (3, 0), (0, 1)]);
+
+        let inner = &tiles[5];
+
+        assert_eq!((inner.x, inner.y, inner.pixels.width, inner.pixels.height), (3, 3, 3, 3));
+        assert_eq!(inner.pixels.data, [33, 34, 35, 43, 44, 45, 53, 54, 55]);
+
+        /* The last column and row are what is left, one pixel wide or high */
+        let edge = &tiles[3];
+
+        assert_eq!((edge.x, edge.y, edge.pixels.width, edge.pixels.height), (9, 0, 1, 3));
+        assert_eq!(edge.pixels.data, [9, 19, 29]);
+        assert_eq!(tiles[15].pixels.data, [99]);
+    }
+
+    #[test]
+    fn padded_tiles_are_full_size () {
+
+        let tiles = numbered().tiles(3, 3, true).unwrap();
+
+        assert_eq!(tiles.len(), 16);
+        assert!(tiles.iter().all(|tile| tile.pixels.width == 3 && tile.pixels.height == 3));
+        assert_eq!(tiles[5].pixels.data, [33, 34, 35, 43, 44, 45, 53, 54, 55]);
+        assert_eq!(tiles[3].pixels.data, [9, 0, 0, 19, 0, 0, 29, 0, 0]);
+        assert_eq!(tiles[15].pixels.data, [99, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        assert!(matches!(numbered().tiles(0, 3, true), Err(PngError::InvalidDimensions { width: 0, height: 3 })));
+    }
+
+    #[test]
+    fn stitching_the_tiles_gives_back_the_image () {
+
+        let image = numbered();
+
+        assert_eq!(PixelBuffer::stitch(&image.tiles(3, 3, false).unwrap(), 4, 4, false).unwrap().data, image.data);
+
+        /* Padded tiles stitch to the padded size, the image in its top left corner */
+        let padded = PixelBuffer::stitch(&image.tiles(3, 3, true).unwrap(), 4, 4, false).unwrap();
+
+        assert_eq!((padded.width, padded.height), (12, 12));
+        assert_eq!(padded.data[.. 12], [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 0]);
+        assert_eq!(padded.data[9 * 12 .. 10 * 12], [90, 91, 92, 93, 94, 95, 96, 97, 98, 99, 0, 0]);
+        assert!(padded.data[10 * 12 ..].iter().all(|sample| *sample == 0));
+    }
+
+    #[test]
+    fn stitch_refuses_tiles_outside_the_grid () {
+
+        let tiles = numbered().tiles(5, 5, false).unwrap();
+
+        assert!(matches!(PixelBuffer::stitch(&tiles, 2, 1, false), Err(PngError::TileOutsideGrid { column: 0, row: 1, columns: 2, rows: 1 })));
+        assert!(matches!(PixelBuffer::stitch(&[], 1, 1, false), Err(PngError::EmptyImage)));
+    }
+
+    #[test]
+    fn stitch_of_mixed_formats_needs_convert () {
+
+        let gray = PixelBuffer::from_data(1, 1, COLOR_TYPE_GRAYSCALE, 8, vec![10]).unwrap();
+        let rgb = PixelBuffer::from_data(1, 1, COLOR_TYPE_TRUECOLOR, 16, vec![0xFF, 0xFF, 0, 0, 0x80, 0x80]).unwrap();
+
+        let tiles = [
+
+            Tile { column: 0, row: 0, x: 0, y: 0, pixels: gray },
+            Tile { column: 1, row: 0, x: 1, y: 0, pixels: rgb },
+        ];
+
+        assert!(matches!(PixelBuffer::stitch(&tiles, 2, 1, false), Err(PngError::IncompatiblePixelFormat { expected: (COLOR_TYPE_GRAYSCALE, 8), found: (COLOR_TYPE_TRUECOLOR, 16) })));
+
+        let stitched = PixelBuffer::stitch(&tiles, 2, 1, true).unwrap();
+
+        assert_eq!((stitched.color_type, stitched.bit_depth), (COLOR_TYPE_TRUECOLOR_ALPHA, 16));
+        assert_eq!(stitched.data, [0x0A, 0x0A, 0x0A, 0x0A, 0x0A, 0x0A, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0x80, 0x80, 0xFF, 0xFF]);
+    }
+}
@@ -0,0 +1,408 @@
+/*
+    lib/rust/png/src/validate.rs
+    Q@khaa.pk
+ */
+
+use std::fmt;
+
+use crate::constants::{PNG_SIGNATURE, COLOR_TYPE_GRAYSCALE, COLOR_TYPE_TRUECOLOR, COLOR_TYPE_INDEXED, COLOR_TYPE_GRAYSCALE_ALPHA, COLOR_TYPE_TRUECOLOR_ALPHA};
+use crate::pixels::is_valid_bit_depth;
+use crate::Png;
+
+/*
+    Errors break the PNG specification, decoders may refuse the file. Warnings are legal but unusual or wasteful.
+ */
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+
+    Error,
+    Warning,
+}
+
+/*
+    One finding of Png::validate()
+ */
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationIssue {
+
+    pub severity: Severity,
+    /* Position of the chunk in Png::chunks, None for findings about the file as a whole */
+    pub chunk_index: Option<usize>,
+    pub message: String,
+    /* Section of the PNG specification (W3C, second edition) the check comes from */
+    pub spec: &'static str,
+}
+
+/*
+    Every finding of Png::validate() in the order the checks ran
+ */
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ValidationReport {
+
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+
+    fn push (&mut self, severity: Severity, chunk_index: Option<usize>, spec: &'static str, message: String) {
+
+        self.issues.push(ValidationIssue { severity, chunk_index, message, spec });
+    }
+
+    fn error (&mut self, chunk_index: Option<usize>, spec: &'static str, message: String) {
+
+        self.push(Severity::Error, chunk_index, spec, message);
+    }
+
+    fn warning (&mut self, chunk_index: Option<usize>, spec: &'static str, message: String) {
+
+        self.push(Severity::Warning, chunk_index, spec, message);
+    }
+
+    /*
+        True when there are no errors, warnings don't count
+     */
+    pub fn is_valid (&self) -> bool {
+
+        self.errors().next().is_none()
+    }
+
+    pub fn errors (&self) -> impl Iterator<Item = &ValidationIssue> {
+
+        self.issues.iter().filter(|issue| issue.severity == Severity::Error)
+    }
+
+    pub fn warnings (&self) -> impl Iterator<Item = &ValidationIssue> {
+
+        self.issues.iter().filter(|issue| issue.severity == Severity::Warning)
+    }
+}
+
+/*
+    One line per issue, e.g. "error: chunk 0: IHDR filter method is 1, only 0 is defined [11.2.2]", or "OK" when there are none
+ */
+impl fmt::Display for ValidationReport {
+
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+
+        if self.issues.is_empty() {
+
+            return writeln!(f, "OK");
+        }
+
+        for issue in self.issues.iter() {
+
+            write!(f, "{}: ", if issue.severity == Severity::Error { "error" } else { "warning" })?;
+
+            if let Some(index) = issue.chunk_index {
+
+                write!(f, "chunk {}: ", index)?;
+            }
+
+            writeln!(f, "{} [{}]", issue.message, issue.spec)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Png {
+
+    /*
+        Runs every structural check this crate knows of and reports all findings instead of stopping at the first, much like pngcheck.
+        Checks the signature, CRCs, chunk ordering, IHDR fields, PLTE and tRNS against the color type.
+        Whether the IDAT stream inflates to height * scanline bytes is not checked, that needs a zlib layer this crate doesn't have.
+     */
+    pub fn validate (&self) -> ValidationReport {
+
+        let mut report = ValidationReport::default();
+
+        if self.signature != PNG_SIGNATURE {
+
+            report.error(None, "5.2", "signature is not the PNG signature".to_string());
+        }
+
+        for (index, chunk) in self.iter().enumerate() {
+
+            if chunk.type_name.len() != 4 || !chunk.type_name.iter().all(|byte| byte.is_ascii_alphabetic()) {
+
+                report.error(Some(index), "5.3", format!("chunk type {:02x?} is not four ASCII letters", chunk.type_name));
+            }
+            else if chunk.type_name[2].is_ascii_lowercase() {
+
+                report.error(Some(index), "5.4", format!("{} has the reserved bit set (third letter lowercase)", chunk.get_type_name()));
+            }
+            else if chunk.is_critical() && !matches!(chunk.type_name.as_slice(), b"IHDR" | b"PLTE" | b"IDAT" | b"IEND") {
+
+                report.error(Some(index), "5.4", format!("{} is a critical chunk type no decoder knows", chunk.get_type_name()));
+            }
+
+            if !chunk.is_crc_valid() {
+
+                report.error(Some(index), "5.3", format!("{} CRC does not match its type and data", chunk.get_type_name()));
+            }
+        }
+
+        let count = |type_name: &[u8]| self.iter().filter(|chunk| chunk.type_name == type_name).count();
+        let first = |type_name: &[u8]| self.iter().position(|chunk| chunk.type_name == type_name);
+
+        if self.chunk_at(0).map(|chunk| chunk.type_name != b"IHDR").unwrap_or(true) {
+
+            report.error(None, "5.6", "first chunk is not IHDR".to_string());
+        }
+
+        if count(b"IHDR") > 1 {
+
+            report.error(None, "5.6", format!("{} IHDR chunks, only one is allowed", count(b"IHDR")));
+        }
+
+        match first(b"IEND") {
+
+            None => report.error(None, "5.6", "IEND chunk is missing".to_string()),
+            Some(index) if index + 1 != self.chunks.len() => report.error(Some(index), "5.6", "IEND is not the last chunk".to_string()),
+            _ => {},
+        }
+
+        match first(b"IDAT") {
+
+            None => report.error(None, "11.2.4", "IDAT chunk is missing".to_string()),
+            Some(start) => {
+
+                let end = self.iter().rposition(|chunk| chunk.type_name == b"IDAT").unwrap_or(start);
+
+                if let Some(offset) = self.chunks[start ..= end].iter().position(|chunk| chunk.type_name != b"IDAT") {
+
+                    report.error(Some(start + offset), "5.6", "IDAT chunks are not consecutive".to_string());
+                }
+
+                if let Some(plte) = first(b"PLTE") {
+
+                    if plte > start {
+
+                        report.error(Some(plte), "5.6", "PLTE comes after IDAT".to_string());
+                    }
+                }
+            }
+        }
+
+        /* Ancillary chunks with a fixed place relative to PLTE and IDAT, table 5.3 of the specification */
+        for (index, chunk) in self.iter().enumerate() {
+
+            let before_plte = matches!(chunk.type_name.as_slice(), b"gAMA" | b"cHRM" | b"sRGB" | b"iCCP" | b"sBIT");
+            let after_plte = matches!(chunk.type_name.as_slice(), b"bKGD" | b"hIST" | b"tRNS");
+            let before_idat = before_plte || after_plte || matches!(chunk.type_name.as_slice(), b"pHYs" | b"sPLT" | b"oFFs");
+
+            if before_plte && first(b"PLTE").map(|plte| plte < index).unwrap_or(false) {
+
+                report.error(Some(index), "5.6", format!("{} comes after PLTE", chunk.get_type_name()));
+            }
+
+            if after_plte && first(b"PLTE").map(|plte| plte > index).unwrap_or(false) {
+
+                report.error(Some(index), "5.6", format!("{} comes before PLTE", chunk.get_type_name()));
+            }
+
+            if before_idat && first(b"IDAT").map(|idat| idat < index).unwrap_or(false) {
+
+                report.error(Some(index), "5.6", format!("{} comes after IDAT", chunk.get_type_name()));
+            }
+        }
+
+        let ihdr_index = match first(b"IHDR") {
+
+            Some(index) => index,
+            None => {
+
+                report.error(None, "11.2.2", "IHDR chunk is missing".to_string());
+
+                return report;
+            }
+        };
+
+        let ihdr = &self.chunks[ihdr_index].data;
+
+        if ihdr.len() != 13 {
+
+            report.error(Some(ihdr_index), "11.2.2", format!("IHDR has {} data bytes, expected 13", ihdr.len()));
+
+            return report;
+        }
+
+        let width = u32::from_be_bytes([ihdr[0], ihdr[1], ihdr[2], ihdr[3]]);
+        let height = u32::from_be_bytes([ihdr[4], ihdr[5], ihdr[6], ihdr[7]]);
+        let (bit_depth, color_type) = (ihdr[8], ihdr[9]);
+
+        if width == 0 || height == 0 || width > i32::MAX as u32 || height > i32::MAX as u32 {
+
+            report.error(Some(ihdr_index), "11.2.2", format!("dimensions {}x{} are outside 1 to 2147483647", width, height));
+        }
+
+        if !is_valid_bit_depth(color_type, bit_depth) {
+
+            report.error(Some(ihdr_index), "11.2.2", format!("bit depth {} is not allowed for color type {}", bit_depth, color_type));
+        }
+
+        if ihdr[10] != 0 {
+
+            report.error(Some(ihdr_index), "11.2.2", format!("IHDR compression method is {}, only 0 is defined", ihdr[10]));
+        }
+
+        if ihdr[11] != 0 {
+
+            report.error(Some(ihdr_index), "11.2.2", format!("IHDR filter method is {}, only 0 is defined", ihdr[11]));
+        }
+
+        if ihdr[12] > 1 {
+
+            report.error(Some(ihdr_index), "11.2.2", format!("IHDR interlace method is {}, only 0 and 1 are defined", ihdr[12]));
+        }
+
+        let mut palette_entries: Option<usize> = None;
+
+        match first(b"PLTE") {
+
+            None if color_type == COLOR_TYPE_INDEXED => report.error(None, "11.2.3", "PLTE chunk is missing, color type 3 requires it".to_string()),
+            None => {},
+            Some(index) => {
+
+                let length = self.chunks[index].data.len();
+
+                if color_type == COLOR_TYPE_GRAYSCALE || color_type == COLOR_TYPE_GRAYSCALE_ALPHA {
+
+                    report.error(Some(index), "11.2.3", format!("PLTE is not allowed for color type {}", color_type));
+                }
+
+                if count(b"PLTE") > 1 {
+
+                    report.error(Some(index), "5.6", format!("{} PLTE chunks, only one is allowed", count(b"PLTE")));
+                }
+
+                if length == 0 || !length.is_multiple_of(3) || length / 3 > 256 {
+
+                    report.error(Some(index), "11.2.3", format!("PLTE has {} data bytes, expected 1 to 256 entries of 3 bytes", length));
+                }
+                else if color_type == COLOR_TYPE_INDEXED && bit_depth < 8 && length / 3 > 1 << bit_depth {
+
+                    report.error(Some(index), "11.2.3", format!("PLTE has {} entries, bit depth {} can only index {}", length / 3, bit_depth, 1 << bit_depth));
+                }
+
+                palette_entries = Some(length / 3);
+            }
+        }
+
+        if let Some(index) = first(b"tRNS") {
+
+            let length = self.chunks[index].data.len();
+
+            match color_type {
+
+                COLOR_TYPE_GRAYSCALE if length != 2 => report.error(Some(index), "11.3.2.1", format!("tRNS has {} data bytes, expected 2 for color type 0", length)),
+                COLOR_TYPE_TRUECOLOR if length != 6 => report.error(Some(index), "11.3.2.1", format!("tRNS has {} data bytes, expected 6 for color type 2", length)),
+                COLOR_TYPE_INDEXED if palette_entries.map(|entries| length > entries).unwrap_or(false) => report.error(Some(index), "11.3.2.1", format!("tRNS has {} entries but PLTE only {}", length, palette_entries.unwrap_or(0))),
+                COLOR_TYPE_GRAYSCALE_ALPHA | COLOR_TYPE_TRUECOLOR_ALPHA => report.error(Some(index), "11.3.2.1", format!("tRNS is not allowed for color type {}, it has an alpha channel", color_type)),
+                _ => {},
+            }
+        }
+
+        if count(b"IDAT") > 0 && self.get_all_idat_data_as_vec().is_empty() {
+
+            report.error(None, "11.2.4", "every IDAT chunk is empty, there is no image data".to_string());
+        }
+        else {
+
+            for (index, _) in self.iter().enumerate().filter(|(_, chunk)| chunk.type_name == b"IDAT" && chunk.data.is_empty()) {
+
+                report.warning(Some(index), "11.2.4", "empty IDAT chunk".to_string());
+            }
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::fixtures;
+
+    fn ordering_errors (chunks: &[(&[u8; 4], Vec<u8>)]) -> Vec<(Option<usize>, String)> {
+
+        let png = Png::try_from(fixtures::file(chunks)).unwrap();
+
+        png.validate().errors().filter(|issue| issue.spec == "5.6").map(|issue| (issue.chunk_index, issue.message.clone())).collect()
+    }
+
+    #[test]
+    fn fixture_is_valid () {
+
+        let report = Png::try_from(fixtures::rgb()).unwrap().validate();
+
+        assert!(report.is_valid(), "{}", report);
+    }
+
+    #[test]
+    fn color_chunks_come_before_plte_and_idat () {
+
+        let errors = ordering_errors(&[
+
+            (b"IHDR", fixtures::ihdr(2, 2, 8, 2)),
+            (b"PLTE", vec![0; 6]),
+            (b"gAMA", 45455u32.to_be_bytes().to_vec()),
+            (b"IDAT", vec![0x78, 0x01]),
+            (b"sRGB", vec![0]),
+            (b"IEND", Vec::new()),
+        ]);
+
+        assert_eq!(errors, vec![
+
+            (Some(2), "gAMA comes after PLTE".to_string()),
+            (Some(4), "sRGB comes after PLTE".to_string()),
+            (Some(4), "sRGB comes after IDAT".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn palette_chunks_come_after_plte () {
+
+        let errors = ordering_errors(&[
+
+            (b"IHDR", fixtures::ihdr(2, 2, 8, 3)),
+            (b"tRNS", vec![0]),
+            (b"bKGD", vec![0]),
+            (b"PLTE", vec![0; 6]),
+            (b"hIST", vec![0; 4]),
+            (b"IDAT", vec![0x78, 0x01]),
+            (b"IEND", Vec::new()),
+        ]);
+
+        assert_eq!(errors, vec![
+
+            (Some(1), "tRNS comes before PLTE".to_string()),
+            (Some(2), "bKGD comes before PLTE".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn layout_chunks_come_before_idat () {
+
+        let errors = ordering_errors(&[
+
+            (b"IHDR", fixtures::ihdr(2, 2, 8, 2)),
+            (b"pHYs", vec![0; 9]),
+            (b"IDAT", vec![0x78, 0x01]),
+            (b"oFFs", vec![0; 9]),
+            (b"sPLT", b"p\0\x08".to_vec()),
+            (b"IEND", Vec::new()),
+        ]);
+
+        assert_eq!(errors, vec![
+
+            (Some(3), "oFFs comes after IDAT".to_string()),
+            (Some(4), "sPLT comes after IDAT".to_string()),
+        ]);
+    }
+}
@@ -0,0 +1,199 @@
+/*
+    lib/rust/png/src/watermark.rs
+    Q@khaa.pk
+ */
+
+use crate::constants::{COLOR_TYPE_TRUECOLOR, COLOR_TYPE_TRUECOLOR_ALPHA};
+use crate::error::PngError;
+use crate::pixels::PixelBuffer;
+
+/*
+    Where PixelBuffer::watermark() puts the mark, the corners keep @margin pixels between the mark and both edges they touch
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WatermarkPosition {
+
+    TopLeft { margin: u32 },
+    TopRight { margin: u32 },
+    BottomLeft { margin: u32 },
+    BottomRight { margin: u32 },
+    /* Centered on the canvas, an odd pixel left over goes to the right and bottom */
+    Center,
+    /* Top left corner of the mark at (x, y), which can be negative or past the canvas, what falls outside is clipped */
+    At { x: i32, y: i32 },
+}
+
+impl WatermarkPosition {
+
+    /*
+        Top left corner of a @mark_width by @mark_height mark on a @canvas_width by @canvas_height canvas
+     */
+    pub fn origin (&self, canvas_width: u32, canvas_height: u32, mark_width: u32, mark_height: u32) -> (i32, i32) {
+
+        let far = |canvas: u32, mark: u32, margin: u32| (canvas as i64 - mark as i64 - margin as i64) as i32;
+        let near = |margin: u32| margin.min(i32::MAX as u32) as i32;
+
+        match *self {
+
+            WatermarkPosition::TopLeft { margin } => (near(margin), near(margin)),
+            WatermarkPosition::TopRight { margin } => (far(canvas_width, mark_width, margin), near(margin)),
+            WatermarkPosition::BottomLeft { margin } => (near(margin), far(canvas_height, mark_height, margin)),
+            WatermarkPosition::BottomRight { margin } => (far(canvas_width, mark_width, margin), far(canvas_height, mark_height, margin)),
+            WatermarkPosition::Center => ((canvas_width as i64 - mark_width as i64).div_euclid(2) as i32, (canvas_height as i64 - mark_height as i64).div_euclid(2) as i32),
+            WatermarkPosition::At { x, y } => (x, y),
+        }
+    }
+}
+
+impl PixelBuffer {
+
+    /*
+        Copy of this buffer with @mark blended over it at @position, the mark's alpha scaled by @opacity (0.0 - 1.0, clamped) first.
+        A mark wider or taller than @max_fraction of the canvas is shrunk with resize_bilinear(), aspect ratio kept, until it fits.
+        The mark is turned into RGBA at this buffer's bit depth, and gray canvases into RGBA so a colored mark stays colored, RGB and RGBA canvases keep their color type.
+        Indexed buffers, either one, give UnsupportedColorType.
+     */
+    pub fn watermark (&self, mark: &PixelBuffer, position: WatermarkPosition, opacity: f32, max_fraction: f32) -> Result<PixelBuffer, PngError> {
+
+        let canvas = match self.color_type {
+
+            COLOR_TYPE_TRUECOLOR | COLOR_TYPE_TRUECOLOR_ALPHA => self.clone(),
+            _ => self.to_rgba()?,
+        };
+
+        let mut mark = mark.to_rgba()?.convert_bit_depth(canvas.bit_depth)?;
+
+        let (limit_width, limit_height) = (canvas.width as f64 * max_fraction.max(0.0) as f64, canvas.height as f64 * max_fraction.max(0.0) as f64);
+
+        if mark.width as f64 > limit_width || mark.height as f64 > limit_height {
+
+            let scale = (limit_width / mark.width.max(1) as f64).min(limit_height / mark.height.max(1) as f64);
+            let (width, height) = (((mark.width as f64 * scale) as u32).max(1), ((mark.height as f64 * scale) as u32).max(1));
+
+            mark = mark.resize_bilinear(width, height, true);
+        }
+
+        let opacity = opacity.clamp(0.0, 1.0);
+
+        if opacity < 1.0 {
+
+            let sample_size = mark.bytes_per_sample();
+            let bytes_per_pixel = mark.bytes_per_pixel();
+
+            for pixel in 0 .. mark.width as usize * mark.height as usize {
+
+                let offset = (pixel + 1) * bytes_per_pixel - sample_size;
+                let alpha = (mark.sample(offset) as f32 * opacity).round() as u32;
+
+                mark.set_sample(offset, alpha);
+            }
+        }
+
+        let (x, y) = position.origin(canvas.width, canvas.height, mark.width, mark.height);
+
+        mark.composite_over(&canvas, x, y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::constants::COLOR_TYPE_GRAYSCALE;
+
+    /*
+        Black RGB canvas
+     */
+    fn canvas (width: u32, height: u32) -> PixelBuffer {
+
+        PixelBuffer::new(width, height, COLOR_TYPE_TRUECOLOR, 8).unwrap()
+    }
+
+    /*
+        Opaque white RGBA mark
+     */
+    fn mark (width: u32, height: u32) -> PixelBuffer {
+
+        PixelBuffer::from_data(width, height, COLOR_TYPE_TRUECOLOR_ALPHA, 8, vec![0xFF; width as usize * height as usize * 4]).unwrap()
+    }
+
+    /*
+        (x, y) of every pixel of an RGB or RGBA @buffer whose red is not 0, row by row
+     */
+    fn covered (buffer: &PixelBuffer) -> Vec<(u32, u32)> {
+
+        let channels = buffer.channels();
+
+        (0 .. buffer.height).flat_map(|y| (0 .. buffer.width).map(move |x| (x, y))).filter(|(x, y)| buffer.data[(*y * buffer.width + *x) as usize * channels] != 0).collect()
+    }
+
+    /*
+        Pixels of the @width by @height rectangle at (@x, @y), row by row
+     */
+    fn rect (x: u32, y: u32, width: u32, height: u32) -> Vec<(u32, u32)> {
+
+        (y .. y + height).flat_map(|row| (x .. x + width).map(move |column| (column, row))).collect()
+    }
+
+    #[test]
+    fn mark_lands_where_each_position_says () {
+
+        let positions = [
+
+            (WatermarkPosition::TopLeft { margin: 1 }, rect(1, 1, 2, 2)),
+            (WatermarkPosition::TopRight { margin: 1 }, rect(3, 1, 2, 2)),
+            (WatermarkPosition::BottomLeft { margin: 1 }, rect(1, 2, 2, 2)),
+            (WatermarkPosition::BottomRight { margin: 1 }, rect(3, 2, 2, 2)),
+            /* 3 rows left over on a 5 row canvas, 1 above and 2 below */
+            (WatermarkPosition::Center, rect(2, 1, 2, 2)),
+            (WatermarkPosition::At { x: -1, y: 4 }, rect(0, 4, 1, 1)),
+        ];
+
+        for (position, expected) in positions {
+
+            let output = canvas(6, 5).watermark(&mark(2, 2), position, 1.0, 1.0).unwrap();
+
+            assert_eq!(output.color_type, COLOR_TYPE_TRUECOLOR);
+            assert_eq!(covered(&output), expected, "{:?}", position);
+        }
+    }
+
+    #[test]
+    fn gray_canvas_becomes_rgba () {
+
+        let gray = PixelBuffer::new(3, 3, COLOR_TYPE_GRAYSCALE, 8).unwrap();
+        let output = gray.watermark(&mark(1, 1), WatermarkPosition::Center, 1.0, 1.0).unwrap();
+
+        assert_eq!(output.color_type, COLOR_TYPE_TRUECOLOR_ALPHA);
+        assert_eq!(covered(&output), [(1, 1)]);
+    }
+
+    #[test]
+    fn large_marks_are_shrunk_to_max_fraction () {
+
+        /* 8x4 on a 10x10 canvas at 0.5 may be at most 5x5, scaled by 5/8 it is 5x2 */
+        let output = canvas(10, 10).watermark(&mark(8, 4), WatermarkPosition::TopLeft { margin: 0 }, 1.0, 0.5).unwrap();
+
+        assert_eq!(covered(&output), rect(0, 0, 5, 2));
+
+        /* Marks that fit are left alone */
+        let output = canvas(10, 10).watermark(&mark(4, 4), WatermarkPosition::TopLeft { margin: 0 }, 1.0, 0.5).unwrap();
+
+        assert_eq!(covered(&output), rect(0, 0, 4, 4));
+    }
+
+    #[test]
+    fn opacity_scales_the_mark_alpha () {
+
+        let over_black = |opacity: f32| canvas(1, 1).watermark(&mark(1, 1), WatermarkPosition::At { x: 0, y: 0 }, opacity, 1.0).unwrap().data;
+
+        /* Alpha 255 * 0.5 rounds to 128, white at 128 over black is 128 */
+        assert_eq!(over_black(0.5), [128, 128, 128]);
+        assert_eq!(over_black(0.0), [0, 0, 0]);
+        assert_eq!(over_black(1.0), [255, 255, 255]);
+
+        /* Clamped to 0.0 - 1.0 */
+        assert_eq!(over_black(2.0), [255, 255, 255]);
+        assert_eq!(over_black(-1.0), [0, 0, 0]);
+    }
+}